@@ -0,0 +1,34 @@
+use std::{fs, path::Path};
+
+use uuid::Uuid;
+
+use crate::models::ApiConfig;
+
+/// File in `.synclite/` holding the generated API key
+pub const API_KEY_FILE: &str = "api_key";
+
+/// The key clients of the API must send: the configured one, or the one generated
+/// into `.synclite/api_key` the first time the API was started
+pub fn api_key(storage_path: &Path, config: &ApiConfig) -> Result<String, String> {
+    if let Some(key) = &config.key {
+        return Ok(key.clone());
+    }
+
+    let key_path = storage_path.join(API_KEY_FILE);
+    if let Ok(key) = fs::read_to_string(&key_path)
+        && !key.trim().is_empty()
+    {
+        return Ok(key.trim().to_string());
+    }
+
+    let key = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    fs::write(&key_path, &key)
+        .map_err(|e| format!("Failed to write {}: {}", key_path.display(), e))?;
+    // Only the owner of the workspace may read it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600));
+    }
+    Ok(key)
+}
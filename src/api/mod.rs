@@ -0,0 +1,5 @@
+mod key;
+mod server;
+
+pub use key::*;
+pub use server::*;
@@ -0,0 +1,228 @@
+use std::{collections::BTreeMap, convert::Infallible, net::Ipv4Addr, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
+};
+use serde::Serialize;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc},
+};
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+
+use crate::{
+    control::{ControlCall, ControlRequest, ControlResponse},
+    engine::SyncEvent,
+    models::{FileEntry, InstanceState, PeersConfig, PeersState, SyncConfig, SyncConflict},
+    utils::Log,
+};
+
+/// Everything the API reads from and acts on in a running workspace
+#[derive(Clone)]
+pub struct ApiContext {
+    /// Key every request must carry
+    pub key: String,
+    pub sync_config: Arc<SyncConfig>,
+    pub peers_config: Arc<PeersConfig>,
+    /// Control requests are answered by the engine, as if sent on the control socket
+    pub control: mpsc::Sender<ControlCall>,
+    pub events: broadcast::Sender<SyncEvent>,
+}
+
+/// Listen on 127.0.0.1 and return the future serving the API, which stops when dropped
+pub async fn start_api_server(
+    port: u16,
+    context: ApiContext,
+) -> Result<impl Future<Output = ()> + Send + 'static, String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .map_err(|e| format!("Failed to listen on 127.0.0.1:{}: {}", port, e))?;
+
+    let app = Router::new()
+        .route("/api/status", get(status))
+        .route("/api/peers", get(peers))
+        .route("/api/files", get(files))
+        .route("/api/conflicts", get(conflicts))
+        .route("/api/pause", post(pause))
+        .route("/api/resume", post(resume))
+        .route("/api/rescan", post(rescan))
+        .route("/api/events", get(events))
+        .layer(middleware::from_fn_with_state(
+            context.clone(),
+            authenticate,
+        ))
+        .with_state(context);
+
+    Ok(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            Log::error(format!("HTTP API stopped: {}", e), None);
+        }
+    })
+}
+
+/// An error answered as `{"error": "..."}`
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Let requests through that carry the key as `Authorization: Bearer <key>`, `X-API-Key: <key>`
+/// or, for browsers' `EventSource` which can't set headers, `?api_key=<key>`
+async fn authenticate(State(context): State<ApiContext>, request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let api_key = headers
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok());
+    let query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("api_key="))
+    });
+
+    let authorised = [bearer, api_key, query]
+        .into_iter()
+        .flatten()
+        .any(|key| keys_match(key.trim(), &context.key));
+    if !authorised {
+        return ApiError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong API key".to_string(),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Compare keys without returning early, so the time taken doesn't tell how much matched
+fn keys_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Hand a request to the engine and wait for its answer
+async fn control(
+    context: &ApiContext,
+    request: ControlRequest,
+) -> Result<ControlResponse, ApiError> {
+    let unavailable = || {
+        ApiError(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The workspace stopped".to_string(),
+        )
+    };
+
+    let (call, answer) = ControlCall::new(request);
+    context
+        .control
+        .send(call)
+        .await
+        .map_err(|_| unavailable())?;
+    match answer.await.map_err(|_| unavailable())? {
+        ControlResponse::Error { message } => Err(ApiError(StatusCode::CONFLICT, message)),
+        response => Ok(response),
+    }
+}
+
+async fn instance_state(context: &ApiContext) -> Result<InstanceState, ApiError> {
+    match control(context, ControlRequest::Status).await? {
+        ControlResponse::Status { status } => Ok(status),
+        _ => Err(ApiError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected answer to a status request".to_string(),
+        )),
+    }
+}
+
+/// Role, mode, pause state and connections of the running workspace
+async fn status(State(context): State<ApiContext>) -> Result<Json<InstanceState>, ApiError> {
+    instance_state(&context).await.map(Json)
+}
+
+#[derive(Serialize)]
+struct PeersResponse {
+    #[serde(flatten)]
+    state: PeersState,
+    /// Peers with an open connection right now
+    connected: Vec<String>,
+}
+
+/// Known peers from `peers.json` and the ones currently connected
+async fn peers(State(context): State<ApiContext>) -> Result<Json<PeersResponse>, ApiError> {
+    let connected = match control(&context, ControlRequest::ListPeers).await? {
+        ControlResponse::Peers { connected, .. } => connected,
+        _ => Vec::new(),
+    };
+    Ok(Json(PeersResponse {
+        state: context.peers_config.state().await,
+        connected,
+    }))
+}
+
+/// Every tracked file by path, deleted ones included as tombstones
+async fn files(State(context): State<ApiContext>) -> Json<BTreeMap<String, FileEntry>> {
+    Json(context.sync_config.state().await.into_iter().collect())
+}
+
+/// Conflicts detected since they were last looked at, newest last
+async fn conflicts(State(context): State<ApiContext>) -> Result<Json<Vec<SyncConflict>>, ApiError> {
+    instance_state(&context)
+        .await
+        .map(|status| Json(status.conflicts))
+}
+
+async fn pause(State(context): State<ApiContext>) -> Result<Response, ApiError> {
+    act(&context, ControlRequest::Pause).await
+}
+
+async fn resume(State(context): State<ApiContext>) -> Result<Response, ApiError> {
+    act(&context, ControlRequest::Resume).await
+}
+
+async fn rescan(State(context): State<ApiContext>) -> Result<Response, ApiError> {
+    act(&context, ControlRequest::Rescan).await
+}
+
+/// Run a control action, answered as `{"message": "..."}`
+async fn act(context: &ApiContext, request: ControlRequest) -> Result<Response, ApiError> {
+    match control(context, request).await? {
+        ControlResponse::Done { message } => {
+            Ok(Json(serde_json::json!({ "message": message })).into_response())
+        }
+        _ => Err(ApiError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Unexpected answer to a control request".to_string(),
+        )),
+    }
+}
+
+/// Stream sync events as they happen, named after their `type`
+async fn events(
+    State(context): State<ApiContext>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(context.events.subscribe()).filter_map(|event| {
+        // Clients too slow to keep up skip what they missed
+        let event = serde_json::to_value(event.ok()?).ok()?;
+        let name = event["type"].as_str().unwrap_or("event").to_string();
+        Some(Ok(Event::default().event(name).data(event.to_string())))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use crate::{models::SyncMode, storage::InitPolicy};
+
+/// Option defaults for one command, any option left out falls back to the built-in default
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandDefaults {
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub bind: Option<IpAddr>,
+    #[serde(default)]
+    pub mode: Option<SyncMode>,
+    #[serde(default)]
+    pub leader: Option<SocketAddr>,
+    #[serde(default)]
+    pub init: Option<InitPolicy>,
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+}
+
+impl CommandDefaults {
+    /// Options set here, completed by the ones set in `fallback`
+    fn or(&self, fallback: &CommandDefaults) -> CommandDefaults {
+        CommandDefaults {
+            port: self.port.or(fallback.port),
+            bind: self.bind.or(fallback.bind),
+            mode: self.mode.or(fallback.mode),
+            leader: self.leader.or(fallback.leader),
+            init: self.init.or(fallback.init),
+            metrics_port: self.metrics_port.or(fallback.metrics_port),
+        }
+    }
+}
+
+/// Defaults for command-line options, read from `config.json` in the user config directory.
+/// `defaults` applies to every command, the per-command sections override it.
+/// Options given on the command line always win.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CliConfig {
+    #[serde(default)]
+    pub defaults: CommandDefaults,
+    #[serde(default)]
+    pub serve: CommandDefaults,
+    #[serde(default)]
+    pub connect: CommandDefaults,
+    #[serde(default)]
+    pub daemon: CommandDefaults,
+}
+
+impl CliConfig {
+    pub fn serve(&self) -> CommandDefaults {
+        self.serve.or(&self.defaults)
+    }
+
+    pub fn connect(&self) -> CommandDefaults {
+        self.connect.or(&self.defaults)
+    }
+
+    pub fn daemon(&self) -> CommandDefaults {
+        self.daemon.or(&self.defaults)
+    }
+}
+
+/// Get the path to the user config file, e.g. `~/.config/synclite/config.json`
+pub fn get_cli_config_path() -> Result<PathBuf, String> {
+    dirs::config_dir()
+        .map(|dir| dir.join("synclite").join("config.json"))
+        .ok_or_else(|| "Failed to get user config directory".to_string())
+}
+
+/// Load the option defaults from the given file, or from the user config file if there is one
+pub fn load_cli_config(path: Option<&Path>) -> Result<CliConfig, String> {
+    let config_path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let config_path = get_cli_config_path()?;
+            if !config_path.exists() {
+                return Ok(CliConfig::default());
+            }
+            config_path
+        }
+    };
+
+    let content = fs::read_to_string(&config_path)
+        .map_err(|e| format!("Failed to read {}: {}", config_path.display(), e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse {}: {}", config_path.display(), e))
+}
@@ -1,4 +1,5 @@
+mod cli_config;
 mod registry;
 
+pub use cli_config::*;
 pub use registry::*;
-
@@ -1,12 +1,45 @@
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
-    fs,
+    fs::{self, File},
     path::{Path, PathBuf},
 };
 
+use crate::models::{InstanceRole, SyncMode};
+
+/// A synclite directory known on this machine, with how it was last started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredWorkspace {
+    pub path: String,
+    #[serde(default)]
+    pub role: Option<InstanceRole>,
+    #[serde(default)]
+    pub mode: Option<SyncMode>,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    #[serde(default)]
+    pub last_started: Option<DateTime<Utc>>,
+}
+
+impl RegisteredWorkspace {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            role: None,
+            mode: None,
+            port: None,
+            folder_id: None,
+            last_started: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirectoriesRegistry {
-    pub directories: Vec<String>,
+    #[serde(deserialize_with = "deserialize_directories")]
+    pub directories: Vec<RegisteredWorkspace>,
 }
 
 impl DirectoriesRegistry {
@@ -15,6 +48,13 @@ impl DirectoriesRegistry {
             directories: Vec::new(),
         }
     }
+
+    /// Find a registered directory by its canonical path
+    pub fn get(&self, path: &str) -> Option<&RegisteredWorkspace> {
+        self.directories
+            .iter()
+            .find(|workspace| workspace.path == path)
+    }
 }
 
 impl Default for DirectoriesRegistry {
@@ -23,6 +63,27 @@ impl Default for DirectoriesRegistry {
     }
 }
 
+/// Read registry entries, older registries only listed the paths
+fn deserialize_directories<'de, D>(deserializer: D) -> Result<Vec<RegisteredWorkspace>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+        Path(String),
+        Workspace(RegisteredWorkspace),
+    }
+
+    Ok(Vec::<Entry>::deserialize(deserializer)?
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Path(path) => RegisteredWorkspace::new(path),
+            Entry::Workspace(workspace) => workspace,
+        })
+        .collect())
+}
+
 /// Get the path to the synclite app data directory
 pub fn get_app_data_dir() -> Result<PathBuf, String> {
     dirs::data_dir()
@@ -74,10 +135,43 @@ pub fn save_registry(registry: &DirectoriesRegistry) -> Result<(), String> {
         )
     })?;
 
-    fs::write(&registry_path, json_string)
+    // Write next to the registry and swap it in, readers never see a half written file
+    let temp_path = registry_path.with_extension("json.tmp");
+    fs::write(&temp_path, json_string)
+        .map_err(|e| format!("Failed to write synclite directories registry file: {}", e))?;
+    fs::rename(&temp_path, &registry_path)
         .map_err(|e| format!("Failed to write synclite directories registry file: {}", e))
 }
 
+/// Load, change and save the registry while holding an exclusive lock,
+/// so concurrent synclite processes don't overwrite each other's changes
+pub fn update_registry<T>(update: impl FnOnce(&mut DirectoriesRegistry) -> T) -> Result<T, String> {
+    let app_data_dir = get_app_data_dir()?;
+    fs::create_dir_all(&app_data_dir)
+        .map_err(|e| format!("Failed to create synclite app data directory: {}", e))?;
+
+    // Released when the file is closed at the end of the function
+    let lock = File::create(app_data_dir.join("directories.lock"))
+        .map_err(|e| format!("Failed to create registry lock file: {}", e))?;
+    lock.lock()
+        .map_err(|e| format!("Failed to lock synclite directories registry: {}", e))?;
+
+    let mut registry = load_registry()?;
+    let result = update(&mut registry);
+    save_registry(&registry)?;
+
+    Ok(result)
+}
+
+/// Canonical form of a directory path as stored in the registry.
+/// Paths that no longer exist are kept as given so they can still be removed.
+pub fn registry_key(path: &Path) -> String {
+    path.canonicalize()
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
 /// Add a directory to the registry
 pub fn add_directory(path: &Path) -> Result<(), String> {
     let canonical_path = path
@@ -86,30 +180,61 @@ pub fn add_directory(path: &Path) -> Result<(), String> {
         .to_string_lossy()
         .to_string();
 
-    let mut registry = load_registry()?;
+    update_registry(|registry| {
+        // Don't add duplicates
+        if registry.get(&canonical_path).is_none() {
+            registry
+                .directories
+                .push(RegisteredWorkspace::new(canonical_path));
+        }
+    })
+}
 
-    // Don't add duplicates
-    if !registry.directories.contains(&canonical_path) {
-        registry.directories.push(canonical_path);
-        save_registry(&registry)?;
-    }
+/// Remove a directory from the registry, returns whether it was registered
+pub fn remove_directory(path: &Path) -> Result<bool, String> {
+    let key = registry_key(path);
 
-    Ok(())
+    update_registry(|registry| {
+        let original_count = registry.directories.len();
+        registry
+            .directories
+            .retain(|workspace| workspace.path != key);
+        registry.directories.len() != original_count
+    })
 }
 
-/// Remove a directory from the registry
-pub fn remove_directory(path: &Path) -> Result<(), String> {
-    let canonical_path = path
-        .canonicalize()
-        .map_err(|e| format!("Failed to canonicalize synclite directory path: {}", e))?
-        .to_string_lossy()
-        .to_string();
+/// Record how a registered directory was started, registering it if needed
+pub fn record_workspace_start(
+    path: &Path,
+    role: InstanceRole,
+    mode: SyncMode,
+    port: u16,
+    folder_id: Option<String>,
+) -> Result<(), String> {
+    let key = registry_key(path);
 
-    let mut registry = load_registry()?;
-    registry.directories.retain(|dir| dir != &canonical_path);
-    save_registry(&registry)?;
+    update_registry(|registry| {
+        let index = match registry
+            .directories
+            .iter()
+            .position(|workspace| workspace.path == key)
+        {
+            Some(index) => index,
+            None => {
+                registry
+                    .directories
+                    .push(RegisteredWorkspace::new(key.clone()));
+                registry.directories.len() - 1
+            }
+        };
 
-    Ok(())
+        let workspace = &mut registry.directories[index];
+        workspace.role = Some(role);
+        workspace.mode = Some(mode);
+        workspace.port = Some(port);
+        workspace.folder_id = folder_id;
+        workspace.last_started = Some(Utc::now());
+    })
 }
 
 /// Check if a path conflicts with existing synclite directories
@@ -121,35 +246,35 @@ pub fn check_path_conflicts(path: &Path) -> Result<(bool, Option<String>), Strin
 
     let registry = load_registry()?;
 
-    for existing_dir in &registry.directories {
-        let existing_path = Path::new(existing_dir);
+    for existing in &registry.directories {
+        let existing_path = Path::new(&existing.path);
 
         // Check if the new path is a subdirectory of an existing synclite directory
         if canonical_path.starts_with(existing_path) {
-            return Ok((true, Some(existing_dir.clone())));
+            return Ok((true, Some(existing.path.clone())));
         }
 
         // Check if an existing synclite directory is a subdirectory of the new path
         if existing_path.starts_with(&canonical_path) {
-            return Ok((true, Some(existing_dir.clone())));
+            return Ok((true, Some(existing.path.clone())));
         }
     }
 
     Ok((false, None))
 }
 
-/// Clean up the registry by removing directories that no longer have .synclite folders
-pub fn cleanup_registry() -> Result<(), String> {
-    let mut registry = load_registry()?;
-    let original_count = registry.directories.len();
-
-    registry
-        .directories
-        .retain(|dir| Path::new(dir).join(".synclite").exists());
-
-    if registry.directories.len() != original_count {
-        save_registry(&registry)?;
-    }
-
-    Ok(())
+/// Clean up the registry by removing directories that no longer have .synclite folders.
+/// Returns the removed paths.
+pub fn cleanup_registry() -> Result<Vec<String>, String> {
+    update_registry(|registry| {
+        let (kept, removed): (Vec<_>, Vec<_>) = registry
+            .directories
+            .drain(..)
+            .partition(|workspace| Path::new(&workspace.path).join(".synclite").exists());
+        registry.directories = kept;
+        removed
+            .into_iter()
+            .map(|workspace| workspace.path)
+            .collect()
+    })
 }
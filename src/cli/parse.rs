@@ -1,62 +1,448 @@
-use std::path::{Path, PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
-use super::types::{Args, Command};
-use crate::utils::Log;
+use clap::{Parser, Subcommand, builder::PossibleValuesParser};
+
+use super::types::{Args, Command, LogOptions, WorkspacesAction};
+use crate::{
+    app::{CommandDefaults, load_cli_config},
+    control::ControlRequest,
+    models::{SyncMode, WorkspaceConfig},
+    network::{DEFAULT_BIND, DEFAULT_PORT},
+    storage::{InitPolicy, ignore_template_names},
+    utils::{LogFilter, LogFormat},
+};
 
 pub enum ParseArgsError {
-    InvalidArguments,
-    InvalidCommand(String),
+    /// Bad usage, or help and version requests, clap renders them
+    Cli(clap::Error),
     InvalidPath(String),
-    InvalidPortNumber(String),
+    InvalidMode(String),
+    InvalidConfig(String),
 }
 
-// Parse Function
-pub fn parse_args() -> Result<Args, ParseArgsError> {
-    // Get the arguments from the command line
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
+/// Real-time folder sync over the local network
+#[derive(Parser, Debug)]
+#[command(name = "synclite", version, about, arg_required_else_help = true)]
+struct Cli {
+    /// Read option defaults from this file instead of the user config file
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
 
-    // Improper usage
-    if args.len() < 2 || args[0] == "-h" || args[0] == "--help" {
-        return Err(ParseArgsError::InvalidArguments);
-    }
+    /// Log level, optionally with per-module levels like `info,engine::peer=debug` [default: info]
+    #[arg(long, global = true, value_name = "FILTER")]
+    log_level: Option<LogFilter>,
+
+    /// Console log format: pretty, plain or json [default: pretty in a terminal, plain otherwise]
+    #[arg(long, global = true, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// Start a sync server (leader) in a directory
+    Serve {
+        #[command(flatten)]
+        sync: SyncOptions,
+
+        /// Address to listen on [default: 0.0.0.0]
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<IpAddr>,
+    },
+    /// Connect a directory to a sync server (peer)
+    Connect {
+        #[command(flatten)]
+        sync: SyncOptions,
+
+        /// Leader to connect to instead of discovering it over mDNS
+        #[arg(long, value_name = "HOST:PORT")]
+        leader: Option<SocketAddr>,
+    },
+    /// Prepare a workspace without starting to sync it
+    Init {
+        /// Workspace directory
+        path: PathBuf,
+
+        /// Folder the workspace is served under or joins [default: directory name]
+        #[arg(long, value_name = "ID")]
+        folder_id: Option<String>,
+
+        /// Sync mode used when serve or connect don't give one
+        #[arg(long)]
+        mode: Option<SyncMode>,
+
+        /// Write a starter .syncignore
+        #[arg(long, value_name = "TEMPLATE", value_parser = PossibleValuesParser::new(ignore_template_names()))]
+        ignore: Option<String>,
+    },
+    /// Sync every registered workspace over one port
+    Daemon {
+        /// Port shared by all served folders [default: 8080]
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// Address to listen on [default: 0.0.0.0]
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<IpAddr>,
+
+        /// Serve Prometheus metrics on 127.0.0.1 at this port [default: off]
+        #[arg(long, value_name = "PORT", value_parser = clap::value_parser!(u16).range(1..))]
+        metrics_port: Option<u16>,
+
+        #[arg(hide = true, conflicts_with = "port", value_name = "PORT")]
+        legacy_port: Option<u16>,
+    },
+    /// Manage the registered workspaces
+    Workspaces {
+        #[command(subcommand)]
+        action: Option<CliWorkspacesAction>,
+    },
+    /// List the stored versions of a file, or restore one
+    Restore {
+        /// Workspace directory
+        path: PathBuf,
+
+        /// File path relative to the workspace
+        file: String,
+
+        /// Version to restore, or "latest"
+        version: Option<String>,
+    },
+    /// Check files against their recorded hashes
+    Verify {
+        /// Workspace directory
+        path: PathBuf,
+
+        /// Fetch corrupted files again from the leader
+        #[arg(long)]
+        repair: bool,
+
+        /// Leader to repair from instead of the configured or discovered one
+        #[arg(long, value_name = "HOST:PORT", requires = "repair")]
+        leader: Option<SocketAddr>,
+    },
+    /// Show the sync state of a workspace
+    Status {
+        /// Workspace directory
+        path: PathBuf,
+
+        /// Print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Rehash every file to also find corrupted ones, slower on large workspaces
+        #[arg(long)]
+        scan: bool,
+    },
+    /// List the peers of the running instance
+    Peers {
+        /// Workspace directory
+        path: PathBuf,
+    },
+    /// Stop syncing until resumed
+    Pause {
+        /// Workspace directory
+        path: PathBuf,
+    },
+    /// Resume syncing
+    Resume {
+        /// Workspace directory
+        path: PathBuf,
+    },
+    /// Rehash the workspace and send missed changes
+    Rescan {
+        /// Workspace directory
+        path: PathBuf,
+    },
+    /// Replace local edits with the leader's copy of the files
+    Revert {
+        /// Workspace directory
+        path: PathBuf,
+    },
+    /// Drop a connected peer
+    Disconnect {
+        /// Workspace directory
+        path: PathBuf,
+
+        /// Peer ID as listed by `synclite peers`
+        peer_id: String,
+    },
+    /// Stop the running instance
+    Shutdown {
+        /// Workspace directory
+        path: PathBuf,
+    },
+}
+
+/// Options shared by serve and connect
+#[derive(clap::Args, Debug)]
+struct SyncOptions {
+    /// Workspace directory
+    path: PathBuf,
+
+    /// Port to listen on, or to find the leader on [default: 8080]
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// send-receive, send-only, receive-only or mirror (connect only) [default: send-receive]
+    #[arg(short, long)]
+    mode: Option<SyncMode>,
+
+    /// Initialise a new workspace without asking
+    #[arg(short, long, conflicts_with = "no_init")]
+    yes: bool,
+
+    /// Fail instead of initialising a new workspace
+    #[arg(long)]
+    no_init: bool,
+
+    /// Serve the local HTTP API on this port [default: off, unless enabled in the workspace config]
+    #[arg(long, value_name = "PORT")]
+    api_port: Option<u16>,
+
+    /// Serve Prometheus metrics on 127.0.0.1 at this port [default: off]
+    #[arg(long, value_name = "PORT", value_parser = clap::value_parser!(u16).range(1..))]
+    metrics_port: Option<u16>,
+
+    // Older releases took the port and mode positionally
+    #[arg(hide = true, conflicts_with = "port", value_name = "PORT")]
+    legacy_port: Option<u16>,
 
-    // Get the command
-    let command = match args[0].as_str() {
-        "serve" => Command::Serve,
-        "connect" => Command::Connect,
-        _ => return Err(ParseArgsError::InvalidCommand(args[0].clone())),
+    #[arg(hide = true, conflicts_with = "mode", value_name = "MODE")]
+    legacy_mode: Option<SyncMode>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliWorkspacesAction {
+    /// List registered workspaces and whether they are running
+    List,
+    /// Unregister a workspace, its .synclite folder is kept
+    Remove { path: PathBuf },
+    /// Unregister workspaces whose .synclite folder is gone
+    Prune,
+    /// Show the metadata of a workspace
+    Info { path: PathBuf },
+}
+
+// Parse Function
+pub fn parse_args() -> Result<Args, ParseArgsError> {
+    let cli = Cli::try_parse().map_err(ParseArgsError::Cli)?;
+    let config = load_cli_config(cli.config.as_deref()).map_err(ParseArgsError::InvalidConfig)?;
+    let log = LogOptions {
+        filter: cli.log_level.unwrap_or_default(),
+        format: cli.log_format,
     };
 
-    // Get the path and validate it
-    let abs_workspace_path = match Path::new(&args[1]).canonicalize() {
-        Ok(mut canonical_workspace) => {
-            let canonical_str = canonical_workspace.to_string_lossy();
-            if canonical_str.starts_with(r"\\?\") {
-                canonical_workspace = PathBuf::from(&canonical_str[4..]);
+    let (command, path) = match cli.command {
+        CliCommand::Serve { sync, bind } => {
+            let defaults = config.serve();
+            let workspace = workspace_config(&sync.path)?;
+            let bind = bind
+                .or(workspace.bind)
+                .or(defaults.bind)
+                .unwrap_or(DEFAULT_BIND);
+            let api_port = sync.api_port;
+            let args = sync_args(
+                Command::Serve { bind, api_port },
+                sync,
+                &workspace,
+                &defaults,
+                log,
+            )?;
+
+            // A leader has nobody to mirror
+            if args.mode == SyncMode::Mirror {
+                return Err(ParseArgsError::InvalidMode(
+                    "mirror mode is only available with connect".to_string(),
+                ));
             }
-            canonical_workspace
+            return Ok(args);
+        }
+        CliCommand::Connect { sync, leader } => {
+            let defaults = config.connect();
+            let workspace = workspace_config(&sync.path)?;
+            let leader = leader.or(workspace.leader).or(defaults.leader);
+            return sync_args(
+                Command::Connect {
+                    leader,
+                    api_port: sync.api_port,
+                },
+                sync,
+                &workspace,
+                &defaults,
+                log,
+            );
+        }
+        CliCommand::Daemon {
+            port,
+            bind,
+            metrics_port,
+            legacy_port,
+        } => {
+            let defaults = config.daemon();
+            return Ok(Args {
+                command: Command::Daemon {
+                    bind: bind.or(defaults.bind).unwrap_or(DEFAULT_BIND),
+                },
+                abs_workspace_path: PathBuf::new(),
+                port: port
+                    .or(legacy_port)
+                    .or(defaults.port)
+                    .unwrap_or(DEFAULT_PORT),
+                mode: SyncMode::default(),
+                init_policy: InitPolicy::Never,
+                log,
+                metrics_port: metrics_port.or(defaults.metrics_port),
+            });
         }
-        Err(_) => {
-            return Err(ParseArgsError::InvalidPath(args[1].clone()));
+        CliCommand::Workspaces { action } => {
+            // Registry paths may no longer exist, they are only made absolute
+            let absolute = |path: PathBuf| std::path::absolute(&path).unwrap_or(path);
+            let action = match action.unwrap_or(CliWorkspacesAction::List) {
+                CliWorkspacesAction::List => WorkspacesAction::List,
+                CliWorkspacesAction::Remove { path } => WorkspacesAction::Remove {
+                    path: absolute(path),
+                },
+                CliWorkspacesAction::Prune => WorkspacesAction::Prune,
+                CliWorkspacesAction::Info { path } => WorkspacesAction::Info {
+                    path: absolute(path),
+                },
+            };
+            return Ok(Args {
+                command: Command::Workspaces { action },
+                abs_workspace_path: PathBuf::new(),
+                port: DEFAULT_PORT,
+                mode: SyncMode::default(),
+                init_policy: InitPolicy::Never,
+                log,
+                metrics_port: None,
+            });
         }
+        CliCommand::Init {
+            path,
+            folder_id,
+            mode,
+            ignore,
+        } => (
+            Command::Init {
+                folder_id,
+                mode,
+                ignore_template: ignore,
+            },
+            path,
+        ),
+        CliCommand::Restore {
+            path,
+            file,
+            version,
+        } => (
+            Command::Restore {
+                file_path: file.replace('\\', "/"),
+                version,
+            },
+            path,
+        ),
+        CliCommand::Verify {
+            path,
+            repair,
+            leader,
+        } => {
+            // Repair from the same leader `connect` would sync with
+            let leader = if repair {
+                leader
+                    .or(workspace_config(&path)?.leader)
+                    .or(config.connect().leader)
+            } else {
+                None
+            };
+            (Command::Verify { repair, leader }, path)
+        }
+        CliCommand::Status { path, json, scan } => (Command::Status { json, scan }, path),
+        CliCommand::Peers { path } => (control(ControlRequest::ListPeers), path),
+        CliCommand::Pause { path } => (control(ControlRequest::Pause), path),
+        CliCommand::Resume { path } => (control(ControlRequest::Resume), path),
+        CliCommand::Rescan { path } => (control(ControlRequest::Rescan), path),
+        CliCommand::Revert { path } => (control(ControlRequest::Revert), path),
+        CliCommand::Disconnect { path, peer_id } => {
+            (control(ControlRequest::DisconnectPeer { peer_id }), path)
+        }
+        CliCommand::Shutdown { path } => (control(ControlRequest::Shutdown), path),
     };
 
-    // Other arguments
-    let port = match args.get(2).unwrap_or(&"8080".to_string()).parse::<u16>() {
-        Ok(port) => port,
-        Err(_) => {
-            Log::error(
-                &format!("Invalid port number: '{}'", args.get(2).unwrap()),
-                None,
-            );
-            std::process::exit(1);
-        }
+    // Init, restore, verify, status and control requests take no network options
+    Ok(Args {
+        command,
+        abs_workspace_path: canonical_workspace_path(&path)?,
+        port: DEFAULT_PORT,
+        mode: SyncMode::default(),
+        init_policy: InitPolicy::Never,
+        log,
+        metrics_port: None,
+    })
+}
+
+/// Resolve the options of serve and connect. The command line wins over the
+/// workspace config, which wins over the user config file.
+fn sync_args(
+    command: Command,
+    options: SyncOptions,
+    workspace: &WorkspaceConfig,
+    defaults: &CommandDefaults,
+    log: LogOptions,
+) -> Result<Args, ParseArgsError> {
+    let init_policy = if options.yes {
+        InitPolicy::Yes
+    } else if options.no_init {
+        InitPolicy::Never
+    } else {
+        defaults.init.unwrap_or_default()
     };
 
-    // Return the arguments
     Ok(Args {
         command,
-        abs_workspace_path,
-        port,
+        abs_workspace_path: canonical_workspace_path(&options.path)?,
+        port: options
+            .port
+            .or(options.legacy_port)
+            .or(workspace.port)
+            .or(defaults.port)
+            .unwrap_or(DEFAULT_PORT),
+        mode: options
+            .mode
+            .or(options.legacy_mode)
+            .or(workspace.mode)
+            .or(defaults.mode)
+            .unwrap_or_default(),
+        init_policy,
+        log,
+        metrics_port: options.metrics_port.or(defaults.metrics_port),
     })
 }
+
+/// Settings of a workspace that may not be initialised yet
+fn workspace_config(path: &Path) -> Result<WorkspaceConfig, ParseArgsError> {
+    WorkspaceConfig::load(&path.join(".synclite")).map_err(ParseArgsError::InvalidConfig)
+}
+
+fn control(request: ControlRequest) -> Command {
+    Command::Control { request }
+}
+
+/// Canonicalize an existing workspace directory
+fn canonical_workspace_path(path: &Path) -> Result<PathBuf, ParseArgsError> {
+    match path.canonicalize() {
+        Ok(mut canonical_workspace) => {
+            let canonical_str = canonical_workspace.to_string_lossy();
+            if let Some(stripped) = canonical_str.strip_prefix(r"\\?\") {
+                canonical_workspace = PathBuf::from(stripped);
+            }
+            Ok(canonical_workspace)
+        }
+        Err(_) => Err(ParseArgsError::InvalidPath(path.display().to_string())),
+    }
+}
@@ -1,13 +1,96 @@
-use std::path::PathBuf;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+use crate::{
+    control::ControlRequest,
+    models::SyncMode,
+    storage::InitPolicy,
+    utils::{LogFilter, LogFormat},
+};
 
 #[derive(Debug)]
 pub enum Command {
-    Serve,
-    Connect,
+    /// Sync a workspace as the leader
+    Serve {
+        /// Address the leader listens on
+        bind: IpAddr,
+        /// Port of the local HTTP API, enabled by the workspace config when not set
+        api_port: Option<u16>,
+    },
+    /// Sync a workspace as a peer
+    Connect {
+        /// Leader to connect to, discovered over mDNS when not set
+        leader: Option<SocketAddr>,
+        /// Port of the local HTTP API, enabled by the workspace config when not set
+        api_port: Option<u16>,
+    },
+    /// Create the .synclite folder without starting to sync
+    Init {
+        folder_id: Option<String>,
+        mode: Option<SyncMode>,
+        /// Name of a starter .syncignore
+        ignore_template: Option<String>,
+    },
+    /// Sync every registered workspace in one process
+    Daemon {
+        /// Address the shared port listens on
+        bind: IpAddr,
+    },
+    /// Inspect or clean up the registry of synclite directories
+    Workspaces { action: WorkspacesAction },
+    /// List or restore stored versions of a workspace file
+    Restore {
+        file_path: String,
+        version: Option<String>,
+    },
+    /// Check the files on disk against the recorded hashes
+    Verify {
+        /// Fetch corrupted files again from the leader
+        repair: bool,
+        /// Leader to repair from, resolved like `connect` does, discovered over mDNS when unset
+        leader: Option<SocketAddr>,
+    },
+    /// Show the sync state of a workspace
+    Status {
+        /// Print machine-readable JSON instead of the colored summary
+        json: bool,
+        /// Rehash every file instead of comparing modification times
+        scan: bool,
+    },
+    /// Send a request to the instance running in a workspace
+    Control { request: ControlRequest },
+}
+
+#[derive(Debug)]
+pub enum WorkspacesAction {
+    List,
+    /// Unregister a directory, its `.synclite` folder is kept
+    Remove {
+        path: PathBuf,
+    },
+    /// Unregister directories whose `.synclite` folder is gone
+    Prune,
+    Info {
+        path: PathBuf,
+    },
 }
 
 pub struct Args {
     pub command: Command,
     pub abs_workspace_path: PathBuf,
     pub port: u16,
+    pub mode: SyncMode,
+    pub init_policy: InitPolicy,
+    pub log: LogOptions,
+    /// Port of the local Prometheus metrics listener, off when not set
+    pub metrics_port: Option<u16>,
+}
+
+/// How the process logs, see `init_logging`
+pub struct LogOptions {
+    pub filter: LogFilter,
+    /// Picked from the terminal when not set
+    pub format: Option<LogFormat>,
 }
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use crate::control::{ControlRequest, ControlResponse};
+
+/// Send a request to the instance running in a workspace and wait for its answer
+#[cfg(unix)]
+pub async fn send_control_request(
+    storage_path: &Path,
+    request: &ControlRequest,
+) -> Result<ControlResponse, String> {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::UnixStream,
+    };
+
+    use crate::control::CONTROL_SOCKET;
+
+    let stream = UnixStream::connect(storage_path.join(CONTROL_SOCKET))
+        .await
+        .map_err(|_| "No running instance for this workspace".to_string())?;
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line =
+        serde_json::to_string(request).map_err(|e| format!("Failed to encode request: {}", e))?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    let mut response = String::new();
+    BufReader::new(reader)
+        .read_line(&mut response)
+        .await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+
+    serde_json::from_str(&response).map_err(|e| format!("Invalid response: {}", e))
+}
+
+/// Send a request to the instance running in a workspace and wait for its answer
+#[cfg(not(unix))]
+pub async fn send_control_request(
+    _storage_path: &Path,
+    _request: &ControlRequest,
+) -> Result<ControlResponse, String> {
+    Err("The control socket is only available on Unix".to_string())
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::InstanceState;
+
+/// Name of the control socket inside `.synclite/`
+pub const CONTROL_SOCKET: &str = "control.sock";
+
+/// Requests understood by a running instance, sent as one JSON line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum ControlRequest {
+    Status,
+    ListPeers,
+    /// Stop applying and sending changes until resumed
+    Pause,
+    /// Pick up local changes and resync with the other side
+    Resume,
+    /// Rehash the workspace and send the changes the file watcher missed
+    Rescan,
+    DisconnectPeer {
+        peer_id: String,
+    },
+    /// Fetch these corrupted files again from the leader
+    Repair {
+        paths: Vec<String>,
+    },
+    /// Replace local edits with the leader's copy of the files
+    Revert,
+    Shutdown,
+}
+
+/// Answers of a running instance, sent as one JSON line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "kebab-case")]
+pub enum ControlResponse {
+    Status {
+        status: InstanceState,
+    },
+    Peers {
+        connected: Vec<String>,
+        known: Vec<String>,
+    },
+    Done {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
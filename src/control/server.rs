@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::control::{ControlRequest, ControlResponse};
+
+/// A request received on the control socket or the HTTP API, answered through `respond`
+pub struct ControlCall {
+    pub request: ControlRequest,
+    reply: oneshot::Sender<ControlResponse>,
+}
+
+impl ControlCall {
+    /// A call for `request` and where its response arrives
+    pub fn new(request: ControlRequest) -> (Self, oneshot::Receiver<ControlResponse>) {
+        let (reply, answer) = oneshot::channel();
+        (Self { request, reply }, answer)
+    }
+
+    pub fn respond(self, response: ControlResponse) {
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Listen on `.synclite/control.sock` and hand every request to `tx`.
+/// Fails if another instance is already running in the workspace.
+#[cfg(unix)]
+pub async fn start_control_server(
+    storage_path: &Path,
+    tx: mpsc::Sender<ControlCall>,
+) -> Result<(), String> {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{UnixListener, UnixStream},
+    };
+
+    use crate::control::CONTROL_SOCKET;
+
+    let socket_path = storage_path.join(CONTROL_SOCKET);
+    if socket_path.exists() {
+        if UnixStream::connect(&socket_path).await.is_ok() {
+            return Err("Another instance is already running in this workspace".to_string());
+        }
+        // Left behind by an instance that didn't shut down cleanly
+        let _ = std::fs::remove_file(&socket_path);
+    }
+
+    let listener = UnixListener::bind(&socket_path)
+        .map_err(|e| format!("Failed to bind control socket: {}", e))?;
+
+    tokio::spawn(async move {
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(_) => break,
+                },
+                // The instance stopped, e.g. a workspace removed from the daemon
+                _ = tx.closed() => break,
+            };
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut line = String::new();
+                if BufReader::new(reader).read_line(&mut line).await.is_err() {
+                    return;
+                }
+
+                let response = match serde_json::from_str::<ControlRequest>(&line) {
+                    Ok(request) => {
+                        let (call, answer) = ControlCall::new(request);
+                        if tx.send(call).await.is_err() {
+                            return;
+                        }
+                        answer.await.unwrap_or(ControlResponse::Error {
+                            message: "The instance dropped the request".to_string(),
+                        })
+                    }
+                    Err(e) => ControlResponse::Error {
+                        message: format!("Invalid request: {}", e),
+                    },
+                };
+
+                if let Ok(mut line) = serde_json::to_string(&response) {
+                    line.push('\n');
+                    let _ = writer.write_all(line.as_bytes()).await;
+                }
+            });
+        }
+        let _ = std::fs::remove_file(&socket_path);
+    });
+
+    Ok(())
+}
+
+/// Listen on `.synclite/control.sock` and hand every request to `tx`.
+/// Without Unix sockets the instance can only be controlled through the HTTP API.
+#[cfg(not(unix))]
+pub async fn start_control_server(
+    _storage_path: &Path,
+    _tx: mpsc::Sender<ControlCall>,
+) -> Result<(), String> {
+    Ok(())
+}
+
+/// Remove the control socket, called when the instance shuts down
+pub fn remove_control_socket(storage_path: &Path) {
+    let _ = std::fs::remove_file(storage_path.join(crate::control::CONTROL_SOCKET));
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::Path,
+    time::Duration,
+};
+
+use colored::Colorize;
+use log::Level;
+use tokio::net::TcpListener;
+
+use crate::{
+    app::{RegisteredWorkspace, load_registry},
+    engine::{SyncEngine, Transport},
+    models::{InstanceRole, InstanceStatus, PeersState},
+    network::{FolderRouter, serve_folders},
+    utils::{Log, read_json},
+};
+
+/// How often the daemon checks the registry for added and removed workspaces
+const REGISTRY_POLL_SECS: u64 = 5;
+
+/// Sync every registered workspace in one process. Leaders share a single port and
+/// connections are routed to them by folder ID. Workspaces registered or unregistered
+/// while running are started or stopped, and workspaces whose engine failed are restarted.
+pub async fn run_daemon(bind: IpAddr, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind((bind, port))
+        .await
+        .map_err(|e| format!("Failed to listen on {}:{}: {}", bind, port, e))?;
+    let router = FolderRouter::new();
+    let mut serving = tokio::spawn(serve_folders(listener, router.clone()));
+
+    let mut workspaces: HashMap<String, SyncEngine> = HashMap::new();
+    // Shut down through their control socket, left alone until registered again
+    let mut stopped: HashSet<String> = HashSet::new();
+    // Why a workspace couldn't start, only logged when it changes
+    let mut failures: HashMap<String, String> = HashMap::new();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(REGISTRY_POLL_SECS));
+    loop {
+        tokio::select! {
+            result = &mut serving => {
+                return result.map_err(|e| format!("Listener stopped: {}", e))?;
+            }
+            _ = interval.tick() => {}
+        }
+
+        let registry = match load_registry() {
+            Ok(registry) => registry,
+            Err(e) => {
+                Log::log_at(
+                    Level::Error,
+                    format!("Failed to load registry: {}", e).red(),
+                    None,
+                );
+                continue;
+            }
+        };
+
+        // Stop workspaces that were unregistered
+        let unregistered: Vec<String> = workspaces
+            .keys()
+            .filter(|path| registry.get(path).is_none())
+            .cloned()
+            .collect();
+        for path in unregistered {
+            if let Some(mut engine) = workspaces.remove(&path) {
+                let _ = engine.stop().await;
+                Log::log(format!("⏹️  Stopped syncing {}", path).yellow(), None);
+            }
+        }
+
+        // Collect workspaces whose engine ended
+        let ended: Vec<String> = workspaces
+            .iter()
+            .filter(|(_, engine)| !engine.is_running())
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in ended {
+            let Some(mut engine) = workspaces.remove(&path) else {
+                continue;
+            };
+            match engine.wait().await {
+                Ok(()) => {
+                    Log::log(format!("⏹️  {} was shut down", path).yellow(), None);
+                    stopped.insert(path);
+                }
+                Err(e) => Log::log_at(
+                    Level::Warn,
+                    format!("Syncing {} failed, retrying: {}", path, e).red(),
+                    None,
+                ),
+            }
+        }
+        stopped.retain(|path| registry.get(path).is_some());
+        failures.retain(|path, _| registry.get(path).is_some());
+
+        // Start workspaces that were registered since the last check
+        for registered in registry.directories {
+            let path = registered.path.clone();
+            if workspaces.contains_key(&path) || stopped.contains(&path) {
+                continue;
+            }
+            match start_workspace(&registered, port, &router).await {
+                Ok(engine) => {
+                    failures.remove(&path);
+                    workspaces.insert(path, engine);
+                }
+                Err(e) => {
+                    if failures.get(&path) != Some(&e) {
+                        Log::log_at(
+                            Level::Error,
+                            format!("Cannot sync {}: {}", path, e).red(),
+                            None,
+                        );
+                        failures.insert(path, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Start syncing a registered workspace in the role and mode it last ran with
+async fn start_workspace(
+    registered: &RegisteredWorkspace,
+    port: u16,
+    router: &FolderRouter,
+) -> Result<SyncEngine, String> {
+    let path = &registered.path;
+    let abs_storage_path = Path::new(path).join(".synclite");
+    if !abs_storage_path.exists() {
+        return Err("No .synclite folder found".to_string());
+    }
+
+    // Resume the workspace the way it was last started, registries from older versions only know the last status
+    let (role, mode) = match (registered.role, registered.mode) {
+        (Some(role), Some(mode)) => (role, mode),
+        _ => match InstanceStatus::read(&abs_storage_path) {
+            Some(last_run) => (last_run.role, last_run.mode),
+            None => return Err("Never started, run serve or connect on it once".to_string()),
+        },
+    };
+
+    // Leaders are served on the daemon's port, peers reach their leader as configured
+    let mut builder = SyncEngine::builder(path).role(role).mode(mode);
+    let mut folder_id = None;
+    if role == InstanceRole::Leader {
+        let peers_state: PeersState = read_json(&abs_storage_path.join("peers.json"))?;
+        folder_id = Some(peers_state.effective_folder_id(Path::new(path)));
+        builder = builder.transport(Transport::Router {
+            router: router.clone(),
+            port,
+        });
+    }
+
+    let mut engine = builder.build()?;
+    engine.start().await?;
+
+    Log::log(
+        format!(
+            "▶️  Syncing {} as {} ({} mode){}",
+            path,
+            format!("{:?}", role).to_lowercase(),
+            mode,
+            folder_id
+                .map(|folder_id| format!(", folder {}", folder_id))
+                .unwrap_or_default()
+        )
+        .green(),
+        None,
+    );
+
+    Ok(engine)
+}
@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use colored::Colorize;
+use log::Level;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use super::progress::TransferDisplay;
+use crate::{
+    metrics::WorkspaceMetrics,
+    models::{InstanceRole, SyncConflict, SyncMode},
+    utils::Log,
+};
+
+/// Which way files move between this workspace and a remote one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// How far a single file of a transfer got
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileProgress {
+    pub path: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Something that happened in a running workspace, delivered to `SyncEngine::subscribe` receivers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum SyncEvent {
+    /// The workspace is set up and starts syncing
+    Started {
+        role: InstanceRole,
+        mode: SyncMode,
+    },
+    /// The engine stopped, with the reason if it failed
+    Stopped {
+        error: Option<String>,
+    },
+    /// A local change was picked up and is about to be sent
+    FileQueued {
+        path: String,
+        deleted: bool,
+    },
+    /// Files moving to or from a remote workspace, `peer_id` is unset when sent to every peer.
+    /// While a large message is still arriving its files are unknown, `files_total` is then 0
+    /// and the bytes count the message itself.
+    TransferProgress {
+        peer_id: Option<String>,
+        direction: TransferDirection,
+        files_done: usize,
+        files_total: usize,
+        bytes_done: u64,
+        bytes_total: u64,
+        /// The file in flight, when known
+        file: Option<FileProgress>,
+    },
+    /// A file received from `peer_id` was written to the workspace
+    FileApplied {
+        path: String,
+        peer_id: String,
+    },
+    /// A file was deleted from the workspace because `peer_id` deleted it
+    FileDeleted {
+        path: String,
+        peer_id: String,
+    },
+    /// A file changed on both sides since the last sync, resolved by Last Write Wins
+    ConflictDetected {
+        conflict: SyncConflict,
+    },
+    /// A connection with a remote workspace was established
+    PeerConnected {
+        peer_id: String,
+        addr: Option<SocketAddr>,
+    },
+    PeerDisconnected {
+        peer_id: String,
+    },
+    /// Changes exchanged with `peer_id` were applied, both workspaces agree as of now
+    Synced {
+        peer_id: String,
+    },
+    /// Something failed without stopping the engine
+    Error {
+        message: String,
+    },
+}
+
+impl SyncEvent {
+    /// Write the event to the log as coming from the caller, JSON lines carry the whole event
+    #[track_caller]
+    fn log(&self) {
+        let (level, message) = match self {
+            SyncEvent::Started { role, mode } => (
+                Level::Debug,
+                format!("Started syncing as {:?} in {} mode", role, mode),
+            ),
+            SyncEvent::Stopped { error: None } => (Level::Debug, "Stopped syncing".to_string()),
+            SyncEvent::Stopped { error: Some(e) } => {
+                (Level::Debug, format!("Stopped syncing: {}", e))
+            }
+            SyncEvent::FileQueued { path, deleted } => (
+                Level::Debug,
+                match deleted {
+                    true => format!("Queued deletion of {}", path),
+                    false => format!("Queued {}", path),
+                },
+            ),
+            SyncEvent::TransferProgress {
+                direction,
+                files_done,
+                files_total,
+                bytes_done,
+                bytes_total,
+                file,
+                ..
+            } => (
+                Level::Debug,
+                format!(
+                    "{:?} {}/{} files, {}/{} bytes{}",
+                    direction,
+                    files_done,
+                    files_total,
+                    bytes_done,
+                    bytes_total,
+                    file.as_ref()
+                        .map(|file| format!(", at {}", file.path))
+                        .unwrap_or_default()
+                ),
+            ),
+            SyncEvent::FileApplied { path, .. } => (
+                Level::Info,
+                format!("  ✨ Creating/Updating: {}", path)
+                    .green()
+                    .to_string(),
+            ),
+            SyncEvent::FileDeleted { path, .. } => (
+                Level::Info,
+                format!("  🗑️  Deleting: {}", path).red().to_string(),
+            ),
+            SyncEvent::ConflictDetected { conflict } => (
+                Level::Warn,
+                format!(
+                    "  ⚠️  Conflict on {}, kept the {} version",
+                    conflict.path, conflict.winner
+                )
+                .yellow()
+                .to_string(),
+            ),
+            SyncEvent::PeerConnected { peer_id, addr } => (
+                Level::Info,
+                match addr {
+                    Some(addr) => format!("🔗 Connected to {} ({})", peer_id, addr),
+                    None => format!("🔗 Connected to {}", peer_id),
+                }
+                .bright_cyan()
+                .to_string(),
+            ),
+            SyncEvent::PeerDisconnected { peer_id } => (
+                Level::Warn,
+                format!("Peer {} disconnected", peer_id).red().to_string(),
+            ),
+            SyncEvent::Synced { peer_id } => (Level::Debug, format!("In sync with {}", peer_id)),
+            SyncEvent::Error { message } => (Level::Error, message.red().to_string()),
+        };
+        Log::event(level, message, self);
+    }
+}
+
+/// Messages smaller than this arrive without progress events
+const LARGE_MESSAGE_BYTES: u64 = 1024 * 1024;
+
+/// A batch of files being sent, see `SyncEvents::start_upload`
+pub(crate) struct Upload {
+    peer_id: Option<String>,
+    /// Paths and sizes in the order the files are serialised
+    files: Vec<(String, u64)>,
+    bytes_total: u64,
+}
+
+/// Publishes the events of a workspace to its subscribers, logs them, shows transfer progress
+/// and counts them in the workspace metrics
+#[derive(Clone)]
+pub(crate) struct SyncEvents {
+    sender: broadcast::Sender<SyncEvent>,
+    display: Arc<Mutex<TransferDisplay>>,
+    metrics: Arc<WorkspaceMetrics>,
+}
+
+impl SyncEvents {
+    pub(crate) fn new(
+        sender: broadcast::Sender<SyncEvent>,
+        metrics: Arc<WorkspaceMetrics>,
+    ) -> Self {
+        Self {
+            sender,
+            display: Arc::new(Mutex::new(TransferDisplay::new())),
+            metrics,
+        }
+    }
+
+    /// Metrics of the workspace, for the counts that don't come from events
+    pub(crate) fn metrics(&self) -> &Arc<WorkspaceMetrics> {
+        &self.metrics
+    }
+
+    /// Where the events are published, for subscribers outside the engine
+    pub(crate) fn sender(&self) -> broadcast::Sender<SyncEvent> {
+        self.sender.clone()
+    }
+
+    /// Log the event and hand it to the subscribers, nobody listening is fine
+    #[track_caller]
+    pub(crate) fn emit(&self, event: SyncEvent) {
+        event.log();
+        self.display.lock().unwrap().update(&event);
+        self.metrics.record(&event);
+        let _ = self.sender.send(event);
+    }
+
+    #[track_caller]
+    pub(crate) fn error(&self, message: String) {
+        self.emit(SyncEvent::Error { message });
+    }
+
+    /// Report local changes about to be sent
+    #[track_caller]
+    pub(crate) fn queued(&self, files: &HashMap<String, Vec<u8>>, deleted: &[String]) {
+        for path in files.keys() {
+            self.emit(SyncEvent::FileQueued {
+                path: path.clone(),
+                deleted: false,
+            });
+        }
+        for path in deleted {
+            self.emit(SyncEvent::FileQueued {
+                path: path.clone(),
+                deleted: true,
+            });
+        }
+    }
+
+    /// Report files about to be sent to a peer, or every peer when `peer_id` is unset.
+    /// The files must be iterated in the same order as the map in the message.
+    #[track_caller]
+    pub(crate) fn start_upload(
+        &self,
+        peer_id: Option<&str>,
+        files: &HashMap<String, Vec<u8>>,
+    ) -> Upload {
+        let files: Vec<(String, u64)> = files
+            .iter()
+            .map(|(path, content)| (path.clone(), content.len() as u64))
+            .collect();
+        let upload = Upload {
+            peer_id: peer_id.map(str::to_string),
+            bytes_total: files.iter().map(|(_, size)| size).sum(),
+            files,
+        };
+        self.upload_progress(&upload, 0, 0, None);
+        upload
+    }
+
+    /// Report how much of the message carrying the files of `start_upload` was sent.
+    /// File contents make up most of the message, so its progress is spread over them in order.
+    #[track_caller]
+    pub(crate) fn upload_sent(&self, upload: &Upload, sent: u64, message_bytes: u64) {
+        if message_bytes < LARGE_MESSAGE_BYTES || sent >= message_bytes {
+            return;
+        }
+        let bytes_done = upload.bytes_total * sent / message_bytes;
+
+        // The file the sent bytes end in, the ones before it are complete
+        let mut offset = 0;
+        let mut files_done = 0;
+        let mut file = None;
+        for (path, size) in &upload.files {
+            if bytes_done < offset + size {
+                file = Some(FileProgress {
+                    path: path.clone(),
+                    bytes_done: bytes_done - offset,
+                    bytes_total: *size,
+                });
+                break;
+            }
+            offset += size;
+            files_done += 1;
+        }
+        self.upload_progress(upload, bytes_done, files_done, file);
+    }
+
+    /// Report the files of `start_upload` as sent
+    #[track_caller]
+    pub(crate) fn finish_upload(&self, upload: Upload) {
+        self.upload_progress(&upload, upload.bytes_total, upload.files.len(), None);
+    }
+
+    #[track_caller]
+    fn upload_progress(
+        &self,
+        upload: &Upload,
+        bytes_done: u64,
+        files_done: usize,
+        file: Option<FileProgress>,
+    ) {
+        if upload.files.is_empty() {
+            return;
+        }
+        self.emit(SyncEvent::TransferProgress {
+            peer_id: upload.peer_id.clone(),
+            direction: TransferDirection::Upload,
+            files_done,
+            files_total: upload.files.len(),
+            bytes_done,
+            bytes_total: upload.bytes_total,
+            file,
+        });
+    }
+
+    /// Report how much of a message from a peer arrived, only large messages are reported
+    #[track_caller]
+    pub(crate) fn receiving(&self, peer_id: &str, received: u64, message_bytes: u64) {
+        if message_bytes < LARGE_MESSAGE_BYTES {
+            return;
+        }
+        self.emit(SyncEvent::TransferProgress {
+            peer_id: Some(peer_id.to_string()),
+            direction: TransferDirection::Download,
+            files_done: 0,
+            files_total: 0,
+            bytes_done: received,
+            bytes_total: message_bytes,
+            file: None,
+        });
+    }
+
+    /// Report the files received from a peer that were `written`, one progress step per file
+    #[track_caller]
+    pub(crate) fn applied(
+        &self,
+        peer_id: &str,
+        files: &HashMap<String, Vec<u8>>,
+        written: &[String],
+    ) {
+        let files: Vec<(&String, &Vec<u8>)> = written
+            .iter()
+            .filter_map(|path| files.get_key_value(path))
+            .collect();
+        let bytes_total = files.iter().map(|(_, content)| content.len() as u64).sum();
+        let mut bytes_done = 0;
+        for (files_done, (path, content)) in files.iter().copied().enumerate() {
+            bytes_done += content.len() as u64;
+            self.emit(SyncEvent::FileApplied {
+                path: path.clone(),
+                peer_id: peer_id.to_string(),
+            });
+            self.emit(SyncEvent::TransferProgress {
+                peer_id: Some(peer_id.to_string()),
+                direction: TransferDirection::Download,
+                files_done: files_done + 1,
+                files_total: files.len(),
+                bytes_done,
+                bytes_total,
+                file: Some(FileProgress {
+                    path: path.clone(),
+                    bytes_done: content.len() as u64,
+                    bytes_total: content.len() as u64,
+                }),
+            });
+        }
+    }
+
+    /// Report files deleted because a peer deleted them
+    #[track_caller]
+    pub(crate) fn deleted(&self, peer_id: &str, paths: &[String]) {
+        for path in paths {
+            self.emit(SyncEvent::FileDeleted {
+                path: path.clone(),
+                peer_id: peer_id.to_string(),
+            });
+        }
+    }
+}
@@ -0,0 +1,1351 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use colored::Colorize;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::{
+    control::{ControlRequest, ControlResponse},
+    engine::{SyncEvent, WorkspaceContext, reload_workspace_config},
+    models::{
+        FileEntry, InstanceRole, InstanceStatus, STATUS_HEARTBEAT_SECS, SyncConfig, SyncConflict,
+        SyncMode, WORKSPACE_CONFIG_FILE, check_workspace_id,
+    },
+    network::{
+        ConnectionThrottle, FrameLimits, IncomingPeer, Link, MemoryBudget, PeerConnectionManager,
+        PeerMessage, ProtocolError, ServerMessage, acknowledge_peer, broadcast_peer_list,
+        generate_peer_id, negotiate_compression, receive_message_limited, record_peer_snapshots,
+        send_message_over, send_message_to_peer,
+    },
+    sync::{
+        ReRequests, SYNC_IGNORE_FILE, calculate_file_hash, check_syncable_paths,
+        determine_mirror_files, determine_winning_files, filter_by_sync_modes, filter_subscribed,
+        find_conflicts, find_local_edits, find_offline_deletions, find_resurrected_files,
+        is_subscribed, normalize_subscriptions, reject_corrupted,
+    },
+    utils::Log,
+};
+
+/// Why a leader refuses to repair its own workspace
+const LEADER_REPAIR: &str =
+    "This workspace is the leader, there is no other copy to repair it from";
+
+/// Serve a workspace: watch it, answer control requests and sync every peer handed over by the router
+pub(super) async fn run_leader(
+    context: WorkspaceContext,
+    mut incoming: mpsc::Receiver<IncomingPeer>,
+) -> Result<(), String> {
+    let WorkspaceContext {
+        abs_workspace_path,
+        abs_storage_path,
+        port,
+        mode,
+        leader: _,
+        workspace_config,
+        sync_config,
+        peers_config,
+        sync_snapshots,
+        paused,
+        bandwidth,
+        shutdown,
+        mut control_calls,
+        events,
+        mut tasks,
+    } = context;
+
+    let leader_id = generate_peer_id();
+
+    // Initialize connection manager
+    let connection_manager = Arc::new(PeerConnectionManager::with_metrics(Arc::clone(
+        events.metrics(),
+    )));
+
+    // Report this instance in status.json, refreshing the connected peers with every heartbeat
+    let instance_status = Arc::new(InstanceStatus::new(
+        &abs_storage_path,
+        InstanceRole::Leader,
+        port,
+        mode,
+    ));
+    {
+        let instance_status = Arc::clone(&instance_status);
+        let connection_manager = Arc::clone(&connection_manager);
+        let events = events.clone();
+        tasks.spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(STATUS_HEARTBEAT_SECS));
+            loop {
+                interval.tick().await;
+                let peers = connection_manager.get_connected_peers().await;
+                if let Err(e) = instance_status.set_connected_peers(peers).await {
+                    events.error(format!("Failed to write status: {}", e));
+                }
+            }
+        });
+    }
+
+    // Flag to ignore file watcher events when program is making changes
+    let ignore_file_events = Arc::new(AtomicBool::new(false));
+
+    // Set this peer as the leader
+    if let Err(e) = peers_config.set_leader(leader_id.clone()).await {
+        Log::error(format!("Failed to set leader: {}", e), None);
+    }
+
+    // Separate connection logs from the rest of the logs for clarity
+    Log::separator();
+    Log::wrench(format!("Serving: {}", abs_workspace_path.display()), None);
+    Log::info(format!("Leader ID: {}", leader_id), None);
+    Log::info(
+        format!(
+            "Folder ID: {}",
+            peers_config
+                .state()
+                .await
+                .effective_folder_id(&abs_workspace_path)
+        ),
+        None,
+    );
+    Log::info(format!("Sync mode: {}", mode), None);
+
+    Log::separator();
+
+    // ===== BROADCAST TASK (SERVER) =====
+    // Send the local changes picked up by the watcher to every peer, in order.
+    // The watcher keeps going meanwhile, e.g. to apply new bandwidth limits to a slow upload.
+    let (local_changes_tx, mut local_changes_rx) =
+        mpsc::channel::<(HashMap<String, Vec<u8>>, Vec<String>)>(100);
+    {
+        let sync_config = Arc::clone(&sync_config);
+        let sync_snapshots = Arc::clone(&sync_snapshots);
+        let connection_manager = Arc::clone(&connection_manager);
+        let events = events.clone();
+
+        tasks.spawn(async move {
+            while let Some((mut files_to_update, paths_to_delete)) = local_changes_rx.recv().await {
+                let hashes = sync_config.advertised_hashes(&mut files_to_update).await;
+                let upload = events.start_upload(None, &files_to_update);
+                connection_manager
+                    .broadcast_message(&ServerMessage::FileUpdatePush {
+                        hashes,
+                        files_to_write: files_to_update,
+                        paths_to_delete,
+                    })
+                    .await;
+                events.finish_upload(upload);
+
+                if let Err(e) = record_peer_snapshots(
+                    Arc::clone(&connection_manager),
+                    &sync_snapshots,
+                    &sync_config.state().await,
+                )
+                .await
+                {
+                    events.error(format!("Failed to record sync snapshots: {}", e));
+                }
+            }
+        });
+    }
+
+    // ===== FILE WATCHER TASK (SERVER) =====
+    // Spawn a background task to watch the workspace directory for changes
+    // This runs independently of the connection handling
+    {
+        let sync_config = Arc::clone(&sync_config);
+        let abs_workspace_path = abs_workspace_path.clone();
+        let abs_storage_path = abs_storage_path.clone();
+        let workspace_config = workspace_config.clone();
+        let ignore_file_events = Arc::clone(&ignore_file_events);
+        let paused = Arc::clone(&paused);
+        let events = events.clone();
+
+        tasks.spawn(async move {
+            // Create a tokio channel for async communication
+            let (tx, mut rx) = mpsc::channel::<notify::Event>(100);
+
+            // Create a custom event handler that sends to the tokio channel
+            let metrics = Arc::clone(events.metrics());
+            let event_handler = move |res: notify::Result<notify::Event>| {
+                if let Ok(event) = res {
+                    metrics.watcher_event();
+                    // Use blocking_send since notify runs in sync context
+                    let _ = tx.blocking_send(event);
+                }
+            };
+
+            // Create watcher with our custom event handler
+            let mut watcher = notify::recommended_watcher(event_handler).unwrap();
+            watcher
+                .watch(&abs_workspace_path, RecursiveMode::Recursive)
+                .unwrap();
+
+            // Loop to handle file system events
+            loop {
+                // First event in a burst
+                let Some(first_event) = rx.recv().await else {
+                    continue;
+                };
+
+                // Skip processing if we're currently making programmatic changes
+                if ignore_file_events.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                // Wait to absorb additional events
+                let debounce = workspace_config.borrow().debounce();
+                tokio::time::sleep(debounce).await;
+
+                // Collect all events that arrived during/after the sleep
+                let mut fs_events = vec![first_event];
+                while let Ok(event) = rx.try_recv() {
+                    fs_events.push(event);
+                }
+
+                // Group by file path, collecting ALL event kinds for each path
+                let mut grouped: HashMap<String, Vec<EventKind>> = HashMap::new();
+
+                for event in fs_events {
+                    for path in event.paths {
+                        if let Some(p) = path.to_str() {
+                            grouped.entry(p.to_string()).or_default().push(event.kind);
+                        }
+                    }
+                }
+
+                let mut files_to_update: HashMap<String, Vec<u8>> = HashMap::new();
+                let mut paths_to_delete: Vec<String> = Vec::new();
+
+                // Now handle each file **once** based on event history and current state
+                for (path, event_kinds) in grouped {
+                    let path_buf = PathBuf::from(&path);
+
+                    // Calculate relative path - skip if path is not within workspace
+                    let relative_path = match path_buf.strip_prefix(&abs_workspace_path) {
+                        Ok(rel) => rel.to_str().unwrap().to_string(),
+                        Err(_) => continue, // Path not within workspace
+                    };
+
+                    // Check actual file system state
+                    let file_exists = path_buf.exists();
+
+                    // Reading a file only raises access events, which reloading would cause itself
+                    let written = event_kinds
+                        .iter()
+                        .any(|kind| !matches!(kind, EventKind::Access(_)));
+
+                    // The workspace config changed, apply the settings that can change while running
+                    let ignore_patterns_changed = written
+                        && path_buf == abs_storage_path.join(WORKSPACE_CONFIG_FILE)
+                        && reload_workspace_config(
+                            &abs_storage_path,
+                            &sync_config,
+                            &workspace_config,
+                        );
+
+                    // The ignore rules changed, reload them and pick up newly un-ignored files
+                    if (written && relative_path == SYNC_IGNORE_FILE) || ignore_patterns_changed {
+                        match sync_config.reload_ignore().await {
+                            Ok(new_files) => files_to_update.extend(new_files),
+                            Err(e) => events
+                                .error(format!("Failed to reload {}: {}", SYNC_IGNORE_FILE, e)),
+                        }
+                    }
+
+                    // Skip the .synclite directory and anything matched by .syncignore
+                    if sync_config.is_ignored(&relative_path, path_buf.is_dir()) {
+                        continue;
+                    }
+
+                    // Handle Directory Logic
+                    if file_exists && path_buf.is_dir() {
+                        // Check if it's a Create event (which happens on directory move/copy)
+                        let has_create = event_kinds.iter().any(|k| {
+                            matches!(k, EventKind::Create(_))
+                                || matches!(
+                                    k,
+                                    EventKind::Modify(notify::event::ModifyKind::Name(
+                                        notify::event::RenameMode::To
+                                    ))
+                                )
+                        });
+
+                        if has_create {
+                            // It's a directory creation/move! Scan it recursively.
+                            let new_files = sync_config
+                                .scan_and_add_directory(&abs_workspace_path, &relative_path)
+                                .await;
+                            files_to_update.extend(new_files);
+                        }
+                        // Skip regular processing for directories
+                        continue;
+                    }
+
+                    // Analyze event history
+                    let has_create = event_kinds
+                        .iter()
+                        .any(|k| matches!(k, EventKind::Create(_)));
+                    let has_remove = event_kinds.iter().any(|k| {
+                        matches!(k, EventKind::Remove(_))
+                            || matches!(
+                                k,
+                                EventKind::Modify(notify::event::ModifyKind::Name(
+                                    notify::event::RenameMode::From
+                                ))
+                            )
+                    });
+                    let has_modify = event_kinds
+                        .iter()
+                        .any(|k| matches!(k, EventKind::Modify(_)));
+
+                    // Determine action based on event history and current state
+                    match (file_exists, has_create, has_remove, has_modify) {
+                        // File exists, saw both Create and Remove -> atomic write, treat as modify
+                        (true, true, true, _) => {
+                            if let Ok(hash) = calculate_file_hash(&path_buf)
+                                && let Err(e) = sync_config.update_file(&relative_path, hash).await
+                            {
+                                events.error(format!(
+                                    "Failed to update file {}: {}",
+                                    relative_path, e
+                                ));
+                            }
+                            files_to_update
+                                .insert(relative_path.clone(), fs::read(&path_buf).unwrap());
+                        }
+                        // File exists, saw Create but no Remove -> new file
+                        (true, true, false, _) => {
+                            if let Err(e) = sync_config
+                                .add_file(
+                                    relative_path.clone(),
+                                    FileEntry {
+                                        hash: Some(calculate_file_hash(&path_buf).unwrap()),
+                                        is_deleted: false,
+                                        last_modified: Utc::now(),
+                                        deleted_at: None,
+                                    },
+                                )
+                                .await
+                            {
+                                events
+                                    .error(format!("Failed to add file {}: {}", relative_path, e));
+                            }
+                            files_to_update
+                                .insert(relative_path.clone(), fs::read(&path_buf).unwrap());
+                        }
+                        // File exists, no Create event -> modification
+                        (true, false, _, true) => {
+                            if let Ok(hash) = calculate_file_hash(&path_buf)
+                                && let Err(e) = sync_config.update_file(&relative_path, hash).await
+                            {
+                                events.error(format!(
+                                    "Failed to update file {}: {}",
+                                    relative_path, e
+                                ));
+                            }
+                            files_to_update
+                                .insert(relative_path.clone(), fs::read(&path_buf).unwrap());
+                        }
+                        // File doesn't exist, saw Remove -> delete (could be file or directory)
+                        (false, _, true, _) => {
+                            // Try recursive delete (handles both files and directories)
+                            let _ = sync_config.delete_directory_recursive(&relative_path).await;
+
+                            paths_to_delete.push(relative_path.clone());
+                        }
+                        // Any other case -> no action needed
+                        _ => {}
+                    }
+                }
+                // Local changes are tracked but never leave a receive-only workspace
+                if !mode.sends_local_changes()
+                    && (!files_to_update.is_empty() || !paths_to_delete.is_empty())
+                {
+                    Log::log(
+                        format!(
+                            "⚠️  {} local changes not propagated ({} mode)",
+                            files_to_update.len() + paths_to_delete.len(),
+                            mode
+                        )
+                        .yellow(),
+                        None,
+                    );
+                    continue;
+                }
+
+                // While paused changes are only recorded, resuming reconciles them with every peer
+                if paused.load(Ordering::Relaxed)
+                    && (!files_to_update.is_empty() || !paths_to_delete.is_empty())
+                {
+                    Log::log(
+                        format!(
+                            "⏸️  Paused, keeping {} local changes until resumed",
+                            files_to_update.len() + paths_to_delete.len()
+                        )
+                        .yellow(),
+                        None,
+                    );
+                    continue;
+                }
+
+                // Broadcast the file updates to all peers
+                if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
+                    Log::log(
+                        format!(
+                            "📡 Broadcasting {} files and {} deletions to peers",
+                            files_to_update.len(),
+                            paths_to_delete.len()
+                        )
+                        .blue(),
+                        None,
+                    );
+                }
+
+                if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
+                    events.queued(&files_to_update, &paths_to_delete);
+                    let _ = local_changes_tx
+                        .send((files_to_update, paths_to_delete))
+                        .await;
+                }
+            }
+        });
+    }
+
+    // ===== CONTROL HANDLER (SERVER) =====
+    // Answer requests coming from the control socket
+    {
+        let sync_config = Arc::clone(&sync_config);
+        let sync_snapshots = Arc::clone(&sync_snapshots);
+        let peers_config = Arc::clone(&peers_config);
+        let instance_status = Arc::clone(&instance_status);
+        let connection_manager = Arc::clone(&connection_manager);
+        let shutdown = Arc::clone(&shutdown);
+        let paused = Arc::clone(&paused);
+        let events = events.clone();
+
+        tasks.spawn(async move {
+            while let Some(call) = control_calls.recv().await {
+                let response = match &call.request {
+                    ControlRequest::Status => ControlResponse::Status {
+                        status: instance_status.state().await,
+                    },
+                    ControlRequest::ListPeers => ControlResponse::Peers {
+                        connected: connection_manager.get_connected_peers().await,
+                        known: peers_config.state().await.peers,
+                    },
+                    ControlRequest::Pause => {
+                        paused.store(true, Ordering::Relaxed);
+                        let _ = instance_status.set_paused(true).await;
+                        Log::log("⏸️  Syncing paused".yellow(), None);
+                        ControlResponse::Done {
+                            message: "Syncing paused".to_string(),
+                        }
+                    }
+                    ControlRequest::Resume | ControlRequest::Rescan => {
+                        let resume = matches!(call.request, ControlRequest::Resume);
+                        if resume {
+                            paused.store(false, Ordering::Relaxed);
+                            let _ = instance_status.set_paused(false).await;
+                            Log::log("▶️  Syncing resumed".green(), None);
+                        }
+
+                        // Record what changed on disk and send it unless our mode keeps local changes
+                        match sync_config.rescan().await {
+                            Ok((mut files_to_write, paths_to_delete)) => {
+                                let changes = files_to_write.len() + paths_to_delete.len();
+                                if changes > 0
+                                    && mode.sends_local_changes()
+                                    && !paused.load(Ordering::Relaxed)
+                                {
+                                    let hashes =
+                                        sync_config.advertised_hashes(&mut files_to_write).await;
+                                    events.queued(&files_to_write, &paths_to_delete);
+                                    let upload = events.start_upload(None, &files_to_write);
+                                    connection_manager
+                                        .broadcast_message(&ServerMessage::FileUpdatePush {
+                                            hashes,
+                                            files_to_write,
+                                            paths_to_delete,
+                                        })
+                                        .await;
+                                    events.finish_upload(upload);
+                                    let _ = record_peer_snapshots(
+                                        Arc::clone(&connection_manager),
+                                        &sync_snapshots,
+                                        &sync_config.state().await,
+                                    )
+                                    .await;
+                                }
+
+                                // Peers may have changed things while we ignored them
+                                if resume {
+                                    connection_manager
+                                        .broadcast_message(&ServerMessage::ResyncRequest)
+                                        .await;
+                                }
+
+                                ControlResponse::Done {
+                                    message: format!(
+                                        "Rescan found {} changes{}",
+                                        changes,
+                                        if resume {
+                                            ", peers asked to resync"
+                                        } else {
+                                            ""
+                                        }
+                                    ),
+                                }
+                            }
+                            Err(e) => ControlResponse::Error { message: e },
+                        }
+                    }
+                    ControlRequest::DisconnectPeer { peer_id } => {
+                        if connection_manager.is_peer_connected(peer_id).await {
+                            // Dropping our end closes the connection, the peer's task cleans up
+                            connection_manager.remove_connection(peer_id).await;
+                            Log::log(format!("Disconnecting peer {}", peer_id).yellow(), None);
+                            ControlResponse::Done {
+                                message: format!("Disconnected {}", peer_id),
+                            }
+                        } else {
+                            ControlResponse::Error {
+                                message: format!("No connected peer {}", peer_id),
+                            }
+                        }
+                    }
+                    ControlRequest::Repair { .. } => ControlResponse::Error {
+                        message: LEADER_REPAIR.to_string(),
+                    },
+                    ControlRequest::Revert => ControlResponse::Error {
+                        message: "This workspace is the leader, peers revert to its copy"
+                            .to_string(),
+                    },
+                    ControlRequest::Shutdown => {
+                        Log::log("Shutting down".yellow(), None);
+                        call.respond(ControlResponse::Done {
+                            message: "Shutting down".to_string(),
+                        });
+                        // Let the reply reach the client before stopping
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        shutdown.notify_one();
+                        continue;
+                    }
+                };
+                call.respond(response);
+            }
+        });
+    }
+
+    // ===== CONNECTION HANDLER (SERVER) =====
+    loop {
+        let incoming_peer = tokio::select! {
+            incoming_peer = incoming.recv() => match incoming_peer {
+                Some(incoming_peer) => incoming_peer,
+                None => return Ok(()),
+            },
+            _ = shutdown.notified() => return Ok(()),
+        };
+
+        // Forget about connections that already ended
+        while tasks.try_join_next().is_some() {}
+
+        let peers_config = Arc::clone(&peers_config);
+        let sync_config = Arc::clone(&sync_config);
+        let sync_snapshots = Arc::clone(&sync_snapshots);
+        let instance_status = Arc::clone(&instance_status);
+        let connection_manager = Arc::clone(&connection_manager);
+        let leader_id = leader_id.clone();
+        let abs_workspace_path = abs_workspace_path.clone();
+        let workspace_config = workspace_config.clone();
+        let ignore_file_events = Arc::clone(&ignore_file_events);
+        let paused = Arc::clone(&paused);
+        let bandwidth = Arc::clone(&bandwidth);
+        let events = events.clone();
+
+        if incoming_peer.repair.is_some() {
+            let throttle = bandwidth.connection(incoming_peer.addr.ip());
+            let limits = FrameLimits::for_workspace(&workspace_config.borrow());
+            tasks.spawn(async move {
+                let addr = incoming_peer.addr;
+                let workspace_id = peers_config.state().await.workspace_id;
+                if let Err(e) = serve_repair(
+                    incoming_peer,
+                    leader_id,
+                    &workspace_id,
+                    &sync_config,
+                    &abs_workspace_path,
+                    throttle,
+                    limits,
+                )
+                .await
+                {
+                    Log::warning(format!("Repair request from {} failed: {}", addr, e), None);
+                }
+            });
+            continue;
+        }
+
+        tasks.spawn(async move {
+            let IncomingPeer {
+                mut reader,
+                writer,
+                addr: peer_addr,
+                compression: offered_compression,
+                repair: _,
+            } = incoming_peer;
+
+            // Generate peer ID
+            let peer_id = generate_peer_id();
+
+            // Add peer to connection manager, limited to the bandwidth of its address
+            // and compressing with the algorithm we prefer among the ones it offered
+            let throttle = bandwidth.connection(peer_addr.ip());
+            let compression = negotiate_compression(
+                &workspace_config.borrow().compression,
+                &offered_compression,
+            );
+            let upload = Link {
+                throttle: throttle.upload,
+                compression,
+                metrics: Some(Arc::clone(events.metrics())),
+                remote: Some(peer_addr.ip()),
+            };
+            connection_manager
+                .add_connection(peer_id.clone(), writer, upload)
+                .await;
+            if let Some(compression) = compression {
+                Log::debug(
+                    format!("Compressing messages to {} with {}", peer_id, compression),
+                    None,
+                );
+            }
+            events.emit(SyncEvent::PeerConnected {
+                peer_id: peer_id.clone(),
+                addr: Some(peer_addr),
+            });
+            if let Err(e) = instance_status
+                .set_connected_peers(connection_manager.get_connected_peers().await)
+                .await
+            {
+                events.error(format!("Failed to write status: {}", e));
+            }
+
+            // Acknowledge peer connection
+            if let Err(e) = acknowledge_peer(
+                Arc::clone(&connection_manager),
+                peer_id.clone(),
+                leader_id.clone(),
+                compression,
+            )
+            .await
+            {
+                events.error(format!("Failed to acknowledge peer: {}", e));
+            }
+
+            // Add peer to peers config
+            if let Err(e) = peers_config.add_peer(peer_id.clone()).await {
+                events.error(format!("Failed to add peer to config: {}", e));
+            }
+
+            // Broadcast peer config to all other peers
+            if let Err(failed_peers) = broadcast_peer_list(
+                Arc::clone(&connection_manager),
+                peers_config.state().await.peers.clone(),
+            )
+            .await
+            {
+                events.error(format!("Failed to broadcast peer list to peers: {:?}", failed_peers));
+            }
+
+            // ===== MESSAGE HANDLER (SERVER) =====
+            // Handle incoming messages from this peer
+            // Each message holds on to its share of the memory budget until it's handled
+            let mut re_requests = ReRequests::new();
+            let budget = MemoryBudget::new(FrameLimits::for_workspace(&workspace_config.borrow()));
+            let download = Link {
+                throttle: throttle.download,
+                compression: None,
+                metrics: Some(Arc::clone(events.metrics())),
+                remote: Some(peer_addr.ip()),
+            };
+            loop {
+                let (message, _reservation) = match receive_message_limited::<PeerMessage>(
+                    &mut reader,
+                    &download,
+                    &budget,
+                    |received, total| events.receiving(&peer_id, received, total),
+                )
+                .await
+                {
+                    Ok(received) => received,
+                    Err(e) => {
+                        if let Some(e) = e.downcast_ref::<ProtocolError>() {
+                            Log::warning(format!("Disconnecting {}: {}", peer_id, e), None);
+                        }
+                        break;
+                    }
+                };
+
+                // While paused nothing is applied, resuming asks every peer to resync
+                if paused.load(Ordering::Relaxed)
+                    && !matches!(message, PeerMessage::FileRequest { .. })
+                {
+                    Log::log(
+                        format!("⏸️  Paused, ignoring changes from {}", peer_id).yellow(),
+                        None,
+                    );
+                    continue;
+                }
+
+                match message {
+                    // STEP 1: Peer sends their version
+                    PeerMessage::InitialSyncPush {
+                        sync_state: mut peer_sync_state,
+                        last_synced: peer_last_synced,
+                        mode: peer_mode,
+                        subscriptions: peer_subscriptions,
+                        workspace_id: peer_workspace_id,
+                    } => {
+                        Log::log(
+                            format!("📥 Received initial sync from {}", peer_id).blue(),
+                            None,
+                        );
+
+                        // The ID names the peer's snapshot file, it must not be able to point anywhere else
+                        if let Err(reason) = check_workspace_id(&peer_workspace_id) {
+                            Log::warning(format!("Disconnecting {}: {}", peer_id, reason), None);
+                            let _ = connection_manager
+                                .send_to_peer(&peer_id, &ServerMessage::ConnectionRejected { reason })
+                                .await;
+                            break;
+                        }
+
+                        // Paths we ignore are neither requested nor deleted
+                        peer_sync_state
+                            .retain(|path, _| !sync_config.is_ignored(path, false));
+
+                        // A peer with subscriptions only syncs those subtrees, anything else is left alone
+                        let peer_subscriptions =
+                            normalize_subscriptions(&peer_subscriptions);
+                        if !peer_subscriptions.is_empty() {
+                            Log::info(
+                                format!(
+                                    "Peer {} subscribed to: {}",
+                                    peer_id,
+                                    peer_subscriptions.join(", ")
+                                ),
+                                None,
+                            );
+                        }
+                        connection_manager
+                            .set_subscriptions(&peer_id, peer_subscriptions.clone())
+                            .await;
+                        connection_manager
+                            .set_workspace_id(&peer_id, peer_workspace_id.clone())
+                            .await;
+                        if let Err(e) = peers_config.add_known_workspace(&peer_workspace_id).await {
+                            events.error(format!("Failed to record peer workspace: {}", e));
+                        }
+
+                        let peer_sync_state =
+                            filter_subscribed(&peer_sync_state, &peer_subscriptions);
+
+                        // STEP 2: Check winning files for both sides
+                        let server_sync_state = filter_subscribed(
+                            &sync_config.state().await,
+                            &peer_subscriptions,
+                        );
+                        let (
+                            mut our_winning_files,
+                            mut their_winning_files,
+                            mut files_to_delete_from_server,
+                            mut files_to_delete_from_peer,
+                        ) = if peer_mode == SyncMode::Mirror {
+                            determine_mirror_files(&server_sync_state, &peer_sync_state)
+                        } else {
+                            determine_winning_files(&server_sync_state, &peer_sync_state)
+                        };
+
+                        // Deletions made on either side since the last sync with this peer (e.g. while
+                        // SyncLite wasn't running) carry stale timestamps, let them win against untouched copies
+                        if peer_mode != SyncMode::Mirror
+                            && let Some(snapshot) = sync_snapshots.load(&peer_workspace_id)
+                        {
+                            let (mut deleted_by_us, deleted_by_peer) = find_offline_deletions(
+                                &server_sync_state,
+                                &peer_sync_state,
+                                &snapshot,
+                            );
+                            // Files we stopped tracking because we ignore them now weren't deleted
+                            deleted_by_us.retain(|path| !sync_config.is_ignored(path, false));
+
+                            if !deleted_by_us.is_empty() || !deleted_by_peer.is_empty() {
+                                Log::log(
+                                    format!(
+                                        "{} local and {} remote deletions since the last sync with {}",
+                                        deleted_by_us.len(),
+                                        deleted_by_peer.len(),
+                                        peer_id
+                                    )
+                                    .yellow(),
+                                    None,
+                                );
+                            }
+
+                            their_winning_files
+                                .retain(|path| !deleted_by_us.contains(path));
+                            files_to_delete_from_peer.extend(deleted_by_us);
+                            our_winning_files
+                                .retain(|path| !deleted_by_peer.contains(path));
+                            files_to_delete_from_server.extend(deleted_by_peer);
+
+                            // Files edited on both sides are still resolved by LWW, but recorded for `synclite status`
+                            let conflicts = find_conflicts(
+                                &server_sync_state,
+                                &peer_sync_state,
+                                &snapshot,
+                            );
+                            if !conflicts.is_empty() {
+                                Log::log(
+                                    format!(
+                                        "⚠️  {} files changed on both sides since the last sync with {}, keeping the newest",
+                                        conflicts.len(),
+                                        peer_id
+                                    )
+                                    .yellow(),
+                                    None,
+                                );
+
+                                let detected_at = Utc::now();
+                                let conflicts: Vec<SyncConflict> = conflicts
+                                    .into_iter()
+                                    .map(|path| SyncConflict {
+                                        winner: if their_winning_files.contains(&path) {
+                                            "remote".to_string()
+                                        } else {
+                                            "local".to_string()
+                                        },
+                                        path,
+                                        peer_id: peer_id.clone(),
+                                        detected_at,
+                                    })
+                                    .collect();
+                                for conflict in &conflicts {
+                                    events.emit(SyncEvent::ConflictDetected {
+                                        conflict: conflict.clone(),
+                                    });
+                                }
+                                if let Err(e) =
+                                    instance_status.add_conflicts(conflicts).await
+                                {
+                                    events.error(format!("Failed to record conflicts: {}", e));
+                                }
+                            }
+                        }
+
+                        // A peer that was offline longer than the tombstone retention may still hold
+                        // files whose tombstones we already compacted, delete them instead of pulling them back
+                        if let Some(peer_last_synced) = peer_last_synced
+                            && peer_last_synced < sync_config.tombstone_horizon()
+                        {
+                            let mut resurrected = find_resurrected_files(
+                                &server_sync_state,
+                                &peer_sync_state,
+                                peer_last_synced,
+                            );
+                            // Deletions already found through the snapshot
+                            resurrected.retain(|path| !files_to_delete_from_peer.contains(path));
+
+                            if !resurrected.is_empty() {
+                                Log::log(
+                                    format!(
+                                        "Peer {} was offline past the tombstone retention, {} stale files will be deleted",
+                                        peer_id,
+                                        resurrected.len()
+                                    )
+                                    .yellow(),
+                                    None,
+                                );
+                                their_winning_files
+                                    .retain(|path| !resurrected.contains(path));
+                                files_to_delete_from_peer.extend(resurrected);
+                            }
+                        }
+
+                        // Drop the changes that the sync modes of either side don't allow
+                        let peer_changes =
+                            their_winning_files.len() + files_to_delete_from_server.len();
+                        let (
+                            our_winning_files,
+                            their_winning_files,
+                            files_to_delete_from_server,
+                            files_to_delete_from_peer,
+                        ) = filter_by_sync_modes(
+                            (
+                                our_winning_files,
+                                their_winning_files,
+                                files_to_delete_from_server,
+                                files_to_delete_from_peer,
+                            ),
+                            mode,
+                            peer_mode,
+                        );
+
+                        let skipped_peer_changes = peer_changes
+                            - their_winning_files.len()
+                            - files_to_delete_from_server.len();
+                        if skipped_peer_changes > 0 {
+                            Log::log(
+                                format!(
+                                    "⚠️  {} changes from {} not applied (leader: {}, peer: {})",
+                                    skipped_peer_changes, peer_id, mode, peer_mode
+                                )
+                                .yellow(),
+                                None,
+                            );
+                        }
+
+                        Log::log(
+                                    format!(
+                                        "Sync result: {} to send, {} to receive, {} to delete locally, {} to delete remotely",
+                                        our_winning_files.len(),
+                                        their_winning_files.len(),
+                                        files_to_delete_from_server.len(),
+                                        files_to_delete_from_peer.len()
+                                    )
+                                    .blue(),
+                                    None,
+                                );
+
+                        // Handle file deletions first
+                        if !files_to_delete_from_server.is_empty() {
+                            ignore_file_events.store(true, Ordering::Relaxed);
+
+                            // Unified filesystem + state delete operation
+                            match sync_config
+                                .sync_batch_delete_files(
+                                    &abs_workspace_path,
+                                    &files_to_delete_from_server,
+                                    Some(&peer_sync_state),
+                                )
+                                .await
+                            {
+                                Ok(deleted) => events.deleted(&peer_id, &deleted),
+                                Err(e) => events.error(format!("Failed to delete files: {}", e)),
+                            }
+
+                            // Small delay to ensure file watcher events are processed
+                            let ignore_window = workspace_config.borrow().ignore_window();
+                            tokio::time::sleep(ignore_window).await;
+                            ignore_file_events.store(false, Ordering::Relaxed);
+                        }
+
+                        // STEP 3: Send our winning files with content and request their winning files
+                        // (always answer so the peer knows the initial sync completed)
+                        // Read content of our winning files (paths are relative to workspace)
+                        let mut my_winning_files_with_content: HashMap<String, Vec<u8>> =
+                            HashMap::new();
+                        for file_path in &our_winning_files {
+                            // Convert relative path to absolute path for file operations
+                            let full_path = abs_workspace_path.join(file_path);
+                            match fs::read(&full_path) {
+                                Ok(content) => {
+                                    // Store with relative path as key
+                                    my_winning_files_with_content
+                                        .insert(file_path.clone(), content);
+                                }
+                                Err(e) => {
+                                    events.error(format!(
+                                            "Failed to read workspace file {}: {}",
+                                            file_path, e
+                                        ));
+                                }
+                            }
+                        }
+                        let hashes = sync_config
+                            .advertised_hashes(&mut my_winning_files_with_content)
+                            .await;
+
+                        if !my_winning_files_with_content.is_empty() {
+                            Log::log(
+                                format!(
+                                    "Sending {} winning files to peer: {}",
+                                    my_winning_files_with_content.len(),
+                                    peer_id
+                                )
+                                .blue(),
+                                None,
+                            );
+                        }
+
+                        if !their_winning_files.is_empty() {
+                            Log::log(
+                                format!(
+                                    "Requesting {} winning files from peer: {}",
+                                    their_winning_files.len(),
+                                    peer_id
+                                )
+                                .blue(),
+                                None,
+                            );
+                        }
+
+                        let upload =
+                            events.start_upload(Some(&peer_id), &my_winning_files_with_content);
+                        match connection_manager
+                            .send_to_peer_with_progress(
+                                &peer_id,
+                                &ServerMessage::InitialSyncPushResponse {
+                                    hashes,
+                                    files_to_update: my_winning_files_with_content,
+                                    files_to_delete: files_to_delete_from_peer,
+                                    files_to_send_back: their_winning_files,
+                                },
+                                |sent, total| events.upload_sent(&upload, sent, total),
+                            )
+                            .await
+                        {
+                            Ok(()) => {
+                                events.finish_upload(upload);
+                                events.emit(SyncEvent::Synced {
+                                    peer_id: peer_id.clone(),
+                                });
+                            }
+                            Err(e) => events.error(format!(
+                                "Failed to send initial sync push response to peer {}: {}",
+                                peer_id, e
+                            )),
+                        }
+
+                        if let Err(e) = sync_snapshots.record(
+                            &peer_workspace_id,
+                            filter_subscribed(
+                                &sync_config.state().await,
+                                &peer_subscriptions,
+                            ),
+                        ) {
+                            events.error(format!("Failed to record sync snapshot: {}", e));
+                        }
+                    }
+                    PeerMessage::FileUpdatePush {
+                        mut files_to_write,
+                        hashes,
+                        paths_to_delete,
+                    } => {
+                        Log::log(
+                            format!(
+                                "📥 Received from {}: {} files, {} deletions",
+                                peer_id,
+                                files_to_write.len(),
+                                paths_to_delete.len()
+                            )
+                            .blue(),
+                            None,
+                        );
+
+                        // A send-only leader is the source of truth, flag and drop remote changes
+                        if !mode.accepts_remote_changes() {
+                            Log::log(
+                                format!(
+                                    "⚠️  Ignoring {} remote changes from {} ({} mode)",
+                                    files_to_write.len() + paths_to_delete.len(),
+                                    peer_id,
+                                    mode
+                                )
+                                .yellow(),
+                                None,
+                            );
+                            continue;
+                        }
+
+                        // Never write content that doesn't match what the peer advertised, ask for it again instead
+                        let rejected = reject_corrupted(&mut files_to_write, &hashes);
+                        re_requests.received(files_to_write.keys());
+                        if !rejected.is_empty() {
+                            Log::log(
+                                format!(
+                                    "⚠️  {} files from {} failed the integrity check: {}",
+                                    rejected.len(),
+                                    peer_id,
+                                    rejected.join(", ")
+                                )
+                                .yellow(),
+                                None,
+                            );
+
+                            let paths = re_requests.to_request(rejected);
+                            if !paths.is_empty()
+                                && let Err(e) = connection_manager
+                                    .send_to_peer(
+                                        &peer_id,
+                                        &ServerMessage::FileRequest { paths },
+                                    )
+                                    .await
+                            {
+                                events.error(format!("Failed to request files again: {}", e));
+                            }
+                        }
+
+                        // Handle directory deletions
+                        if !paths_to_delete.is_empty() {
+                            ignore_file_events.store(true, Ordering::Relaxed);
+                            for path in &paths_to_delete {
+                                // Delete from filesystem and state, keeping ignored files
+                                let deleted = sync_config
+                                    .sync_delete_path(&abs_workspace_path, path)
+                                    .await;
+                                events.deleted(&peer_id, &deleted);
+                            }
+                        }
+
+                        if !files_to_write.is_empty() {
+                            ignore_file_events.store(true, Ordering::Relaxed);
+                            match sync_config
+                                .sync_batch_write_files(
+                                    &abs_workspace_path,
+                                    &files_to_write,
+                                )
+                                .await
+                            {
+                                Ok(written) => {
+                                    events.applied(&peer_id, &files_to_write, &written)
+                                }
+                                Err(e) => events.error(format!("Failed to write files: {}", e)),
+                            }
+                        }
+
+                        // Small delay to ensure file watcher events are processed
+                        let ignore_window = workspace_config.borrow().ignore_window();
+                        tokio::time::sleep(ignore_window).await;
+                        ignore_file_events.store(false, Ordering::Relaxed);
+                        events.emit(SyncEvent::Synced {
+                            peer_id: peer_id.clone(),
+                        });
+
+                        // Broadcast to other peers
+                        if !files_to_write.is_empty() || !paths_to_delete.is_empty() {
+                            Log::log(
+                                "📡 Forwarding to other peers".to_string().blue(),
+                                None,
+                            );
+                        }
+
+                        let upload = events.start_upload(None, &files_to_write);
+                        let failed_peers = connection_manager
+                            .broadcast_except(
+                                &ServerMessage::FileUpdatePush {
+                                    files_to_write: files_to_write.clone(),
+                                    hashes,
+                                    paths_to_delete,
+                                },
+                                vec![peer_id.clone()],
+                            )
+                            .await;
+                        events.finish_upload(upload);
+
+                        if !failed_peers.is_empty() {
+                            events.error(format!(
+                                    "Failed to broadcast file updates to {} peers",
+                                    failed_peers.len()
+                                ));
+                        }
+
+                        if let Err(e) = record_peer_snapshots(
+                            Arc::clone(&connection_manager),
+                            &sync_snapshots,
+                            &sync_config.state().await,
+                        )
+                        .await
+                        {
+                            events.error(format!("Failed to record sync snapshots: {}", e));
+                        }
+                    }
+                    PeerMessage::FileRequest { paths } => {
+                        if let Err(e) = check_syncable_paths(&paths) {
+                            Log::warning(
+                                format!("Ignoring file request from {}: {}", peer_id, e),
+                                None,
+                            );
+                            continue;
+                        }
+
+                        // The peer received corrupted content, send the files again from disk
+                        let subscriptions =
+                            connection_manager.get_subscriptions(&peer_id).await;
+                        let paths: Vec<String> = paths
+                            .into_iter()
+                            .filter(|path| is_subscribed(&subscriptions, path))
+                            .collect();
+                        let mut files_to_write = sync_config
+                            .read_tracked_files(&abs_workspace_path, &paths)
+                            .await;
+                        let hashes = sync_config.advertised_hashes(&mut files_to_write).await;
+
+                        Log::log(
+                            format!(
+                                "🔁 Sending {} files to {} again",
+                                files_to_write.len(),
+                                peer_id
+                            )
+                            .blue(),
+                            None,
+                        );
+
+                        let upload = events.start_upload(Some(&peer_id), &files_to_write);
+                        match connection_manager
+                            .send_to_peer_with_progress(
+                                &peer_id,
+                                &ServerMessage::FileUpdatePush {
+                                    hashes,
+                                    files_to_write,
+                                    paths_to_delete: Vec::new(),
+                                },
+                                |sent, total| events.upload_sent(&upload, sent, total),
+                            )
+                            .await
+                        {
+                            Ok(()) => events.finish_upload(upload),
+                            Err(e) => {
+                                events.error(format!("Failed to send requested files: {}", e))
+                            }
+                        }
+                    }
+
+                    // The router already read the folder the peer joined
+                    PeerMessage::JoinFolder { .. } | PeerMessage::RepairFolder { .. } => {}
+                    // Only answered over repair connections
+                    PeerMessage::RevertRequest { .. } => {}
+                }
+            }
+
+            // Remove peer from connection manager and peers config when connection is lost
+            events.emit(SyncEvent::PeerDisconnected {
+                peer_id: peer_id.clone(),
+            });
+
+            // Remove peer from connection manager
+            connection_manager.remove_connection(&peer_id).await;
+            if let Err(e) = instance_status
+                .set_connected_peers(connection_manager.get_connected_peers().await)
+                .await
+            {
+                events.error(format!("Failed to write status: {}", e));
+            }
+
+            // Remove peer from peers config
+            if let Err(e) = peers_config.remove_peer(&peer_id).await {
+                events.error(format!("Failed to remove peer {} from config: {}", peer_id, e));
+            }
+
+            // Broadcast peer config to all other peers
+            if let Err(failed_peers) = broadcast_peer_list(
+                connection_manager,
+                peers_config.state().await.peers.clone(),
+            )
+            .await
+            {
+                events.error(format!("Failed to broadcast peer list to peers: {:?}", failed_peers));
+            }
+        });
+    }
+}
+
+/// Answer a connection that only fetches files, to repair a workspace or revert its local edits.
+/// It isn't registered as a peer and receives nothing but the files it asked for.
+async fn serve_repair(
+    incoming_peer: IncomingPeer,
+    leader_id: String,
+    workspace_id: &str,
+    sync_config: &SyncConfig,
+    abs_workspace_path: &Path,
+    throttle: ConnectionThrottle,
+    limits: FrameLimits,
+) -> Result<(), String> {
+    let IncomingPeer {
+        mut reader,
+        mut writer,
+        addr,
+        repair,
+        ..
+    } = incoming_peer;
+
+    if repair.as_deref() == Some(workspace_id) {
+        let _ = send_message_to_peer(
+            &mut writer,
+            &ServerMessage::ConnectionRejected {
+                reason: LEADER_REPAIR.to_string(),
+            },
+        )
+        .await;
+        return Err(LEADER_REPAIR.to_string());
+    }
+
+    send_message_to_peer(
+        &mut writer,
+        &ServerMessage::ConnectionAck {
+            peer_id: generate_peer_id(),
+            leader_id,
+            compression: None,
+        },
+    )
+    .await
+    .map_err(|e| format!("Failed to acknowledge: {}", e))?;
+
+    let budget = MemoryBudget::new(limits);
+    let download = Link {
+        throttle: throttle.download,
+        ..Link::default()
+    };
+    let paths =
+        match receive_message_limited::<PeerMessage>(&mut reader, &download, &budget, |_, _| {})
+            .await
+        {
+            Ok((PeerMessage::FileRequest { paths }, _)) => paths,
+            Ok((PeerMessage::RevertRequest { sync_state }, _)) => {
+                find_local_edits(&sync_config.state().await, &sync_state)
+            }
+            Ok((other, _)) => return Err(format!("Unexpected message: {:?}", other)),
+            Err(e) => return Err(format!("Failed to receive the request: {}", e)),
+        };
+
+    if let Err(e) = check_syncable_paths(&paths) {
+        let _ = send_message_to_peer(
+            &mut writer,
+            &ServerMessage::ConnectionRejected { reason: e.clone() },
+        )
+        .await;
+        return Err(e);
+    }
+
+    let mut files = sync_config
+        .read_tracked_files(abs_workspace_path, &paths)
+        .await;
+    let hashes = sync_config.advertised_hashes(&mut files).await;
+    Log::log(
+        format!("🔧 Sending {} requested files to {}", files.len(), addr).blue(),
+        None,
+    );
+
+    let upload = Link {
+        throttle: throttle.upload,
+        compression: None,
+        metrics: None,
+        remote: None,
+    };
+    send_message_over(
+        &mut writer,
+        &ServerMessage::RequestedFiles { files, hashes },
+        &upload,
+        |_, _| {},
+    )
+    .await
+    .map_err(|e| format!("Failed to send the files: {}", e))
+}
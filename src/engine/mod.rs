@@ -0,0 +1,364 @@
+mod daemon;
+mod events;
+mod leader;
+mod peer;
+mod progress;
+mod schedule;
+mod sync_engine;
+
+pub use daemon::*;
+pub use events::*;
+pub use sync_engine::*;
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use colored::Colorize;
+use log::Level;
+use tokio::{
+    net::TcpListener,
+    sync::{Notify, mpsc, watch},
+    task::JoinSet,
+};
+
+use crate::{
+    api::{API_KEY_FILE, ApiContext, api_key, start_api_server},
+    app::record_workspace_start,
+    control::{ControlCall, remove_control_socket, start_control_server},
+    models::{
+        ApiConfig, InstanceRole, PeersConfig, SyncConfig, SyncMode, SyncSnapshots, WorkspaceConfig,
+    },
+    network::{BandwidthLimiter, FolderRouter, IncomingPeer, serve_folders},
+    sync::acknowledged_by_snapshots,
+    utils::{Log, read_json},
+};
+
+/// A workspace to sync and how to sync it, resolved by the `SyncEngine` builder
+struct WorkspaceOptions {
+    abs_workspace_path: PathBuf,
+    role: InstanceRole,
+    mode: SyncMode,
+    transport: Transport,
+    config: WorkspaceConfig,
+    /// Local HTTP API to serve, if any
+    api: Option<ApiConfig>,
+    events: SyncEvents,
+}
+
+/// Everything the leader and peer engines of a workspace share
+struct WorkspaceContext {
+    abs_workspace_path: PathBuf,
+    abs_storage_path: PathBuf,
+    port: u16,
+    mode: SyncMode,
+    leader: Option<SocketAddr>,
+    /// Settings from `.synclite/config.json`, replaced when the file changes
+    workspace_config: watch::Sender<WorkspaceConfig>,
+    sync_config: Arc<SyncConfig>,
+    peers_config: Arc<PeersConfig>,
+    sync_snapshots: Arc<SyncSnapshots>,
+    /// Set through the control socket, nothing is applied or sent while paused
+    paused: Arc<AtomicBool>,
+    /// Throttles every connection of the workspace, following the workspace config
+    bandwidth: Arc<BandwidthLimiter>,
+    /// Notified by the control socket to stop the engine
+    shutdown: Arc<Notify>,
+    control_calls: mpsc::Receiver<ControlCall>,
+    events: SyncEvents,
+    /// Background tasks of the workspace, aborted when the engine stops
+    tasks: JoinSet<()>,
+}
+
+/// A workspace set up and ready to sync
+struct PreparedWorkspace {
+    role: InstanceRole,
+    context: WorkspaceContext,
+    /// Connections for the folder of a leader
+    incoming: Option<mpsc::Receiver<IncomingPeer>>,
+}
+
+/// Set up an initialised workspace: take its control socket, load its state and
+/// start accepting connections. Fails if it is already running.
+async fn prepare_workspace(options: WorkspaceOptions) -> Result<PreparedWorkspace, String> {
+    let WorkspaceOptions {
+        abs_workspace_path,
+        role,
+        mode,
+        transport,
+        config: workspace_config,
+        api,
+        events,
+    } = options;
+    let abs_storage_path = abs_workspace_path.join(".synclite");
+    let port = transport.port();
+    let mut tasks = JoinSet::new();
+
+    // Listen for control requests (status, pause, shutdown...) from the CLI
+    let (control_tx, control_calls) = mpsc::channel::<ControlCall>(16);
+    start_control_server(&abs_storage_path, control_tx.clone()).await?;
+
+    // Initialize sync_config, load the state from the storage directory
+    // and patch with the the state of the physical filesystem
+    let sync_config = Arc::new(SyncConfig::new(&abs_storage_path));
+    sync_config.apply_workspace_config(&workspace_config);
+    sync_config.load().await?;
+    sync_config.patch().await?;
+
+    // Initialize peers_config, load the state from the storage directory
+    let peers_config = Arc::new(PeersConfig::new(
+        &abs_storage_path,
+        read_json(&abs_storage_path.join("peers.json"))?,
+    ));
+
+    // Serve status and control to dashboards and editor plugins
+    if let Some(api) = api {
+        let server = start_api_server(
+            api.port,
+            ApiContext {
+                key: api_key(&abs_storage_path, &api)?,
+                sync_config: Arc::clone(&sync_config),
+                peers_config: Arc::clone(&peers_config),
+                control: control_tx.clone(),
+                events: events.sender(),
+            },
+        )
+        .await?;
+        tasks.spawn(server);
+        let key_source = match api.key {
+            Some(_) => "key from the workspace config".to_string(),
+            None => format!("key in .synclite/{}", API_KEY_FILE),
+        };
+        Log::info(
+            format!("HTTP API on http://127.0.0.1:{}, {}", api.port, key_source),
+            None,
+        );
+    }
+
+    // Remember how the workspace was started, the daemon resumes it the same way
+    let folder_id = match role {
+        InstanceRole::Leader => Some(
+            peers_config
+                .state()
+                .await
+                .effective_folder_id(&abs_workspace_path),
+        ),
+        InstanceRole::Peer => peers_config.state().await.folder_id,
+    };
+    if let Err(e) = record_workspace_start(&abs_workspace_path, role, mode, port, folder_id.clone())
+    {
+        Log::log_at(
+            Level::Error,
+            format!("Failed to update registry: {}", e).red(),
+            None,
+        );
+    }
+
+    // Snapshots of the state last agreed with each remote workspace, used to date offline deletions
+    let sync_snapshots = Arc::new(SyncSnapshots::new(&abs_storage_path));
+
+    // A standalone leader accepts connections on its own port, a daemon's leaders share one
+    let incoming = match (role, &transport) {
+        (InstanceRole::Leader, Transport::Tcp { bind, port }) => {
+            let listener = TcpListener::bind((*bind, *port))
+                .await
+                .map_err(|e| format!("Failed to listen on {}:{}: {}", bind, port, e))?;
+            let router = FolderRouter::new();
+            let incoming = router
+                .register(folder_id.as_deref().unwrap_or_default())
+                .await?;
+            tasks.spawn(async move {
+                if let Err(e) = serve_folders(listener, router).await {
+                    Log::error(e, None);
+                }
+            });
+            Some(incoming)
+        }
+        (InstanceRole::Leader, Transport::Router { router, .. }) => Some(
+            router
+                .register(folder_id.as_deref().unwrap_or_default())
+                .await?,
+        ),
+        _ => None,
+    };
+
+    // Pause and resume on the schedule of the workspace config, as the CLI would
+    let workspace_config = watch::Sender::new(workspace_config);
+    tasks.spawn(schedule::run_schedule(
+        workspace_config.subscribe(),
+        control_tx,
+    ));
+
+    // Drop tombstones and stored versions that outlived their retention, then keep cleaning periodically
+    {
+        let sync_config = Arc::clone(&sync_config);
+        let peers_config = Arc::clone(&peers_config);
+        let sync_snapshots = Arc::clone(&sync_snapshots);
+        let workspace_config = workspace_config.subscribe();
+        tasks.spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                let compact_acknowledged =
+                    workspace_config.borrow().compact_acknowledged_tombstones;
+                let compacted = match (compact_acknowledged, role) {
+                    (false, _) => sync_config.compact_tombstones(|_, _| false).await,
+                    // The leader keeps what it agreed on with each peer
+                    (true, InstanceRole::Leader) => {
+                        let known_workspaces = peers_config.state().await.known_workspaces;
+                        let snapshots = sync_snapshots.load_all();
+                        sync_config
+                            .compact_tombstones(|path, _| {
+                                acknowledged_by_snapshots(path, &known_workspaces, &snapshots)
+                            })
+                            .await
+                    }
+                    // The leader, our only peer, has every deletion recorded before our last sync with it
+                    (true, InstanceRole::Peer) => {
+                        let last_synced = peers_config.state().await.last_synced;
+                        sync_config
+                            .compact_tombstones(|_, entry| {
+                                last_synced
+                                    .is_some_and(|last_synced| entry.deleted_since() <= last_synced)
+                            })
+                            .await
+                    }
+                };
+                match compacted {
+                    Ok(purged) if !purged.is_empty() => {
+                        Log::log(
+                            format!("🧹 Compacted {} tombstones", purged.len()).yellow(),
+                            None,
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        Log::log_at(
+                            Level::Error,
+                            format!("Failed to compact tombstones: {}", e).red(),
+                            None,
+                        );
+                    }
+                }
+                match sync_config.versioning().cleanup() {
+                    Ok(removed) if removed > 0 => {
+                        Log::log(
+                            format!("🧹 Removed {} expired file versions", removed).yellow(),
+                            None,
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        Log::log_at(
+                            Level::Error,
+                            format!("Failed to clean up file versions: {}", e).red(),
+                            None,
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    // Limit bandwidth as configured, adjusting open connections when the config changes
+    let bandwidth = Arc::new(BandwidthLimiter::new(&workspace_config.borrow().bandwidth));
+    {
+        let bandwidth = Arc::clone(&bandwidth);
+        let mut workspace_config = workspace_config.subscribe();
+        tasks.spawn(async move {
+            while workspace_config.changed().await.is_ok() {
+                bandwidth.apply(&workspace_config.borrow_and_update().bandwidth);
+            }
+        });
+    }
+
+    let context = WorkspaceContext {
+        abs_workspace_path,
+        abs_storage_path,
+        port,
+        mode,
+        leader: transport.leader(),
+        workspace_config,
+        sync_config,
+        peers_config,
+        sync_snapshots,
+        paused: Arc::new(AtomicBool::new(false)),
+        bandwidth,
+        shutdown: Arc::new(Notify::new()),
+        control_calls,
+        events,
+        tasks,
+    };
+
+    Ok(PreparedWorkspace {
+        role,
+        context,
+        incoming,
+    })
+}
+
+/// Sync a prepared workspace until it is shut down or its connection fails
+async fn run_workspace(workspace: PreparedWorkspace) -> Result<(), String> {
+    let PreparedWorkspace {
+        role,
+        context,
+        incoming,
+    } = workspace;
+    let abs_storage_path = context.abs_storage_path.clone();
+
+    let result = match (role, incoming) {
+        (InstanceRole::Leader, Some(incoming)) => leader::run_leader(context, incoming).await,
+        _ => peer::run_peer(context).await,
+    };
+
+    remove_control_socket(&abs_storage_path);
+    result
+}
+
+/// Re-read `.synclite/config.json` after it changed on disk and apply what can change while running.
+/// An invalid file is reported and the previous settings are kept.
+/// Returns whether the ignore patterns changed, the caller then reloads the ignore rules.
+fn reload_workspace_config(
+    abs_storage_path: &Path,
+    sync_config: &SyncConfig,
+    workspace_config: &watch::Sender<WorkspaceConfig>,
+) -> bool {
+    let config = match WorkspaceConfig::load(abs_storage_path) {
+        Ok(config) => config,
+        Err(e) => {
+            Log::log_at(
+                Level::Warn,
+                format!("Keeping the previous workspace config: {}", e).red(),
+                None,
+            );
+            return false;
+        }
+    };
+
+    let previous = workspace_config.borrow().clone();
+    if config == previous {
+        return false;
+    }
+
+    let needs_restart = config.restart_required_changes(&previous);
+    if !needs_restart.is_empty() {
+        Log::log(
+            format!(
+                "⚠️  Changed {} in the workspace config, restart to apply",
+                needs_restart.join(", ")
+            )
+            .yellow(),
+            None,
+        );
+    }
+
+    sync_config.apply_workspace_config(&config);
+    let ignore_changed = config.ignore != previous.ignore;
+    workspace_config.send_replace(config);
+    Log::log("⚙️  Reloaded the workspace config".green(), None);
+
+    ignore_changed
+}
@@ -0,0 +1,1200 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use colored::Colorize;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::{net::TcpStream, sync::mpsc};
+
+use crate::{
+    control::{ControlRequest, ControlResponse},
+    engine::{SyncEvent, WorkspaceContext, reload_workspace_config},
+    models::{
+        FileEntry, InstanceRole, InstanceStatus, STATUS_HEARTBEAT_SECS, WORKSPACE_CONFIG_FILE,
+    },
+    network::{
+        FrameLimits, Link, MemoryBudget, MemoryReservation, PeerMessage, ServerMessage,
+        discover_leader_until, receive_message_from_peer, receive_message_limited, repair_files,
+        revert_files, send_message_over, send_message_to_peer,
+    },
+    sync::{
+        ReRequests, SYNC_IGNORE_FILE, calculate_file_hash, check_syncable_paths, reject_corrupted,
+    },
+    utils::Log,
+};
+
+/// Connect a workspace to its leader and keep it in sync until the connection drops
+pub(super) async fn run_peer(context: WorkspaceContext) -> Result<(), String> {
+    let WorkspaceContext {
+        abs_workspace_path,
+        abs_storage_path,
+        port,
+        mode,
+        leader,
+        workspace_config,
+        sync_config,
+        peers_config,
+        sync_snapshots: _,
+        paused,
+        bandwidth,
+        shutdown,
+        mut control_calls,
+        events,
+        mut tasks,
+    } = context;
+
+    // Discover the leader over mDNS unless it was given, browsing blocks so keep it off the async workers
+    let addr = match leader {
+        Some(addr) => addr,
+        None => {
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let mut discovery = tokio::task::spawn_blocking({
+                let cancelled = Arc::clone(&cancelled);
+                move || discover_leader_until(&cancelled)
+            });
+
+            // The control handler only starts once connected, answer what we can meanwhile
+            loop {
+                tokio::select! {
+                    discovered = &mut discovery => {
+                        break discovered
+                            .map_err(|e| format!("Failed to discover the leader: {}", e))??;
+                    }
+                    _ = shutdown.notified() => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    Some(call) = control_calls.recv() => {
+                        if let ControlRequest::Shutdown = call.request {
+                            cancelled.store(true, Ordering::Relaxed);
+                            Log::log("Shutting down".yellow(), None);
+                            call.respond(ControlResponse::Done {
+                                message: "Shutting down".to_string(),
+                            });
+                            // Let the reply reach the client before stopping
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            return Ok(());
+                        }
+                        call.respond(ControlResponse::Error {
+                            message: "Still looking for the leader".to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    };
+
+    let stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+
+    let (mut reader, mut writer) = stream.into_split();
+
+    // Name the folder we want, a daemon serving several folders routes us by it,
+    // and offer the compression algorithms we're willing to use
+    let folder_id = peers_config.state().await.folder_id;
+    let compression = workspace_config.borrow().compression.clone();
+    send_message_to_peer(
+        &mut writer,
+        &PeerMessage::JoinFolder {
+            folder_id,
+            compression,
+        },
+    )
+    .await
+    .map_err(|e| format!("Failed to join folder: {}", e))?;
+
+    // Wait for connection acknowledgment from leader
+    match receive_message_from_peer::<ServerMessage>(&mut reader).await {
+        Ok(ServerMessage::ConnectionAck {
+            peer_id,
+            leader_id,
+            compression,
+        }) => {
+            // Separate connection logs from the rest of the logs for clarity
+            Log::separator();
+            Log::wrench(format!("Connected to: {}", addr), None);
+            Log::info(format!("Peer ID: {}", peer_id), None);
+            Log::info(format!("Sync mode: {}", mode), None);
+            match compression {
+                Some(compression) => Log::info(format!("Compression: {}", compression), None),
+                None => Log::info("Compression: off", None),
+            }
+            Log::separator();
+
+            events.emit(SyncEvent::PeerConnected {
+                peer_id: leader_id.clone(),
+                addr: Some(addr),
+            });
+
+            // Everything exchanged with the leader is limited to the bandwidth of its address,
+            // and what we send compressed as agreed
+            let throttle = bandwidth.connection(addr.ip());
+            let metrics = Arc::clone(events.metrics());
+            // Every byte sent to the leader is counted in the workspace metrics
+            let to_leader = Link {
+                throttle: throttle.upload,
+                compression,
+                metrics: Some(Arc::clone(&metrics)),
+                remote: Some(addr.ip()),
+            };
+
+            // Update peers config to set the leader
+            if let Err(e) = peers_config.set_leader(leader_id.clone()).await {
+                Log::error(format!("Failed to set leader: {}", e), None);
+            }
+
+            // Report this instance in status.json and keep its heartbeat fresh
+            let instance_status = Arc::new(InstanceStatus::new(
+                &abs_storage_path,
+                InstanceRole::Peer,
+                port,
+                mode,
+            ));
+            if let Err(e) = instance_status
+                .set_connected_peers(vec![leader_id.clone()])
+                .await
+            {
+                events.error(format!("Failed to write status: {}", e));
+            }
+            {
+                let instance_status = Arc::clone(&instance_status);
+                let events = events.clone();
+                tasks.spawn(async move {
+                    let mut interval =
+                        tokio::time::interval(Duration::from_secs(STATUS_HEARTBEAT_SECS));
+                    loop {
+                        interval.tick().await;
+                        if let Err(e) = instance_status.save().await {
+                            events.error(format!("Failed to write status: {}", e));
+                        }
+                    }
+                });
+            }
+
+            // ===== INITIAL SYNC (PEER) =====
+            // Send our current sync state to the server immediately after connecting
+            {
+                Log::log("Sending version to server".to_string().bright_cyan(), None);
+
+                let message =
+                    PeerMessage::initial_sync_push(&sync_config, &peers_config, mode).await;
+
+                if let Err(e) =
+                    send_message_over(&mut writer, &message, &to_leader, |_, _| {}).await
+                {
+                    return Err(format!(
+                        "Failed to send initial sync state to server: {}",
+                        e
+                    ));
+                }
+            }
+
+            // ===== FILE WATCHER TASK (PEER) =====
+            // Spawn a background task to watch the workspace directory for changes
+            let ignore_file_events = Arc::new(AtomicBool::new(false));
+
+            // Create a channel for the file watcher to send messages to the main connection handler
+            let (file_change_tx, mut file_change_rx) = mpsc::channel::<PeerMessage>(100);
+
+            {
+                let sync_config = Arc::clone(&sync_config);
+                let abs_workspace_path = abs_workspace_path.clone();
+                let abs_storage_path = abs_storage_path.clone();
+                let workspace_config = workspace_config.clone();
+                let ignore_file_events = Arc::clone(&ignore_file_events);
+                let paused = Arc::clone(&paused);
+                let file_change_tx = file_change_tx.clone();
+                let events = events.clone();
+
+                tasks.spawn(async move {
+                    // Create a tokio channel for async communication
+                    let (tx, mut rx) = mpsc::channel::<notify::Event>(100);
+
+                    // Create a custom event handler that sends to the tokio channel
+                    let metrics = Arc::clone(events.metrics());
+                    let event_handler = move |res: notify::Result<notify::Event>| {
+                        if let Ok(event) = res {
+                            metrics.watcher_event();
+                            // Use blocking_send since notify runs in sync context
+                            let _ = tx.blocking_send(event);
+                        }
+                    };
+
+                    // Create watcher with our custom event handler
+                    let mut watcher = notify::recommended_watcher(event_handler).unwrap();
+                    watcher
+                        .watch(&abs_workspace_path, RecursiveMode::Recursive)
+                        .unwrap();
+
+                    // Loop to handle file system events
+                    loop {
+                        // First event in a burst
+                        let Some(first_event) = rx.recv().await else {
+                            continue;
+                        };
+
+                        // Skip processing if we're currently making programmatic changes
+                        if ignore_file_events.load(Ordering::Relaxed) {
+                            continue;
+                        }
+
+                        // Wait to absorb additional events
+                        let debounce = workspace_config.borrow().debounce();
+                        tokio::time::sleep(debounce).await;
+
+                        // Collect all events that arrived during/after the sleep
+                        let mut fs_events = vec![first_event];
+                        while let Ok(event) = rx.try_recv() {
+                            fs_events.push(event);
+                        }
+
+                        // Group by file path, collecting ALL event kinds for each path
+                        let mut grouped: HashMap<String, Vec<EventKind>> = HashMap::new();
+
+                        for event in fs_events {
+                            for path in event.paths {
+                                if let Some(p) = path.to_str() {
+                                    grouped.entry(p.to_string()).or_default().push(event.kind);
+                                }
+                            }
+                        }
+
+                        let mut files_to_update: HashMap<String, Vec<u8>> = HashMap::new();
+                        let mut paths_to_delete: Vec<String> = Vec::new();
+
+                        // Now handle each file **once** based on event history and current state
+                        for (path, event_kinds) in grouped {
+                            let path_buf = PathBuf::from(&path);
+
+                            // Calculate relative path - skip if path is not within workspace
+                            let relative_path = match path_buf.strip_prefix(&abs_workspace_path) {
+                                Ok(rel) => rel.to_str().unwrap().to_string(),
+                                Err(_) => continue, // Path not within workspace
+                            };
+
+                            // Check actual file system state
+                            let file_exists = path_buf.exists();
+
+                            // Reading a file only raises access events, which reloading would cause itself
+                            let written = event_kinds
+                                .iter()
+                                .any(|kind| !matches!(kind, EventKind::Access(_)));
+
+                            // The workspace config changed, apply the settings that can change while running
+                            let ignore_patterns_changed = written
+                                && path_buf == abs_storage_path.join(WORKSPACE_CONFIG_FILE)
+                                && reload_workspace_config(
+                                    &abs_storage_path,
+                                    &sync_config,
+                                    &workspace_config,
+                                );
+
+                            // The ignore rules changed, reload them and pick up newly un-ignored files
+                            if (written && relative_path == SYNC_IGNORE_FILE)
+                                || ignore_patterns_changed
+                            {
+                                match sync_config.reload_ignore().await {
+                                    Ok(new_files) => files_to_update.extend(new_files),
+                                    Err(e) => events.error(format!(
+                                        "Failed to reload {}: {}",
+                                        SYNC_IGNORE_FILE, e
+                                    )),
+                                }
+                            }
+
+                            // Skip the .synclite directory and anything matched by .syncignore
+                            if sync_config.is_ignored(&relative_path, path_buf.is_dir()) {
+                                continue;
+                            }
+
+                            // Handle Directory Logic
+                            if file_exists && path_buf.is_dir() {
+                                // Check if it's a Create event
+                                let has_create = event_kinds.iter().any(|k| {
+                                    matches!(k, EventKind::Create(_))
+                                        || matches!(
+                                            k,
+                                            EventKind::Modify(notify::event::ModifyKind::Name(
+                                                notify::event::RenameMode::To
+                                            ))
+                                        )
+                                });
+
+                                if has_create {
+                                    // It's a directory creation/move! Scan it recursively.
+                                    let new_files = sync_config
+                                        .scan_and_add_directory(&abs_workspace_path, &relative_path)
+                                        .await;
+                                    files_to_update.extend(new_files);
+                                }
+                                // Skip regular processing for directories
+                                continue;
+                            }
+
+                            // Analyze event history
+                            let has_create = event_kinds
+                                .iter()
+                                .any(|k| matches!(k, EventKind::Create(_)));
+                            let has_remove = event_kinds.iter().any(|k| {
+                                matches!(k, EventKind::Remove(_))
+                                    || matches!(
+                                        k,
+                                        EventKind::Modify(notify::event::ModifyKind::Name(
+                                            notify::event::RenameMode::From
+                                        ))
+                                    )
+                            });
+                            let has_modify = event_kinds
+                                .iter()
+                                .any(|k| matches!(k, EventKind::Modify(_)));
+
+                            // Determine action based on event history and current state
+                            match (file_exists, has_create, has_remove, has_modify) {
+                                // File exists, saw both Create and Remove -> atomic write, treat as modify
+                                (true, true, true, _) => {
+                                    if let Ok(hash) = calculate_file_hash(&path_buf)
+                                        && let Err(e) =
+                                            sync_config.update_file(&relative_path, hash).await
+                                    {
+                                        events.error(format!(
+                                            "Failed to update file {}: {}",
+                                            relative_path, e
+                                        ));
+                                    }
+                                    if let Ok(content) = fs::read(&path_buf) {
+                                        files_to_update.insert(relative_path.clone(), content);
+                                    }
+                                }
+                                // File exists, saw Create but no Remove -> new file
+                                (true, true, false, _) => {
+                                    if let Ok(hash) = calculate_file_hash(&path_buf)
+                                        && let Err(e) = sync_config
+                                            .add_file(
+                                                relative_path.clone(),
+                                                FileEntry {
+                                                    hash: Some(hash),
+                                                    is_deleted: false,
+                                                    last_modified: Utc::now(),
+                                                    deleted_at: None,
+                                                },
+                                            )
+                                            .await
+                                    {
+                                        events.error(format!(
+                                            "Failed to add file {}: {}",
+                                            relative_path, e
+                                        ));
+                                    }
+                                    if let Ok(content) = fs::read(&path_buf) {
+                                        files_to_update.insert(relative_path.clone(), content);
+                                    }
+                                }
+                                // File exists, no Create event -> modification
+                                (true, false, _, true) => {
+                                    if let Ok(hash) = calculate_file_hash(&path_buf)
+                                        && let Err(e) =
+                                            sync_config.update_file(&relative_path, hash).await
+                                    {
+                                        events.error(format!(
+                                            "Failed to update file {}: {}",
+                                            relative_path, e
+                                        ));
+                                    }
+                                    if let Ok(content) = fs::read(&path_buf) {
+                                        files_to_update.insert(relative_path.clone(), content);
+                                    }
+                                }
+                                // File doesn't exist, saw Remove -> delete (could be file or directory)
+                                (false, _, true, _) => {
+                                    // Try recursive delete (handles both files and directories)
+                                    let _ = sync_config
+                                        .delete_directory_recursive(&relative_path)
+                                        .await;
+
+                                    paths_to_delete.push(relative_path.clone());
+                                }
+                                // Any other case -> no action needed
+                                _ => {}
+                            }
+                        }
+
+                        // Local changes are tracked but never leave a receive-only or mirror workspace
+                        if !mode.sends_local_changes()
+                            && (!files_to_update.is_empty() || !paths_to_delete.is_empty())
+                        {
+                            Log::log(
+                                format!(
+                                    "⚠️  {} local changes not propagated ({} mode)",
+                                    files_to_update.len() + paths_to_delete.len(),
+                                    mode
+                                )
+                                .yellow(),
+                                None,
+                            );
+                            continue;
+                        }
+
+                        // While paused changes are only recorded, resuming resyncs with the leader
+                        if paused.load(Ordering::Relaxed)
+                            && (!files_to_update.is_empty() || !paths_to_delete.is_empty())
+                        {
+                            Log::log(
+                                format!(
+                                    "⏸️  Paused, keeping {} local changes until resumed",
+                                    files_to_update.len() + paths_to_delete.len()
+                                )
+                                .yellow(),
+                                None,
+                            );
+                            continue;
+                        }
+
+                        // Send the file updates to the main connection handler via channel
+                        if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
+                            Log::log(
+                                format!(
+                                    "📡 Sending to server: {} files, {} deletions",
+                                    files_to_update.len(),
+                                    paths_to_delete.len()
+                                )
+                                .blue(),
+                                None,
+                            );
+                        }
+
+                        if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
+                            let hashes = sync_config.advertised_hashes(&mut files_to_update).await;
+                            events.queued(&files_to_update, &paths_to_delete);
+                            let _ = file_change_tx
+                                .send(PeerMessage::FileUpdatePush {
+                                    hashes,
+                                    files_to_write: files_to_update,
+                                    paths_to_delete,
+                                })
+                                .await;
+                        }
+                    }
+                });
+            }
+
+            // ===== CONTROL HANDLER (PEER) =====
+            // Answer requests coming from the control socket, sending through the watcher channel
+            {
+                let sync_config = Arc::clone(&sync_config);
+                let peers_config = Arc::clone(&peers_config);
+                let instance_status = Arc::clone(&instance_status);
+                let shutdown = Arc::clone(&shutdown);
+                let paused = Arc::clone(&paused);
+                let file_change_tx = file_change_tx.clone();
+                let events = events.clone();
+                let abs_workspace_path = abs_workspace_path.clone();
+                let workspace_config = workspace_config.clone();
+                let ignore_file_events = Arc::clone(&ignore_file_events);
+
+                tasks.spawn(async move {
+                    while let Some(call) = control_calls.recv().await {
+                        let response = match &call.request {
+                            ControlRequest::Status => ControlResponse::Status {
+                                status: instance_status.state().await,
+                            },
+                            ControlRequest::ListPeers => ControlResponse::Peers {
+                                connected: instance_status.state().await.connected_peers,
+                                known: peers_config.state().await.peers,
+                            },
+                            ControlRequest::Pause => {
+                                paused.store(true, Ordering::Relaxed);
+                                let _ = instance_status.set_paused(true).await;
+                                Log::log("⏸️  Syncing paused".yellow(), None);
+                                ControlResponse::Done {
+                                    message: "Syncing paused".to_string(),
+                                }
+                            }
+                            ControlRequest::Resume | ControlRequest::Rescan => {
+                                let resume = matches!(call.request, ControlRequest::Resume);
+                                if resume {
+                                    paused.store(false, Ordering::Relaxed);
+                                    let _ = instance_status.set_paused(false).await;
+                                    Log::log("▶️  Syncing resumed".green(), None);
+                                }
+
+                                match sync_config.rescan().await {
+                                    Ok((mut files_to_write, paths_to_delete)) => {
+                                        let changes = files_to_write.len() + paths_to_delete.len();
+
+                                        // A full initial sync reconciles both sides after a pause,
+                                        // a plain rescan only sends what changed here
+                                        let message = if resume {
+                                            Some(
+                                                PeerMessage::initial_sync_push(
+                                                    &sync_config,
+                                                    &peers_config,
+                                                    mode,
+                                                )
+                                                .await,
+                                            )
+                                        } else if changes > 0
+                                            && mode.sends_local_changes()
+                                            && !paused.load(Ordering::Relaxed)
+                                        {
+                                            let hashes = sync_config
+                                                .advertised_hashes(&mut files_to_write)
+                                                .await;
+                                            events.queued(&files_to_write, &paths_to_delete);
+                                            Some(PeerMessage::FileUpdatePush {
+                                                hashes,
+                                                files_to_write,
+                                                paths_to_delete,
+                                            })
+                                        } else {
+                                            None
+                                        };
+                                        if let Some(message) = message {
+                                            let _ = file_change_tx.send(message).await;
+                                        }
+
+                                        ControlResponse::Done {
+                                            message: format!(
+                                                "Rescan found {} changes{}",
+                                                changes,
+                                                if resume {
+                                                    ", resyncing with the leader"
+                                                } else {
+                                                    ""
+                                                }
+                                            ),
+                                        }
+                                    }
+                                    Err(e) => ControlResponse::Error { message: e },
+                                }
+                            }
+                            ControlRequest::Repair { paths } => {
+                                // Written through our own state, the watcher mustn't send them back
+                                ignore_file_events.store(true, Ordering::Relaxed);
+                                let repaired = repair_files(
+                                    &sync_config,
+                                    &abs_workspace_path,
+                                    addr,
+                                    &peers_config.state().await,
+                                    paths.clone(),
+                                )
+                                .await;
+                                let ignore_window = workspace_config.borrow().ignore_window();
+                                tokio::time::sleep(ignore_window).await;
+                                ignore_file_events.store(false, Ordering::Relaxed);
+
+                                match repaired {
+                                    Ok(repaired) => {
+                                        Log::log(
+                                            format!("🔧 Repaired {} files", repaired.len()).green(),
+                                            None,
+                                        );
+                                        let message = format!(
+                                            "Repaired {} of {} corrupted files",
+                                            repaired.len(),
+                                            paths.len()
+                                        );
+                                        if repaired.len() == paths.len() {
+                                            ControlResponse::Done { message }
+                                        } else {
+                                            ControlResponse::Error { message }
+                                        }
+                                    }
+                                    Err(e) => ControlResponse::Error { message: e },
+                                }
+                            }
+                            ControlRequest::Revert if !mode.accepts_remote_changes() => {
+                                ControlResponse::Error {
+                                    message: format!(
+                                        "A {} workspace doesn't take files from the leader",
+                                        mode
+                                    ),
+                                }
+                            }
+                            ControlRequest::Revert => {
+                                // Written through our own state, the watcher mustn't send them back
+                                ignore_file_events.store(true, Ordering::Relaxed);
+                                let reverted = revert_files(
+                                    &sync_config,
+                                    &abs_workspace_path,
+                                    addr,
+                                    &peers_config.state().await,
+                                )
+                                .await;
+                                let ignore_window = workspace_config.borrow().ignore_window();
+                                tokio::time::sleep(ignore_window).await;
+                                ignore_file_events.store(false, Ordering::Relaxed);
+
+                                match reverted {
+                                    Ok(reverted) => {
+                                        Log::log(
+                                            format!("↩️  Reverted {} files", reverted.len())
+                                                .green(),
+                                            None,
+                                        );
+                                        ControlResponse::Done {
+                                            message: format!(
+                                                "Reverted {} locally edited files",
+                                                reverted.len()
+                                            ),
+                                        }
+                                    }
+                                    Err(e) => ControlResponse::Error { message: e },
+                                }
+                            }
+                            ControlRequest::DisconnectPeer { .. } => ControlResponse::Error {
+                                message: "Only the leader manages peer connections".to_string(),
+                            },
+                            ControlRequest::Shutdown => {
+                                Log::log("Shutting down".yellow(), None);
+                                call.respond(ControlResponse::Done {
+                                    message: "Shutting down".to_string(),
+                                });
+                                // Let the reply reach the client before stopping
+                                tokio::time::sleep(Duration::from_millis(100)).await;
+                                shutdown.notify_one();
+                                continue;
+                            }
+                        };
+                        call.respond(response);
+                    }
+                });
+            }
+
+            // ===== RECEIVER TASK (PEER) =====
+            // Read messages from the server in a task of its own, so a message still arriving
+            // (slowly when throttled) isn't cut off when another event is handled
+            // Messages waiting to be handled count against the memory budget of the connection
+            let (server_msg_tx, mut server_msg_rx) =
+                mpsc::channel::<Result<(ServerMessage, MemoryReservation), String>>(1);
+            {
+                let download = Link {
+                    throttle: throttle.download.clone(),
+                    compression: None,
+                    metrics: Some(Arc::clone(&metrics)),
+                    remote: Some(addr.ip()),
+                };
+                let budget =
+                    MemoryBudget::new(FrameLimits::for_workspace(&workspace_config.borrow()));
+                let leader_id = leader_id.clone();
+                let events = events.clone();
+
+                tasks.spawn(async move {
+                    loop {
+                        let server_msg = receive_message_limited::<ServerMessage>(
+                            &mut reader,
+                            &download,
+                            &budget,
+                            |received, total| events.receiving(&leader_id, received, total),
+                        )
+                        .await
+                        .map_err(|e| e.to_string());
+                        let failed = server_msg.is_err();
+                        if server_msg_tx.send(server_msg).await.is_err() || failed {
+                            return;
+                        }
+                    }
+                });
+            }
+
+            // ===== MESSAGE HANDLER (PEER) =====
+            // Listen for messages from the server AND file watcher changes
+            let mut re_requests = ReRequests::new();
+            loop {
+                tokio::select! {
+                    // Handle incoming messages from server
+                    server_msg = server_msg_rx.recv() => {
+                        match server_msg.unwrap_or_else(|| Err("connection closed".to_string())) {
+                            Ok((message, _reservation)) => {
+                                // While paused nothing is applied, resuming resyncs with the leader
+                                if paused.load(Ordering::Relaxed)
+                                    && matches!(
+                                        message,
+                                        ServerMessage::InitialSyncPushResponse { .. }
+                                            | ServerMessage::FileUpdatePush { .. }
+                                            | ServerMessage::ResyncRequest
+                                    )
+                                {
+                                    Log::log(
+                                        "⏸️  Paused, ignoring changes from the leader".yellow(),
+                                        None,
+                                    );
+                                    continue;
+                                }
+
+                                match message {
+                                    ServerMessage::PeerListUpdate { peers } => {
+                                        Log::debug("Updated connected peers list", None);
+
+                                        // Update local peers config with full peer list
+                                        if let Err(e) = peers_config.set_peers(peers).await {
+                                            events.error(format!(
+                                                "Failed to update peers config: {}",
+                                                e
+                                            ));
+                                        }
+                                    }
+
+                                    ServerMessage::InitialSyncPushResponse {
+                                        mut files_to_update,
+                                        hashes,
+                                        mut files_to_delete,
+                                        mut files_to_send_back,
+                                    } => {
+                                        Log::log(
+                                    format!(
+                                        "📥 Initial sync response: {} to receive, {} to delete, {} to send",
+                                        files_to_update.len(),
+                                        files_to_delete.len(),
+                                        files_to_send_back.len()
+                                    )
+                                    .blue(),
+                                    None,
+                                );
+
+                                        // Enforce our own sync mode regardless of what the leader asked for
+                                        if !mode.accepts_remote_changes()
+                                            && (!files_to_update.is_empty()
+                                                || !files_to_delete.is_empty())
+                                        {
+                                            Log::log(
+                                                format!(
+                                                    "⚠️  Ignoring {} remote changes ({} mode)",
+                                                    files_to_update.len() + files_to_delete.len(),
+                                                    mode
+                                                )
+                                                .yellow(),
+                                                None,
+                                            );
+                                            files_to_update.clear();
+                                            files_to_delete.clear();
+                                        }
+                                        if !mode.sends_local_changes() {
+                                            files_to_send_back.clear();
+                                        }
+
+                                        // Never write content that doesn't match what the server advertised, ask for it again instead
+                                        let rejected =
+                                            reject_corrupted(&mut files_to_update, &hashes);
+                                        re_requests.received(files_to_update.keys());
+                                        if !rejected.is_empty() {
+                                            Log::log(
+                                                format!(
+                                                    "⚠️  {} files failed the integrity check: {}",
+                                                    rejected.len(),
+                                                    rejected.join(", ")
+                                                )
+                                                .yellow(),
+                                                None,
+                                            );
+
+                                            let paths = re_requests.to_request(rejected);
+                                            if !paths.is_empty()
+                                                && let Err(e) = send_message_over(
+                                                    &mut writer,
+                                                    &PeerMessage::FileRequest { paths },
+                                                    &to_leader,
+                                                    |_, _| {},
+                                                )
+                                                .await
+                                            {
+                                                events.error(format!(
+                                                    "Failed to request files again: {}",
+                                                    e
+                                                ));
+                                            }
+                                        }
+
+                                        // Handle file deletions first
+                                        if !files_to_delete.is_empty() {
+                                            ignore_file_events.store(true, Ordering::Relaxed);
+
+                                            // Unified filesystem + state delete operation
+                                            match sync_config
+                                                .sync_batch_delete_files(
+                                                    &abs_workspace_path,
+                                                    &files_to_delete,
+                                                    None,
+                                                )
+                                                .await
+                                            {
+                                                Ok(deleted) => {
+                                                    events.deleted(&leader_id, &deleted)
+                                                }
+                                                Err(e) => events.error(format!(
+                                                    "Failed to delete files: {}",
+                                                    e
+                                                )),
+                                            }
+                                        }
+
+                                        // Apply server's winning files to our file system
+                                        if !files_to_update.is_empty() {
+                                            ignore_file_events.store(true, Ordering::Relaxed);
+
+                                            // Unified filesystem + state write operation
+                                            match sync_config
+                                                .sync_batch_write_files(
+                                                    &abs_workspace_path,
+                                                    &files_to_update,
+                                                )
+                                                .await
+                                            {
+                                                Ok(written) => events.applied(
+                                                    &leader_id,
+                                                    &files_to_update,
+                                                    &written,
+                                                ),
+                                                Err(e) => events
+                                                    .error(format!("Failed to write files: {}", e)),
+                                            }
+                                        }
+
+                                        // Wait for file watcher events to be processed
+                                        let ignore_window =
+                                            workspace_config.borrow().ignore_window();
+                                        tokio::time::sleep(ignore_window).await;
+                                        ignore_file_events.store(false, Ordering::Relaxed);
+
+                                        let mut our_winning_files: HashMap<String, Vec<u8>> =
+                                            HashMap::new();
+                                        for path in files_to_send_back {
+                                            if sync_config.is_ignored(&path, false) {
+                                                continue;
+                                            }
+
+                                            // path is relative to workspace_path
+                                            // Convert to absolute path for file operations
+                                            let full_path = abs_workspace_path.join(&path);
+                                            match fs::read(&full_path) {
+                                                Ok(content) => {
+                                                    // Store with relative path as key
+                                                    our_winning_files.insert(path, content);
+                                                }
+                                                Err(e) => {
+                                                    events.error(format!(
+                                                        "Failed to read requested file {}: {}",
+                                                        path, e
+                                                    ));
+                                                }
+                                            }
+                                        }
+
+                                        // Send our winning files back to server
+                                        let hashes = sync_config
+                                            .advertised_hashes(&mut our_winning_files)
+                                            .await;
+                                        if !our_winning_files.is_empty() {
+                                            Log::log(
+                                                format!(
+                                                    "📤 Sending {} files to server",
+                                                    our_winning_files.len()
+                                                )
+                                                .blue(),
+                                                None,
+                                            );
+                                            let upload = events
+                                                .start_upload(Some(&leader_id), &our_winning_files);
+                                            let message = PeerMessage::FileUpdatePush {
+                                                hashes,
+                                                files_to_write: our_winning_files,
+                                                paths_to_delete: Vec::new(),
+                                            };
+                                            match send_message_over(
+                                                &mut writer,
+                                                &message,
+                                                &to_leader,
+                                                |sent, total| {
+                                                    events.upload_sent(&upload, sent, total)
+                                                },
+                                            )
+                                            .await
+                                            {
+                                                Ok(()) => events.finish_upload(upload),
+                                                Err(e) => events.error(format!(
+                                                    "Failed to send file content response: {}",
+                                                    e
+                                                )),
+                                            }
+                                        }
+
+                                        // Initial sync is complete, remember when it happened
+                                        if let Err(e) =
+                                            peers_config.set_last_synced(Utc::now()).await
+                                        {
+                                            events.error(format!(
+                                                "Failed to record last sync time: {}",
+                                                e
+                                            ));
+                                        }
+                                        events.emit(SyncEvent::Synced {
+                                            peer_id: leader_id.clone(),
+                                        });
+                                    }
+
+                                    ServerMessage::FileUpdatePush {
+                                        mut files_to_write,
+                                        hashes,
+                                        paths_to_delete,
+                                    } => {
+                                        Log::log(
+                                            format!(
+                                                "📥 Received from server: {} files, {} deletions",
+                                                files_to_write.len(),
+                                                paths_to_delete.len()
+                                            )
+                                            .blue(),
+                                            None,
+                                        );
+
+                                        // A send-only workspace is the source of truth, flag and drop remote changes
+                                        if !mode.accepts_remote_changes() {
+                                            Log::log(
+                                                format!(
+                                                    "⚠️  Ignoring {} remote changes ({} mode)",
+                                                    files_to_write.len() + paths_to_delete.len(),
+                                                    mode
+                                                )
+                                                .yellow(),
+                                                None,
+                                            );
+                                            continue;
+                                        }
+
+                                        // Never write content that doesn't match what the server advertised, ask for it again instead
+                                        let rejected =
+                                            reject_corrupted(&mut files_to_write, &hashes);
+                                        re_requests.received(files_to_write.keys());
+                                        if !rejected.is_empty() {
+                                            Log::log(
+                                                format!(
+                                                    "⚠️  {} files failed the integrity check: {}",
+                                                    rejected.len(),
+                                                    rejected.join(", ")
+                                                )
+                                                .yellow(),
+                                                None,
+                                            );
+
+                                            let paths = re_requests.to_request(rejected);
+                                            if !paths.is_empty()
+                                                && let Err(e) = send_message_over(
+                                                    &mut writer,
+                                                    &PeerMessage::FileRequest { paths },
+                                                    &to_leader,
+                                                    |_, _| {},
+                                                )
+                                                .await
+                                            {
+                                                events.error(format!(
+                                                    "Failed to request files again: {}",
+                                                    e
+                                                ));
+                                            }
+                                        }
+
+                                        // Server is pushing updated files to us
+                                        if !files_to_write.is_empty() {
+                                            ignore_file_events.store(true, Ordering::Relaxed);
+                                            match sync_config
+                                                .sync_batch_write_files(
+                                                    &abs_workspace_path,
+                                                    &files_to_write,
+                                                )
+                                                .await
+                                            {
+                                                Ok(written) => events.applied(
+                                                    &leader_id,
+                                                    &files_to_write,
+                                                    &written,
+                                                ),
+                                                Err(e) => events
+                                                    .error(format!("Failed to write files: {}", e)),
+                                            }
+                                        }
+
+                                        // Handle directory deletions
+                                        if !paths_to_delete.is_empty() {
+                                            ignore_file_events.store(true, Ordering::Relaxed);
+                                            for path in &paths_to_delete {
+                                                // Delete from filesystem and state, keeping ignored files
+                                                let deleted = sync_config
+                                                    .sync_delete_path(&abs_workspace_path, path)
+                                                    .await;
+                                                events.deleted(&leader_id, &deleted);
+                                            }
+                                        }
+
+                                        // Wait for file watcher events to be processed
+                                        let ignore_window =
+                                            workspace_config.borrow().ignore_window();
+                                        tokio::time::sleep(ignore_window).await;
+                                        ignore_file_events.store(false, Ordering::Relaxed);
+
+                                        if let Err(e) =
+                                            peers_config.set_last_synced(Utc::now()).await
+                                        {
+                                            events.error(format!(
+                                                "Failed to record last sync time: {}",
+                                                e
+                                            ));
+                                        }
+                                        events.emit(SyncEvent::Synced {
+                                            peer_id: leader_id.clone(),
+                                        });
+                                    }
+
+                                    ServerMessage::FileRequest { paths } => {
+                                        if let Err(e) = check_syncable_paths(&paths) {
+                                            Log::warning(
+                                                format!("Ignoring file request from server: {}", e),
+                                                None,
+                                            );
+                                            continue;
+                                        }
+
+                                        // The server received corrupted content, send the files again from disk
+                                        let mut files_to_write = sync_config
+                                            .read_tracked_files(&abs_workspace_path, &paths)
+                                            .await;
+                                        let hashes = sync_config
+                                            .advertised_hashes(&mut files_to_write)
+                                            .await;
+
+                                        Log::log(
+                                            format!(
+                                                "🔁 Sending {} files to server again",
+                                                files_to_write.len()
+                                            )
+                                            .blue(),
+                                            None,
+                                        );
+
+                                        let upload =
+                                            events.start_upload(Some(&leader_id), &files_to_write);
+                                        let message = PeerMessage::FileUpdatePush {
+                                            hashes,
+                                            files_to_write,
+                                            paths_to_delete: Vec::new(),
+                                        };
+                                        match send_message_over(
+                                            &mut writer,
+                                            &message,
+                                            &to_leader,
+                                            |sent, total| {
+                                                events.upload_sent(&upload, sent, total)
+                                            },
+                                        )
+                                        .await
+                                        {
+                                            Ok(()) => events.finish_upload(upload),
+                                            Err(e) => events.error(format!(
+                                                "Failed to send requested files: {}",
+                                                e
+                                            )),
+                                        }
+                                    }
+
+                                    ServerMessage::ResyncRequest => {
+                                        Log::log(
+                                            "🔄 Leader asked for a resync".bright_cyan(),
+                                            None,
+                                        );
+                                        let message = PeerMessage::initial_sync_push(
+                                            &sync_config,
+                                            &peers_config,
+                                            mode,
+                                        )
+                                        .await;
+                                        if let Err(e) = send_message_over(
+                                            &mut writer,
+                                            &message,
+                                            &to_leader,
+                                            |_, _| {},
+                                        )
+                                        .await
+                                        {
+                                            events.error(format!(
+                                                "Failed to send sync state to server: {}",
+                                                e
+                                            ));
+                                        }
+                                    }
+
+                                    ServerMessage::ConnectionRejected { reason } => {
+                                        events.emit(SyncEvent::PeerDisconnected { peer_id: leader_id });
+                                        return Err(format!(
+                                            "Leader rejected the connection: {}",
+                                            reason
+                                        ));
+                                    }
+
+                                    _ => {
+                                        Log::debug(
+                                            format!("Received message: {:?}", message),
+                                            None,
+                                        );
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                events.emit(SyncEvent::PeerDisconnected { peer_id: leader_id });
+                                return Err(format!("Connection to leader lost: {}", e));
+                            }
+                        }
+                    }
+                    // Stop when asked through the control socket
+                    _ = shutdown.notified() => return Ok(()),
+                    // Handle file changes from the file watcher
+                    Some(file_change_msg) = file_change_rx.recv() => {
+                        let upload = match &file_change_msg {
+                            PeerMessage::FileUpdatePush { files_to_write, .. } => {
+                                Some(events.start_upload(Some(&leader_id), files_to_write))
+                            }
+                            _ => None,
+                        };
+
+                        // Send file change to server
+                        let progress = |sent, total| {
+                            if let Some(upload) = &upload {
+                                events.upload_sent(upload, sent, total);
+                            }
+                        };
+                        match send_message_over(&mut writer, &file_change_msg, &to_leader, progress)
+                            .await
+                        {
+                            Ok(()) => {
+                                if let Some(upload) = upload {
+                                    events.finish_upload(upload);
+                                }
+                            }
+                            Err(e) => events.error(format!(
+                                "Failed to send file changes to server: {}",
+                                e
+                            )),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(ServerMessage::ConnectionRejected { reason }) => {
+            Err(format!("Leader rejected the connection: {}", reason))
+        }
+        Ok(other_message) => Err(format!(
+            "Unexpected message from leader: {:?}",
+            other_message
+        )),
+        Err(e) => Err(format!(
+            "Failed to receive acknowledgment from leader: {}",
+            e
+        )),
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+
+use crate::{
+    engine::{FileProgress, SyncEvent, TransferDirection},
+    utils::{Log, progress_bars},
+};
+
+/// How often a running transfer is summarised when progress bars can't be drawn
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Transfers that complete quicker than this aren't summarised
+const SUMMARY_MIN_DURATION: Duration = Duration::from_secs(2);
+
+/// Files at least this large get a bar of their own
+const LARGE_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Files moving with one peer, or every peer, in one direction
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TransferKey {
+    peer_id: Option<String>,
+    direction: TransferDirection,
+}
+
+impl TransferKey {
+    fn label(&self) -> String {
+        // Peer IDs are UUIDs, their start is enough to tell peers apart
+        let peer = match &self.peer_id {
+            Some(peer_id) => peer_id.chars().take(8).collect(),
+            None => "every peer".to_string(),
+        };
+        match self.direction {
+            TransferDirection::Upload => format!("📤 to {}", peer),
+            TransferDirection::Download => format!("📥 from {}", peer),
+        }
+    }
+}
+
+/// Latest progress of a transfer, see `SyncEvent::TransferProgress`
+struct Progress {
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+}
+
+impl Progress {
+    fn is_complete(&self) -> bool {
+        self.files_done >= self.files_total && self.bytes_done >= self.bytes_total
+    }
+
+    /// `12/3000 files, 1.2 MiB of 40 MiB`, files are left out while they are unknown
+    fn describe(&self) -> String {
+        let bytes = format!(
+            "{} of {}",
+            HumanBytes(self.bytes_done),
+            HumanBytes(self.bytes_total)
+        );
+        match self.files_total {
+            0 => bytes,
+            files_total => format!("{}/{} files, {}", self.files_done, files_total, bytes),
+        }
+    }
+}
+
+struct Transfer {
+    started: Instant,
+    /// When the first bytes moved and how many, preparing a message doesn't count towards the rate
+    moving_since: Option<(Instant, u64)>,
+    last_summary: Instant,
+    bar: Option<ProgressBar>,
+    /// Path and bar of the large file in flight
+    file_bar: Option<(String, ProgressBar)>,
+}
+
+impl Transfer {
+    /// Bytes per second since the first bytes moved
+    fn rate(&self, bytes_done: u64) -> u64 {
+        match self.moving_since {
+            Some((since, bytes)) => match since.elapsed().as_millis() as u64 {
+                0 => 0,
+                millis => bytes_done.saturating_sub(bytes) * 1000 / millis,
+            },
+            None => 0,
+        }
+    }
+
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            moving_since: None,
+            last_summary: now,
+            bar: None,
+            file_bar: None,
+        }
+    }
+
+    fn clear(self) {
+        for bar in self
+            .bar
+            .iter()
+            .chain(self.file_bar.iter().map(|(_, bar)| bar))
+        {
+            bar.finish_and_clear();
+            progress_bars().remove(bar);
+        }
+    }
+}
+
+/// Shows the transfers of a workspace as progress bars with throughput and time left.
+/// When bars can't be drawn, e.g. stdout isn't a terminal, long transfers are summarised periodically instead.
+pub(crate) struct TransferDisplay {
+    transfers: HashMap<TransferKey, Transfer>,
+    draw_bars: bool,
+}
+
+impl TransferDisplay {
+    pub(crate) fn new() -> Self {
+        Self {
+            transfers: HashMap::new(),
+            draw_bars: !progress_bars().is_hidden(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, event: &SyncEvent) {
+        match event {
+            SyncEvent::TransferProgress {
+                peer_id,
+                direction,
+                files_done,
+                files_total,
+                bytes_done,
+                bytes_total,
+                file,
+            } => {
+                let key = TransferKey {
+                    peer_id: peer_id.clone(),
+                    direction: *direction,
+                };
+                let progress = Progress {
+                    files_done: *files_done,
+                    files_total: *files_total,
+                    bytes_done: *bytes_done,
+                    bytes_total: *bytes_total,
+                };
+                self.progress(key, progress, file.as_ref());
+            }
+            // Whatever was in flight with the peer won't complete
+            SyncEvent::PeerDisconnected { peer_id } => {
+                let abandoned: Vec<TransferKey> = self
+                    .transfers
+                    .keys()
+                    .filter(|key| key.peer_id.as_ref() == Some(peer_id))
+                    .cloned()
+                    .collect();
+                for key in abandoned {
+                    if let Some(transfer) = self.transfers.remove(&key) {
+                        transfer.clear();
+                    }
+                }
+            }
+            SyncEvent::Stopped { .. } => self.clear(),
+            _ => {}
+        }
+    }
+
+    /// Remove every bar, transfers still running are forgotten
+    pub(crate) fn clear(&mut self) {
+        for (_, transfer) in self.transfers.drain() {
+            transfer.clear();
+        }
+    }
+
+    fn progress(&mut self, key: TransferKey, progress: Progress, file: Option<&FileProgress>) {
+        let draw_bars = self.draw_bars;
+        let transfer = self
+            .transfers
+            .entry(key.clone())
+            .or_insert_with(Transfer::new);
+        if transfer.moving_since.is_none() && progress.bytes_done > 0 {
+            transfer.moving_since = Some((Instant::now(), progress.bytes_done));
+        }
+
+        if progress.is_complete() {
+            let elapsed = transfer.started.elapsed();
+            let per_second = transfer.rate(progress.bytes_done);
+            if let Some(transfer) = self.transfers.remove(&key) {
+                transfer.clear();
+            }
+            if elapsed >= SUMMARY_MIN_DURATION {
+                // Everything may have moved at once after a long preparation
+                let throughput = match per_second {
+                    0 => String::new(),
+                    per_second => format!(", {}/s", HumanBytes(per_second)),
+                };
+                Log::success(
+                    format!(
+                        "{}: {} in {}{}",
+                        key.label(),
+                        progress.describe(),
+                        HumanDuration(elapsed),
+                        throughput
+                    ),
+                    None,
+                );
+            }
+            return;
+        }
+
+        if draw_bars {
+            show_bars(&key, transfer, &progress, file);
+        } else if transfer.last_summary.elapsed() >= SUMMARY_INTERVAL {
+            transfer.last_summary = Instant::now();
+            let per_second = transfer.rate(progress.bytes_done);
+            let time_left = match per_second {
+                0 => "unknown".to_string(),
+                per_second => HumanDuration(Duration::from_secs(
+                    progress.bytes_total.saturating_sub(progress.bytes_done) / per_second,
+                ))
+                .to_string(),
+            };
+            Log::info(
+                format!(
+                    "{}: {}, {}/s, {} left",
+                    key.label(),
+                    progress.describe(),
+                    HumanBytes(per_second),
+                    time_left
+                ),
+                None,
+            );
+        }
+    }
+}
+
+/// Update the bar of a transfer, and the one of its file in flight when it is large
+fn show_bars(
+    key: &TransferKey,
+    transfer: &mut Transfer,
+    progress: &Progress,
+    file: Option<&FileProgress>,
+) {
+    let bar = transfer.bar.get_or_insert_with(|| {
+        let style = ProgressStyle::with_template(
+            "{prefix} [{bar:30.cyan/blue}] {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec}, {eta} left {msg}",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> ");
+        progress_bars().add(
+            ProgressBar::new(progress.bytes_total)
+                .with_style(style)
+                .with_prefix(key.label()),
+        )
+    });
+    bar.set_length(progress.bytes_total);
+    bar.set_position(progress.bytes_done);
+    if progress.files_total > 0 {
+        bar.set_message(format!(
+            "({}/{} files)",
+            progress.files_done, progress.files_total
+        ));
+    }
+
+    let large_file = file.filter(|file| file.bytes_total >= LARGE_FILE_BYTES);
+    match (large_file, &transfer.file_bar) {
+        (Some(file), Some((path, file_bar))) if *path == file.path => {
+            file_bar.set_position(file.bytes_done);
+        }
+        (Some(file), _) => {
+            if let Some((_, file_bar)) = transfer.file_bar.take() {
+                file_bar.finish_and_clear();
+                progress_bars().remove(&file_bar);
+            }
+            let style = ProgressStyle::with_template(
+                "   {prefix} [{bar:30.green/white}] {binary_bytes}/{binary_total_bytes}",
+            )
+            .expect("valid progress template")
+            .progress_chars("=> ");
+            let file_bar = progress_bars().insert_after(
+                bar,
+                ProgressBar::new(file.bytes_total)
+                    .with_style(style)
+                    .with_prefix(file.path.clone())
+                    .with_position(file.bytes_done),
+            );
+            transfer.file_bar = Some((file.path.clone(), file_bar));
+        }
+        (None, _) => {
+            if let Some((_, file_bar)) = transfer.file_bar.take() {
+                file_bar.finish_and_clear();
+                progress_bars().remove(&file_bar);
+            }
+        }
+    }
+}
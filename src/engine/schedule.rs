@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use chrono::Local;
+use colored::Colorize;
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    control::{ControlCall, ControlRequest, ControlResponse},
+    models::{WorkspaceConfig, in_no_sync_window},
+    utils::Log,
+};
+
+/// How often the clock is checked against the `no_sync` windows
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Pause syncing when a `no_sync` window of the workspace config starts and resume when it ends,
+/// with the same control requests as `synclite pause` and `synclite resume`.
+/// Pausing or resuming by hand holds until the next window starts or ends.
+pub(super) async fn run_schedule(
+    mut workspace_config: watch::Receiver<WorkspaceConfig>,
+    control: mpsc::Sender<ControlCall>,
+) {
+    let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+    let mut in_window = false;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            changed = workspace_config.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+
+        let now = Local::now().naive_local();
+        if in_no_sync_window(&workspace_config.borrow().no_sync, now) == in_window {
+            continue;
+        }
+
+        let request = match in_window {
+            false => ControlRequest::Pause,
+            true => ControlRequest::Resume,
+        };
+        let (call, response) = ControlCall::new(request);
+        if control.send(call).await.is_err() {
+            return;
+        }
+        match response.await {
+            Ok(ControlResponse::Error { message }) => {
+                // A peer still looking for its leader can't pause yet, try again on the next check
+                Log::debug(format!("Schedule not applied yet: {}", message), None);
+            }
+            Ok(_) => {
+                in_window = !in_window;
+                match in_window {
+                    true => Log::log("🕘 No-sync window started".yellow(), None),
+                    false => Log::log("🕘 No-sync window ended".green(), None),
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
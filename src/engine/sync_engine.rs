@@ -0,0 +1,328 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    sync::{Notify, broadcast},
+    task::JoinHandle,
+};
+
+use crate::{
+    engine::{SyncEvent, SyncEvents, WorkspaceOptions, prepare_workspace, run_workspace},
+    metrics::register_workspace,
+    models::{ApiConfig, InstanceRole, SyncMode, WorkspaceConfig},
+    network::{DEFAULT_BIND, DEFAULT_PORT, FolderRouter},
+    storage::{InitPolicy, InitialiseStorageError, initialise_storage},
+};
+
+/// How long `stop` waits for the engine to wind down before aborting it
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many events a slow subscriber can fall behind before it misses some
+const EVENT_CAPACITY: usize = 1024;
+
+/// How a workspace reaches the other side
+#[derive(Clone)]
+pub enum Transport {
+    /// A leader listens on `bind:port` and advertises itself over mDNS,
+    /// a peer discovers its leader over mDNS
+    Tcp { bind: IpAddr, port: u16 },
+    /// A peer connects straight to this leader
+    Direct(SocketAddr),
+    /// A leader is handed its connections by a router shared with other workspaces, listening on `port`
+    Router { router: FolderRouter, port: u16 },
+}
+
+impl Transport {
+    /// Port the workspace is reported to sync on
+    pub fn port(&self) -> u16 {
+        match self {
+            Transport::Tcp { port, .. } | Transport::Router { port, .. } => *port,
+            Transport::Direct(addr) => addr.port(),
+        }
+    }
+
+    /// Leader a peer connects to without discovering it
+    pub fn leader(&self) -> Option<SocketAddr> {
+        match self {
+            Transport::Direct(addr) => Some(*addr),
+            _ => None,
+        }
+    }
+}
+
+/// Configures a `SyncEngine`. Anything left unset comes from the workspace's `.synclite/config.json`.
+pub struct SyncEngineBuilder {
+    workspace_path: PathBuf,
+    role: InstanceRole,
+    mode: Option<SyncMode>,
+    transport: Option<Transport>,
+    config: Option<WorkspaceConfig>,
+    api_port: Option<u16>,
+    init_policy: InitPolicy,
+}
+
+impl SyncEngineBuilder {
+    /// Serve the workspace as the leader (default) or sync it as a peer
+    pub fn role(mut self, role: InstanceRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn mode(mut self, mode: SyncMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Start with these settings instead of the workspace config file.
+    /// Edits to the file while running still replace them.
+    pub fn config(mut self, config: WorkspaceConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Serve the local HTTP API on this port, even if the workspace config doesn't enable it
+    pub fn api_port(mut self, port: u16) -> Self {
+        self.api_port = Some(port);
+        self
+    }
+
+    /// What `start` does when the workspace has no `.synclite` folder, refuses by default
+    pub fn init_policy(mut self, init_policy: InitPolicy) -> Self {
+        self.init_policy = init_policy;
+        self
+    }
+
+    pub fn build(self) -> Result<SyncEngine, String> {
+        let abs_workspace_path = std::path::absolute(&self.workspace_path)
+            .map_err(|e| format!("Invalid workspace path: {}", e))?;
+
+        match (self.role, &self.transport) {
+            (InstanceRole::Leader, Some(Transport::Direct(_))) => {
+                return Err("A leader can't connect to another leader".to_string());
+            }
+            (InstanceRole::Peer, Some(Transport::Router { .. })) => {
+                return Err("Only leaders can be served through a router".to_string());
+            }
+            _ => {}
+        }
+        if self.role == InstanceRole::Leader && self.mode == Some(SyncMode::Mirror) {
+            return Err("Mirror mode is only available to peers".to_string());
+        }
+
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Ok(SyncEngine {
+            abs_workspace_path,
+            role: self.role,
+            mode: self.mode,
+            transport: self.transport,
+            config: self.config,
+            api_port: self.api_port,
+            init_policy: self.init_policy,
+            events,
+            running: None,
+        })
+    }
+}
+
+/// A running engine
+struct RunningEngine {
+    shutdown: Arc<Notify>,
+    task: JoinHandle<Result<(), String>>,
+}
+
+/// Keeps one workspace in sync with its leader or its peers.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), String> {
+/// use synclite::{engine::SyncEngine, models::InstanceRole};
+///
+/// let mut engine = SyncEngine::builder("./project")
+///     .role(InstanceRole::Peer)
+///     .build()?;
+/// let mut events = engine.subscribe();
+/// engine.start().await?;
+/// while let Ok(event) = events.recv().await {
+///     println!("{:?}", event);
+/// }
+/// engine.stop().await
+/// # }
+/// ```
+pub struct SyncEngine {
+    abs_workspace_path: PathBuf,
+    role: InstanceRole,
+    mode: Option<SyncMode>,
+    transport: Option<Transport>,
+    config: Option<WorkspaceConfig>,
+    api_port: Option<u16>,
+    init_policy: InitPolicy,
+    events: broadcast::Sender<SyncEvent>,
+    running: Option<RunningEngine>,
+}
+
+impl SyncEngine {
+    pub fn builder(workspace_path: impl AsRef<Path>) -> SyncEngineBuilder {
+        SyncEngineBuilder {
+            workspace_path: workspace_path.as_ref().to_path_buf(),
+            role: InstanceRole::Leader,
+            mode: None,
+            transport: None,
+            config: None,
+            api_port: None,
+            init_policy: InitPolicy::Never,
+        }
+    }
+
+    pub fn workspace_path(&self) -> &Path {
+        &self.abs_workspace_path
+    }
+
+    pub fn role(&self) -> InstanceRole {
+        self.role
+    }
+
+    /// Receive the events of the engine, subscribe before `start` to see them all
+    pub fn subscribe(&self) -> broadcast::Receiver<SyncEvent> {
+        self.events.subscribe()
+    }
+
+    /// Whether the engine was started and hasn't stopped yet
+    pub fn is_running(&self) -> bool {
+        self.running
+            .as_ref()
+            .is_some_and(|running| !running.task.is_finished())
+    }
+
+    /// Set up the workspace and start syncing it in the background.
+    /// Returns once the workspace is loaded and, for a leader, accepting connections.
+    pub async fn start(&mut self) -> Result<(), String> {
+        if self.is_running() {
+            return Err("The engine is already running".to_string());
+        }
+
+        if !self.abs_workspace_path.join(".synclite").exists() {
+            initialise_storage(&self.abs_workspace_path, self.init_policy)
+                .map_err(describe_init_error)?;
+        }
+
+        let config = match &self.config {
+            Some(config) => {
+                config.validate()?;
+                config.clone()
+            }
+            None => WorkspaceConfig::load(&self.abs_workspace_path.join(".synclite"))?,
+        };
+        let mode = self.mode.or(config.mode).unwrap_or_default();
+        let transport = self
+            .transport
+            .clone()
+            .unwrap_or_else(|| match config.leader {
+                Some(leader) if self.role == InstanceRole::Peer => Transport::Direct(leader),
+                _ => Transport::Tcp {
+                    bind: config.bind.unwrap_or(DEFAULT_BIND),
+                    port: config.port.unwrap_or(DEFAULT_PORT),
+                },
+            });
+        if self.role == InstanceRole::Leader && mode == SyncMode::Mirror {
+            return Err("Mirror mode is only available to peers".to_string());
+        }
+        let api = match self.api_port {
+            Some(0) => return Err("The API port must be between 1 and 65535".to_string()),
+            Some(port) => Some(ApiConfig {
+                port,
+                ..config.api.clone().unwrap_or_default()
+            }),
+            None => config.api.clone(),
+        };
+
+        let workspace = prepare_workspace(WorkspaceOptions {
+            abs_workspace_path: self.abs_workspace_path.clone(),
+            role: self.role,
+            mode,
+            transport,
+            config,
+            api,
+            events: SyncEvents::new(
+                self.events.clone(),
+                register_workspace(&self.abs_workspace_path),
+            ),
+        })
+        .await?;
+        let shutdown = Arc::clone(&workspace.context.shutdown);
+
+        let events = self.events.clone();
+        let _ = events.send(SyncEvent::Started {
+            role: self.role,
+            mode,
+        });
+        let task = tokio::spawn(async move {
+            let result = run_workspace(workspace).await;
+            let _ = events.send(SyncEvent::Stopped {
+                error: result.as_ref().err().cloned(),
+            });
+            result
+        });
+
+        self.running = Some(RunningEngine { shutdown, task });
+        Ok(())
+    }
+
+    /// Wait until the engine stops on its own, through its control socket or because it failed
+    pub async fn wait(&mut self) -> Result<(), String> {
+        let Some(running) = self.running.take() else {
+            return Ok(());
+        };
+        running
+            .task
+            .await
+            .map_err(|e| format!("Sync engine crashed: {}", e))?
+    }
+
+    /// Stop syncing, aborting the engine if it doesn't wind down in time
+    pub async fn stop(&mut self) -> Result<(), String> {
+        let Some(mut running) = self.running.take() else {
+            return Ok(());
+        };
+        running.shutdown.notify_one();
+
+        match tokio::time::timeout(STOP_TIMEOUT, &mut running.task).await {
+            Ok(result) => result.map_err(|e| format!("Sync engine crashed: {}", e))?,
+            Err(_) => {
+                running.task.abort();
+                let _ = self.events.send(SyncEvent::Stopped { error: None });
+                Ok(())
+            }
+        }
+    }
+}
+
+fn describe_init_error(e: InitialiseStorageError) -> String {
+    match e {
+        InitialiseStorageError::NotInitialised => {
+            "Not a synclite workspace, initialise it first".to_string()
+        }
+        InitialiseStorageError::FailedToCheckConflicts(e) => {
+            format!("Failed to check conflicts: {}", e)
+        }
+        InitialiseStorageError::PromptDirectoryCreation(e) => {
+            format!("Failed to prompt directory creation: {}", e)
+        }
+        InitialiseStorageError::FailedToCreateStorageDirectory(e) => {
+            format!("Failed to create storage directory: {}", e)
+        }
+        InitialiseStorageError::FailedToCreateStorageFiles(e) => {
+            format!("Failed to create storage files: {}", e)
+        }
+        InitialiseStorageError::FailedToAddDirectoryToRegistry(e) => {
+            format!("Failed to add directory to registry: {}", e)
+        }
+    }
+}
@@ -1,9 +1,25 @@
-use std::path::Path;
+use std::{net::SocketAddr, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use clap::error::ErrorKind;
+use colored::{ColoredString, Colorize};
+use serde::Serialize;
 
 use crate::{
-    cli::{Args, ParseArgsError, parse_args},
-    storage::{InitialiseStorageError, initialise_storage},
-    utils::Log,
+    app::{RegisteredWorkspace, cleanup_registry, load_registry, registry_key, remove_directory},
+    cli::{Args, ParseArgsError, WorkspacesAction, parse_args},
+    control::{ControlRequest, ControlResponse, send_control_request},
+    models::{
+        InstanceRole, InstanceStatus, PeersState, SyncConfig, SyncConflict, SyncMode,
+        SyncSnapshots, WORKSPACE_CONFIG_FILE, WorkspaceConfig,
+    },
+    network::{discover_leader, repair_files},
+    storage::{
+        FileVersioning, InitPolicy, InitialiseStorageError, VersioningPolicy, ignore_template,
+        initialise_storage,
+    },
+    sync::SYNC_IGNORE_FILE,
+    utils::{Log, read_json, write_json},
 };
 
 pub fn handle_parse_args() -> Args {
@@ -11,17 +27,25 @@ pub fn handle_parse_args() -> Args {
         Ok(args) => args,
         Err(e) => {
             match e {
-                ParseArgsError::InvalidArguments => {
-                    Log::usage();
-                }
-                ParseArgsError::InvalidCommand(command) => {
-                    Log::error(&format!("Invalid command: {}", command), None);
+                ParseArgsError::Cli(e) => {
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::DisplayHelp
+                            | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+                    ) {
+                        Log::banner();
+                    }
+                    // Prints help and version to stdout with success, usage errors to stderr
+                    e.exit();
                 }
                 ParseArgsError::InvalidPath(path) => {
-                    Log::error(&format!("Invalid path: {}", path), None);
+                    Log::error(format!("Invalid path: {}", path), None);
+                }
+                ParseArgsError::InvalidMode(mode) => {
+                    Log::error(format!("Invalid sync mode: {}", mode), None);
                 }
-                ParseArgsError::InvalidPortNumber(port) => {
-                    Log::error(&format!("Invalid port number: {}", port), None);
+                ParseArgsError::InvalidConfig(e) => {
+                    Log::error(format!("Invalid config: {}", e), None);
                 }
             };
             std::process::exit(1);
@@ -29,28 +53,716 @@ pub fn handle_parse_args() -> Args {
     }
 }
 
-pub fn handle_initialise_storage(abs_workspace_path: &Path) {
-    match initialise_storage(abs_workspace_path) {
+pub fn handle_initialise_storage(abs_workspace_path: &Path, policy: InitPolicy) {
+    match initialise_storage(abs_workspace_path, policy) {
         Ok(_) => {}
         Err(e) => {
             match e {
+                InitialiseStorageError::NotInitialised => {
+                    Log::error(
+                        format!(
+                            "{} is not a synclite workspace, run synclite init {} or pass --yes",
+                            abs_workspace_path.display(),
+                            abs_workspace_path.display()
+                        ),
+                        None,
+                    );
+                }
                 InitialiseStorageError::FailedToCheckConflicts(e) => {
-                    Log::error(&format!("Failed to check conflicts: {}", e), None);
+                    Log::error(format!("Failed to check conflicts: {}", e), None);
                 }
                 InitialiseStorageError::PromptDirectoryCreation(e) => {
-                    Log::error(&format!("Failed to prompt directory creation: {}", e), None);
+                    Log::error(format!("Failed to prompt directory creation: {}", e), None);
                 }
                 InitialiseStorageError::FailedToCreateStorageDirectory(e) => {
-                    Log::error(&format!("Failed to create storage directory: {}", e), None);
+                    Log::error(format!("Failed to create storage directory: {}", e), None);
                 }
                 InitialiseStorageError::FailedToCreateStorageFiles(e) => {
-                    Log::error(&format!("Failed to create storage files: {}", e), None);
+                    Log::error(format!("Failed to create storage files: {}", e), None);
                 }
                 InitialiseStorageError::FailedToAddDirectoryToRegistry(e) => {
-                    Log::error(&format!("Failed to add directory to registry: {}", e), None);
+                    Log::error(format!("Failed to add directory to registry: {}", e), None);
                 }
             };
             std::process::exit(1);
         }
     }
 }
+
+/// Initialise a workspace without starting to sync it, optionally presetting
+/// its folder ID, sync mode and a starter `.syncignore`
+pub fn handle_init(
+    abs_workspace_path: &Path,
+    folder_id: Option<&str>,
+    mode: Option<SyncMode>,
+    ignore_template_name: Option<&str>,
+) {
+    handle_initialise_storage(abs_workspace_path, InitPolicy::Yes);
+
+    let abs_storage_path = abs_workspace_path.join(".synclite");
+    if let Some(folder_id) = folder_id {
+        let peers_path = abs_storage_path.join("peers.json");
+        let result = read_json::<PeersState>(&peers_path).and_then(|mut peers_state| {
+            peers_state.folder_id = Some(folder_id.to_string());
+            write_json(&peers_path, &peers_state)
+        });
+        if let Err(e) = result {
+            Log::error(format!("Failed to update peers.json: {}", e), None);
+            std::process::exit(1);
+        }
+    }
+    if mode.is_some() {
+        let result = WorkspaceConfig::load(&abs_storage_path).and_then(|mut config| {
+            config.mode = mode;
+            config.save(&abs_storage_path)
+        });
+        if let Err(e) = result {
+            Log::error(
+                format!("Failed to update {}: {}", WORKSPACE_CONFIG_FILE, e),
+                None,
+            );
+            std::process::exit(1);
+        }
+    }
+
+    // Never overwrite rules the user already wrote
+    if let Some(name) = ignore_template_name {
+        let ignore_path = abs_workspace_path.join(SYNC_IGNORE_FILE);
+        if ignore_path.exists() {
+            Log::info(
+                format!("{} already exists, leaving it untouched", SYNC_IGNORE_FILE),
+                Some(5),
+            );
+        } else if let Some(template) = ignore_template(name)
+            && let Err(e) = std::fs::write(&ignore_path, template)
+        {
+            Log::error(format!("Failed to write {}: {}", SYNC_IGNORE_FILE, e), None);
+            std::process::exit(1);
+        }
+    }
+
+    Log::newline();
+    Log::success(
+        format!("Initialised {}", abs_workspace_path.display()),
+        None,
+    );
+    if let Some(folder_id) = folder_id {
+        Log::info(format!("Folder ID: {}", folder_id), Some(5));
+    }
+    if let Some(mode) = mode {
+        Log::info(format!("Sync mode: {}", mode), Some(5));
+    }
+    if let Some(name) = ignore_template_name {
+        Log::info(format!("Ignore template: {}", name), Some(5));
+    }
+}
+
+pub fn handle_restore(abs_workspace_path: &Path, file_path: &str, version: Option<&str>) {
+    let abs_storage_path = abs_workspace_path.join(".synclite");
+    if !abs_storage_path.exists() {
+        Log::error(
+            format!(
+                "No .synclite folder found in {}",
+                abs_workspace_path.display()
+            ),
+            None,
+        );
+        std::process::exit(1);
+    }
+
+    // The policy only matters for cleanup, which restoring never triggers
+    let versioning = FileVersioning::new(&abs_storage_path, VersioningPolicy::default());
+
+    let Some(version) = version else {
+        match versioning.list_versions(file_path) {
+            Ok(versions) if versions.is_empty() => {
+                Log::info(format!("No stored versions of {}", file_path), None);
+            }
+            Ok(versions) => {
+                Log::info(format!("Stored versions of {}:", file_path), None);
+                for version in versions {
+                    println!(
+                        "     {}  {}  {} bytes",
+                        version.tag.bright_yellow(),
+                        version
+                            .created
+                            .format("%Y-%m-%d %H:%M:%S UTC")
+                            .to_string()
+                            .dimmed(),
+                        version.size
+                    );
+                }
+            }
+            Err(e) => {
+                Log::error(format!("Failed to list versions: {}", e), None);
+                std::process::exit(1);
+            }
+        }
+        return;
+    };
+
+    match versioning.restore(abs_workspace_path, file_path, version) {
+        Ok(restored) => {
+            Log::success(
+                format!("Restored {} from version {}", file_path, restored.tag),
+                None,
+            );
+            Log::info(
+                "A running instance propagates it to peers, otherwise it is picked up on the next start",
+                Some(3),
+            );
+        }
+        Err(e) => {
+            Log::error(format!("Failed to restore {}: {}", file_path, e), None);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub async fn handle_verify(abs_workspace_path: &Path, repair: bool, leader: Option<SocketAddr>) {
+    let abs_storage_path = abs_workspace_path.join(".synclite");
+    if !abs_storage_path.exists() {
+        Log::error(
+            format!(
+                "No .synclite folder found in {}",
+                abs_workspace_path.display()
+            ),
+            None,
+        );
+        std::process::exit(1);
+    }
+
+    // Load the recorded state without patching it, patching would accept whatever is on disk
+    let sync_config = SyncConfig::new(&abs_storage_path);
+    if let Err(e) = sync_config.load().await {
+        Log::error(e, None);
+        std::process::exit(1);
+    }
+
+    let report = match sync_config.verify().await {
+        Ok(report) => report,
+        Err(e) => {
+            Log::error(format!("Failed to verify workspace: {}", e), None);
+            std::process::exit(1);
+        }
+    };
+
+    if report.is_clean() {
+        Log::success("All files match the recorded state", None);
+        return;
+    }
+
+    let sections = [
+        ("Corrupted", &report.corrupted),
+        ("Modified", &report.modified),
+        ("Missing", &report.missing),
+        ("Untracked", &report.untracked),
+    ];
+    for (title, paths) in sections {
+        if paths.is_empty() {
+            continue;
+        }
+        Log::info(format!("{} ({}):", title, paths.len()), None);
+        for path in paths {
+            println!("     {}", path.bright_yellow());
+        }
+    }
+
+    if report.corrupted.is_empty() {
+        return;
+    }
+
+    if !repair {
+        Log::warning(
+            "Corrupted files would be synced as local edits, run with --repair to fetch them from the leader",
+            None,
+        );
+        std::process::exit(1);
+    }
+
+    // A leader has nobody to fetch healthy copies from
+    if InstanceStatus::read(&abs_storage_path)
+        .is_some_and(|state| state.role == InstanceRole::Leader)
+    {
+        Log::error(
+            "Cannot repair: this workspace is the leader, there is no other copy to repair it from. \
+             Restore the corrupted files from their versions with `synclite restore`",
+            None,
+        );
+        std::process::exit(1);
+    }
+
+    // A running instance owns the state, let it fetch and write the files
+    let request = ControlRequest::Repair {
+        paths: report.corrupted.clone(),
+    };
+    match send_control_request(&abs_storage_path, &request).await {
+        Ok(ControlResponse::Done { message }) => {
+            Log::success(message, None);
+            return;
+        }
+        Ok(ControlResponse::Error { message }) => {
+            Log::error(format!("Cannot repair: {}", message), None);
+            std::process::exit(1);
+        }
+        Ok(other) => {
+            Log::error(format!("Unexpected answer: {:?}", other), None);
+            std::process::exit(1);
+        }
+        // Nothing running, repair the workspace ourselves
+        Err(_) => {}
+    }
+
+    // Fetch healthy copies from the leader instead of propagating the bad ones
+    let addr = match leader {
+        Some(addr) => addr,
+        None => match discover_leader(Some(Duration::from_secs(10))) {
+            Ok(addr) => addr,
+            Err(e) => {
+                Log::error(format!("Cannot repair: {}", e), None);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let peers_state: PeersState =
+        read_json(&abs_storage_path.join("peers.json")).unwrap_or_default();
+    let repaired = match repair_files(
+        &sync_config,
+        abs_workspace_path,
+        addr,
+        &peers_state,
+        report.corrupted.clone(),
+    )
+    .await
+    {
+        Ok(repaired) => repaired,
+        Err(e) => {
+            Log::error(format!("Cannot repair: {}", e), None);
+            std::process::exit(1);
+        }
+    };
+
+    for path in &repaired {
+        Log::success(format!("Repaired {}", path), Some(3));
+    }
+
+    let unrepaired = report.corrupted.len() - repaired.len();
+    if unrepaired > 0 {
+        Log::error(
+            format!("{} corrupted files could not be repaired", unrepaired),
+            None,
+        );
+        std::process::exit(1);
+    }
+}
+
+pub async fn handle_control(abs_workspace_path: &Path, request: &ControlRequest) {
+    let abs_storage_path = abs_workspace_path.join(".synclite");
+
+    match send_control_request(&abs_storage_path, request).await {
+        Ok(ControlResponse::Peers { connected, known }) => {
+            Log::wrench(format!("Connected peers ({}):", connected.len()), None);
+            for peer in &connected {
+                println!("     {}", peer.bright_yellow());
+            }
+            Log::wrench(format!("Known peers ({}):", known.len()), None);
+            for peer in &known {
+                println!("     {}", peer.dimmed());
+            }
+        }
+        Ok(ControlResponse::Status { status }) => {
+            if let Ok(output) = serde_json::to_string_pretty(&status) {
+                println!("{}", output);
+            }
+        }
+        Ok(ControlResponse::Done { message }) => Log::success(message, None),
+        Ok(ControlResponse::Error { message }) | Err(message) => {
+            Log::error(message, None);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Everything `synclite status` reports about a workspace
+#[derive(Serialize)]
+struct WorkspaceStatus {
+    workspace: String,
+    workspace_id: String,
+    role: Option<InstanceRole>,
+    running: bool,
+    paused: bool,
+    pid: Option<u32>,
+    port: Option<u16>,
+    mode: Option<SyncMode>,
+    started_at: Option<DateTime<Utc>>,
+    leader: Option<String>,
+    peers: Vec<String>,
+    connected_peers: Vec<String>,
+    files: usize,
+    tombstones: usize,
+    pending_changes: PendingChanges,
+    last_synced: Vec<LastSynced>,
+    conflicts: Vec<SyncConflict>,
+}
+
+/// Changes on disk not recorded in the state yet, they go out with the next sync
+#[derive(Serialize)]
+struct PendingChanges {
+    /// Whether file contents were rehashed, corrupted files are only found then
+    scanned: bool,
+    modified: Vec<String>,
+    missing: Vec<String>,
+    untracked: Vec<String>,
+    corrupted: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct LastSynced {
+    remote: String,
+    synced_at: DateTime<Utc>,
+}
+
+pub async fn handle_status(abs_workspace_path: &Path, json: bool, scan: bool) {
+    let abs_storage_path = abs_workspace_path.join(".synclite");
+    if !abs_storage_path.exists() {
+        Log::error(
+            format!(
+                "No .synclite folder found in {}",
+                abs_workspace_path.display()
+            ),
+            None,
+        );
+        std::process::exit(1);
+    }
+
+    let sync_config = SyncConfig::new(&abs_storage_path);
+    if let Err(e) = sync_config.load().await {
+        Log::error(e, None);
+        std::process::exit(1);
+    }
+    let peers_state: PeersState =
+        read_json(&abs_storage_path.join("peers.json")).unwrap_or_default();
+    // Prefer the live state of the running instance over its last heartbeat
+    let instance = match send_control_request(&abs_storage_path, &ControlRequest::Status).await {
+        Ok(ControlResponse::Status { status }) => Some(status),
+        _ => InstanceStatus::read(&abs_storage_path),
+    };
+    let running = instance
+        .as_ref()
+        .is_some_and(|instance| instance.is_running());
+
+    let state = sync_config.state().await;
+    let tombstones = state.values().filter(|entry| entry.is_deleted).count();
+
+    // Hashing every file is slow on large workspaces, modification times tell about local edits
+    let report = if scan {
+        sync_config.verify().await
+    } else {
+        sync_config.check().await
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            Log::error(format!("Failed to scan workspace: {}", e), None);
+            std::process::exit(1);
+        }
+    };
+
+    // A leader keeps a snapshot per peer, a peer only knows when it last synced with its leader
+    let mut last_synced: Vec<LastSynced> = SyncSnapshots::new(&abs_storage_path)
+        .list()
+        .into_iter()
+        .map(|(remote, synced_at)| LastSynced { remote, synced_at })
+        .collect();
+    if let (Some(leader), Some(synced_at)) = (&peers_state.leader, peers_state.last_synced) {
+        last_synced.push(LastSynced {
+            remote: leader.clone(),
+            synced_at,
+        });
+    }
+
+    let status = WorkspaceStatus {
+        workspace: abs_workspace_path.display().to_string(),
+        workspace_id: peers_state.workspace_id,
+        role: instance.as_ref().map(|instance| instance.role),
+        running,
+        paused: running && instance.as_ref().is_some_and(|instance| instance.paused),
+        pid: instance.as_ref().filter(|_| running).map(|i| i.pid),
+        port: instance.as_ref().map(|instance| instance.port),
+        mode: instance.as_ref().map(|instance| instance.mode),
+        started_at: instance.as_ref().filter(|_| running).map(|i| i.started_at),
+        leader: peers_state.leader,
+        peers: peers_state.peers,
+        connected_peers: instance
+            .as_ref()
+            .filter(|_| running)
+            .map(|i| i.connected_peers.clone())
+            .unwrap_or_default(),
+        files: state.len() - tombstones,
+        tombstones,
+        pending_changes: PendingChanges {
+            scanned: scan,
+            modified: report.modified,
+            missing: report.missing,
+            untracked: report.untracked,
+            corrupted: report.corrupted,
+        },
+        last_synced,
+        conflicts: instance.map(|i| i.conflicts).unwrap_or_default(),
+    };
+
+    if json {
+        match serde_json::to_string_pretty(&status) {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                Log::error(format!("Failed to serialize status: {}", e), None);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    print_status(&status);
+}
+
+fn print_status(status: &WorkspaceStatus) {
+    let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+
+    Log::wrench(format!("Workspace: {}", status.workspace), None);
+    Log::info(format!("Workspace ID: {}", status.workspace_id), Some(3));
+    Log::info(
+        format!(
+            "Role: {}",
+            or_unknown(status.role.map(|role| format!("{:?}", role).to_lowercase()))
+        ),
+        Some(3),
+    );
+    if status.running {
+        Log::success(
+            format!(
+                "Running (pid {}, port {}, {} mode)",
+                or_unknown(status.pid.map(|pid| pid.to_string())),
+                or_unknown(status.port.map(|port| port.to_string())),
+                or_unknown(status.mode.map(|mode| mode.to_string()))
+            ),
+            Some(3),
+        );
+        if status.paused {
+            Log::warning("Syncing paused, run synclite resume to continue", Some(3));
+        }
+    } else {
+        Log::info("Not running".dimmed(), Some(3));
+    }
+    println!();
+
+    Log::wrench("Network:", None);
+    Log::info(
+        format!("Leader: {}", or_unknown(status.leader.clone())),
+        Some(3),
+    );
+    Log::info(format!("Known peers: {}", status.peers.len()), Some(3));
+    if status.running {
+        Log::info(
+            format!("Connected peers: {}", status.connected_peers.len()),
+            Some(3),
+        );
+        for peer in &status.connected_peers {
+            println!("        {}", peer.bright_yellow());
+        }
+    }
+    for synced in &status.last_synced {
+        Log::info(
+            format!(
+                "Last synced with {}: {}",
+                synced.remote,
+                synced.synced_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            Some(3),
+        );
+    }
+    println!();
+
+    let pending = &status.pending_changes;
+    Log::wrench("Files:", None);
+    Log::info(format!("Tracked: {}", status.files), Some(3));
+    Log::info(format!("Tombstones: {}", status.tombstones), Some(3));
+    Log::info(
+        format!(
+            "Pending changes: {} modified, {} deleted, {} new",
+            pending.modified.len(),
+            pending.missing.len(),
+            pending.untracked.len()
+        ),
+        Some(3),
+    );
+    if !pending.corrupted.is_empty() {
+        Log::warning(
+            format!(
+                "{} corrupted files, run synclite verify for details",
+                pending.corrupted.len()
+            ),
+            Some(3),
+        );
+    }
+    if !pending.scanned {
+        Log::info(
+            "Compared by modification time, run with --scan to also find corrupted files".dimmed(),
+            Some(3),
+        );
+    }
+
+    if !status.conflicts.is_empty() {
+        println!();
+        Log::wrench(format!("Conflicts ({}):", status.conflicts.len()), None);
+        for conflict in &status.conflicts {
+            println!(
+                "     {}  {} kept, with {} at {}",
+                conflict.path.bright_yellow(),
+                conflict.winner,
+                conflict.peer_id,
+                conflict
+                    .detected_at
+                    .format("%Y-%m-%d %H:%M:%S UTC")
+                    .to_string()
+                    .dimmed()
+            );
+        }
+    }
+}
+
+pub fn handle_workspaces(action: &WorkspacesAction) {
+    match action {
+        WorkspacesAction::List => {
+            let registry = load_registry().unwrap_or_else(|e| {
+                Log::error(e, None);
+                std::process::exit(1);
+            });
+            if registry.directories.is_empty() {
+                Log::info("No registered workspaces", None);
+                return;
+            }
+
+            Log::wrench(
+                format!("Registered workspaces ({}):", registry.directories.len()),
+                None,
+            );
+            for workspace in &registry.directories {
+                let started = match (workspace.role, workspace.mode) {
+                    (Some(role), Some(mode)) => {
+                        format!("{} ({})", format!("{:?}", role).to_lowercase(), mode)
+                    }
+                    _ => "never started".to_string(),
+                };
+                println!(
+                    "     {}  {}  {}",
+                    workspace.path.bright_yellow(),
+                    started,
+                    workspace_state(workspace)
+                );
+            }
+        }
+        WorkspacesAction::Remove { path } => match remove_directory(path) {
+            Ok(true) => {
+                Log::success(format!("Unregistered {}", path.display()), None);
+                if InstanceStatus::read(&path.join(".synclite"))
+                    .is_some_and(|instance| instance.is_running())
+                {
+                    Log::warning(
+                        "It is still running, stop it with synclite shutdown",
+                        Some(3),
+                    );
+                }
+            }
+            Ok(false) => {
+                Log::error(format!("{} is not registered", path.display()), None);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                Log::error(e, None);
+                std::process::exit(1);
+            }
+        },
+        WorkspacesAction::Prune => match cleanup_registry() {
+            Ok(removed) if removed.is_empty() => Log::info("Nothing to prune", None),
+            Ok(removed) => {
+                Log::success(format!("Pruned {} workspaces:", removed.len()), None);
+                for path in removed {
+                    println!("     {}", path.dimmed());
+                }
+            }
+            Err(e) => {
+                Log::error(e, None);
+                std::process::exit(1);
+            }
+        },
+        WorkspacesAction::Info { path } => {
+            let registry = load_registry().unwrap_or_else(|e| {
+                Log::error(e, None);
+                std::process::exit(1);
+            });
+            let Some(workspace) = registry.get(&registry_key(path)) else {
+                Log::error(format!("{} is not registered", path.display()), None);
+                std::process::exit(1);
+            };
+
+            let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "unknown".to_string());
+            Log::wrench(format!("Workspace: {}", workspace.path), None);
+            Log::info(
+                format!(
+                    "Role: {}",
+                    or_unknown(
+                        workspace
+                            .role
+                            .map(|role| format!("{:?}", role).to_lowercase())
+                    )
+                ),
+                Some(3),
+            );
+            Log::info(
+                format!(
+                    "Mode: {}",
+                    or_unknown(workspace.mode.map(|mode| mode.to_string()))
+                ),
+                Some(3),
+            );
+            Log::info(
+                format!(
+                    "Port: {}",
+                    or_unknown(workspace.port.map(|port| port.to_string()))
+                ),
+                Some(3),
+            );
+            Log::info(
+                format!("Folder ID: {}", or_unknown(workspace.folder_id.clone())),
+                Some(3),
+            );
+            Log::info(
+                format!(
+                    "Last started: {}",
+                    or_unknown(
+                        workspace
+                            .last_started
+                            .map(|started| started.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    )
+                ),
+                Some(3),
+            );
+            Log::info(format!("State: {}", workspace_state(workspace)), Some(3));
+        }
+    }
+}
+
+/// Whether a registered workspace is running, stopped or gone
+fn workspace_state(workspace: &RegisteredWorkspace) -> ColoredString {
+    let abs_storage_path = Path::new(&workspace.path).join(".synclite");
+    if !abs_storage_path.exists() {
+        return "missing, run synclite workspaces prune".red();
+    }
+    match InstanceStatus::read(&abs_storage_path) {
+        Some(instance) if instance.is_running() => {
+            format!("running (pid {})", instance.pid).green()
+        }
+        _ => "stopped".dimmed(),
+    }
+}
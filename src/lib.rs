@@ -1,7 +1,11 @@
 // Internal Modules
+pub mod api;
 pub mod app;
 pub mod cli;
+pub mod control;
+pub mod engine;
 pub mod handlers;
+pub mod metrics;
 pub mod models;
 pub mod network;
 pub mod storage;
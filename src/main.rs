@@ -1,1252 +1,181 @@
-use std::{
-    collections::HashMap,
-    fs,
-    net::SocketAddr,
-    path::PathBuf,
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-    },
-    time::Duration,
-};
-
-use mdns_sd::{ServiceDaemon, ServiceInfo};
-
-use chrono::Utc;
-
-use notify::{EventKind, RecursiveMode, Watcher};
-
-use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::mpsc,
-};
-
-use colored::Colorize;
+use std::path::Path;
 
 use synclite::{
+    app::get_app_data_dir,
     cli::{Args, Command},
-    handlers::{handle_initialise_storage, handle_parse_args},
-    models::{FileEntry, PeersConfig, SyncConfig},
-    network::{
-        PeerConnectionManager, PeerMessage, ServerMessage, acknowledge_peer, broadcast_peer_list,
-        generate_peer_id, receive_message_from_peer, send_message_to_peer,
+    engine::{SyncEngine, Transport, run_daemon},
+    handlers::{
+        handle_control, handle_init, handle_initialise_storage, handle_parse_args, handle_restore,
+        handle_status, handle_verify, handle_workspaces,
     },
-    sync::{calculate_file_hash, determine_winning_files},
-    utils::{Log, read_json},
+    metrics::start_metrics_server,
+    models::{InstanceRole, SyncMode},
+    network::DEFAULT_BIND,
+    utils::{Log, attach_log_file, init_logging},
 };
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    Log::banner();
-
     let Args {
         command,
         abs_workspace_path,
         port,
+        mode,
+        init_policy,
+        log,
+        metrics_port,
     } = handle_parse_args();
 
-    let abs_storage_path = abs_workspace_path.join(".synclite");
+    if let Err(e) = init_logging(log.filter, log.format) {
+        eprintln!("{}", e);
+    }
 
-    // Initialise storage directory with peers and sync state files
-    handle_initialise_storage(&abs_workspace_path);
+    // Keep machine-readable output clean
+    if !matches!(command, Command::Status { json: true, .. }) {
+        Log::banner();
+    }
 
-    // Initialize sync_config, load the state from the storage directory
-    // and patch with the the state of the physical filesystem
-    let sync_config = Arc::new(SyncConfig::new(&abs_storage_path));
-    sync_config.load().await.unwrap();
-    sync_config.patch().await.unwrap();
+    // Initialising only prepares the workspace, syncing starts with serve or connect
+    if let Command::Init {
+        folder_id,
+        mode,
+        ignore_template,
+    } = &command
+    {
+        handle_init(
+            &abs_workspace_path,
+            folder_id.as_deref(),
+            *mode,
+            ignore_template.as_deref(),
+        );
+        return Ok(());
+    }
 
-    // Initialize peers_config, load the state from the storage directory
-    let peers_config = Arc::new(PeersConfig::new(
-        &abs_storage_path,
-        read_json(&abs_storage_path.join("peers.json")).unwrap(),
-    ));
+    // Restoring works on an existing workspace and doesn't start syncing
+    if let Command::Restore { file_path, version } = &command {
+        handle_restore(&abs_workspace_path, file_path, version.as_deref());
+        return Ok(());
+    }
 
-    match command {
-        Command::Serve => {
-            let leader_id = generate_peer_id();
-            let addr: SocketAddr = format!("0.0.0.0:{}", port).parse().unwrap();
-            let listener = TcpListener::bind(addr).await?;
+    // Status only reads the workspace and the running instance
+    if let Command::Status { json, scan } = command {
+        handle_status(&abs_workspace_path, json, scan).await;
+        return Ok(());
+    }
 
-            // Initialize connection manager
-            let connection_manager = Arc::new(PeerConnectionManager::new());
+    // Control requests act on the instance already running in the workspace
+    if let Command::Control { request } = &command {
+        handle_control(&abs_workspace_path, request).await;
+        return Ok(());
+    }
 
-            // Flag to ignore file watcher events when program is making changes
-            let ignore_file_events = Arc::new(AtomicBool::new(false));
+    // Verifying compares the workspace with the recorded state and doesn't start syncing
+    if let Command::Verify { repair, leader } = command {
+        handle_verify(&abs_workspace_path, repair, leader).await;
+        return Ok(());
+    }
 
-            // Set this peer as the leader
-            if let Err(e) = peers_config.set_leader(leader_id.clone()).await {
-                Log::error(&format!("Failed to set leader: {}", e), None);
-            }
+    // Registry management doesn't touch any single workspace
+    if let Command::Workspaces { action } = &command {
+        handle_workspaces(action);
+        return Ok(());
+    }
 
-            // Separate connection logs from the rest of the logs for clarity
-            println!("\n{}\n", "-=".repeat(40).black().bold());
-            Log::wrench(&format!("Listening on: {}", addr.to_string()), None);
-            Log::info(&format!("Leader ID: {}", leader_id), None);
+    // The daemon syncs every registered workspace instead of the one given
+    if let Command::Daemon { bind } = command {
+        match get_app_data_dir() {
+            Ok(dir) => attach_logs(&dir.join("logs")),
+            Err(e) => Log::warning(format!("Not writing a log file: {}", e), None),
+        }
+        serve_metrics(metrics_port).await;
+        if let Err(e) = run_daemon(bind, port).await {
+            Log::error(e, None);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-            // Publish mDNS service
-            let mdns = ServiceDaemon::new().expect("Failed to create mDNS daemon");
-            let service_type = "_synclite._tcp.local.";
-            let instance_name = format!("SyncLite-{}", leader_id);
-            let host_name = format!("{}.local.", leader_id);
-            let my_ip = local_ip_address::local_ip().unwrap();
-            let service_info = ServiceInfo::new(
-                service_type,
-                &instance_name,
-                &host_name,
-                my_ip.to_string().as_str(),
+    // Initialise storage directory with peers and sync state files
+    handle_initialise_storage(&abs_workspace_path, init_policy);
+    attach_logs(&abs_workspace_path.join(".synclite").join("logs"));
+    serve_metrics(metrics_port).await;
+
+    let (role, transport, api_port) = match command {
+        Command::Connect {
+            leader: Some(leader),
+            api_port,
+        } => (InstanceRole::Peer, Transport::Direct(leader), api_port),
+        Command::Connect {
+            leader: None,
+            api_port,
+        } => (
+            InstanceRole::Peer,
+            Transport::Tcp {
+                bind: DEFAULT_BIND,
                 port,
-                None,
-            )
-            .expect("valid service info");
-
-            mdns.register(service_info)
-                .expect("Failed to register mDNS service");
-            Log::info(&format!("Advertising service: {}", instance_name), None);
-
-            println!("\n{}\n", "-=".repeat(40).black().bold());
-
-            // ===== FILE WATCHER TASK (SERVER) =====
-            // Spawn a background task to watch the workspace directory for changes
-            // This runs independently of the connection handling
-            {
-                let sync_config = Arc::clone(&sync_config);
-                let connection_manager = Arc::clone(&connection_manager);
-                let abs_workspace_path = abs_workspace_path.clone();
-                let ignore_file_events = Arc::clone(&ignore_file_events);
-
-                tokio::spawn(async move {
-                    // Create a tokio channel for async communication
-                    let (tx, mut rx) = mpsc::channel::<notify::Event>(100);
-
-                    // Create a custom event handler that sends to the tokio channel
-                    let event_handler = move |res: notify::Result<notify::Event>| {
-                        if let Ok(event) = res {
-                            // Use blocking_send since notify runs in sync context
-                            let _ = tx.blocking_send(event);
-                        }
-                    };
-
-                    // Create watcher with our custom event handler
-                    let mut watcher = notify::recommended_watcher(event_handler).unwrap();
-                    watcher
-                        .watch(&abs_workspace_path, RecursiveMode::Recursive)
-                        .unwrap();
-
-                    // Loop to handle file system events
-                    loop {
-                        // First event in a burst
-                        let Some(first_event) = rx.recv().await else {
-                            continue;
-                        };
-
-                        // Skip processing if we're currently making programmatic changes
-                        if ignore_file_events.load(Ordering::Relaxed) {
-                            continue;
-                        }
-
-                        // Wait to absorb additional events
-                        tokio::time::sleep(Duration::from_millis(150)).await;
-
-                        // Collect all events that arrived during/after the sleep
-                        let mut events = vec![first_event];
-                        while let Ok(event) = rx.try_recv() {
-                            events.push(event);
-                        }
-
-                        // Group by file path, collecting ALL event kinds for each path
-                        let mut grouped: HashMap<String, Vec<EventKind>> = HashMap::new();
-
-                        for event in events {
-                            for path in event.paths {
-                                if let Some(p) = path.to_str() {
-                                    grouped
-                                        .entry(p.to_string())
-                                        .or_insert_with(Vec::new)
-                                        .push(event.kind.clone());
-                                }
-                            }
-                        }
-
-                        let mut files_to_update: HashMap<String, Vec<u8>> = HashMap::new();
-                        let mut paths_to_delete: Vec<String> = Vec::new();
-
-                        // Now handle each file **once** based on event history and current state
-                        for (path, event_kinds) in grouped {
-                            let path_buf = PathBuf::from(&path);
-
-                            // Skip .synclite directory
-                            let is_synclite_dir = path.contains(".synclite");
-                            if is_synclite_dir {
-                                continue;
-                            }
-
-                            // Calculate relative path - skip if path is not within workspace
-                            let relative_path = match path_buf.strip_prefix(&abs_workspace_path) {
-                                Ok(rel) => rel.to_str().unwrap().to_string(),
-                                Err(_) => continue, // Path not within workspace
-                            };
-
-                            // Check actual file system state
-                            let file_exists = path_buf.exists();
-
-                            // Handle Directory Logic
-                            if file_exists && path_buf.is_dir() {
-                                // Check if it's a Create event (which happens on directory move/copy)
-                                let has_create = event_kinds.iter().any(|k| {
-                                    matches!(k, EventKind::Create(_))
-                                        || matches!(
-                                            k,
-                                            EventKind::Modify(notify::event::ModifyKind::Name(
-                                                notify::event::RenameMode::To
-                                            ))
-                                        )
-                                });
-
-                                if has_create {
-                                    // It's a directory creation/move! Scan it recursively.
-                                    let new_files = sync_config
-                                        .scan_and_add_directory(&abs_workspace_path, &relative_path)
-                                        .await;
-                                    files_to_update.extend(new_files);
-                                }
-                                // Skip regular processing for directories
-                                continue;
-                            }
-
-                            // Analyze event history
-                            let has_create = event_kinds
-                                .iter()
-                                .any(|k| matches!(k, EventKind::Create(_)));
-                            let has_remove = event_kinds.iter().any(|k| {
-                                matches!(k, EventKind::Remove(_))
-                                    || matches!(
-                                        k,
-                                        EventKind::Modify(notify::event::ModifyKind::Name(
-                                            notify::event::RenameMode::From
-                                        ))
-                                    )
-                            });
-                            let has_modify = event_kinds
-                                .iter()
-                                .any(|k| matches!(k, EventKind::Modify(_)));
-
-                            // Determine action based on event history and current state
-                            match (file_exists, has_create, has_remove, has_modify) {
-                                // File exists, saw both Create and Remove -> atomic write, treat as modify
-                                (true, true, true, _) => {
-                                    if let Ok(hash) = calculate_file_hash(&path_buf) {
-                                        if let Err(e) =
-                                            sync_config.update_file(&relative_path, hash).await
-                                        {
-                                            Log::log(
-                                                &format!(
-                                                    "Failed to update file {}: {}",
-                                                    relative_path, e
-                                                )
-                                                .red(),
-                                                None,
-                                            );
-                                        }
-                                    }
-                                    files_to_update.insert(
-                                        relative_path.clone(),
-                                        fs::read(&path_buf).unwrap(),
-                                    );
-                                }
-                                // File exists, saw Create but no Remove -> new file
-                                (true, true, false, _) => {
-                                    if let Err(e) = sync_config
-                                        .add_file(
-                                            relative_path.clone(),
-                                            FileEntry {
-                                                hash: Some(calculate_file_hash(&path_buf).unwrap()),
-                                                is_deleted: false,
-                                                last_modified: Utc::now(),
-                                            },
-                                        )
-                                        .await
-                                    {
-                                        Log::log(
-                                            &format!("Failed to add file {}: {}", relative_path, e)
-                                                .red(),
-                                            None,
-                                        );
-                                    }
-                                    files_to_update.insert(
-                                        relative_path.clone(),
-                                        fs::read(&path_buf).unwrap(),
-                                    );
-                                }
-                                // File exists, no Create event -> modification
-                                (true, false, _, true) => {
-                                    if let Ok(hash) = calculate_file_hash(&path_buf) {
-                                        if let Err(e) =
-                                            sync_config.update_file(&relative_path, hash).await
-                                        {
-                                            Log::log(
-                                                &format!(
-                                                    "Failed to update file {}: {}",
-                                                    relative_path, e
-                                                )
-                                                .red(),
-                                                None,
-                                            );
-                                        }
-                                    }
-                                    files_to_update.insert(
-                                        relative_path.clone(),
-                                        fs::read(&path_buf).unwrap(),
-                                    );
-                                }
-                                // File doesn't exist, saw Remove -> delete (could be file or directory)
-                                (false, _, true, _) => {
-                                    // Try recursive delete (handles both files and directories)
-                                    let _ = sync_config
-                                        .delete_directory_recursive(&relative_path)
-                                        .await;
-
-                                    paths_to_delete.push(relative_path.clone());
-                                }
-                                // Any other case -> no action needed
-                                _ => {}
-                            }
-                        }
-                        // Broadcast the file updates to all peers
-                        if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
-                            Log::log(
-                                &format!(
-                                    "📡 Broadcasting {} files and {} deletions to peers",
-                                    files_to_update.len(),
-                                    paths_to_delete.len()
-                                )
-                                .blue(),
-                                None,
-                            );
-                        }
-
-                        if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
-                            connection_manager
-                                .broadcast_message(&ServerMessage::FileUpdatePush {
-                                    files_to_write: files_to_update,
-                                    paths_to_delete,
-                                })
-                                .await;
-                        }
-                    }
-                });
-            }
-
-            // ===== CONNECTION HANDLER (SERVER) =====
-            while let Ok((stream, peer_addr)) = listener.accept().await {
-                let peers_config = Arc::clone(&peers_config);
-                let sync_config = Arc::clone(&sync_config);
-                let connection_manager = Arc::clone(&connection_manager);
-                let leader_id = leader_id.clone();
-                let abs_workspace_path = abs_workspace_path.clone();
-                let ignore_file_events = Arc::clone(&ignore_file_events);
-
-                tokio::spawn(async move {
-                    let (mut reader, writer) = stream.into_split();
-
-                    // Generate peer ID
-                    let peer_id = generate_peer_id();
-                    Log::log(
-                        &format!("New peer connecting: {} ({})", peer_id, peer_addr).bright_cyan(),
-                        None,
-                    );
-
-                    // Add peer to connection manager
-                    connection_manager
-                        .add_connection(peer_id.clone(), writer)
-                        .await;
-
-                    // Acknowledge peer connection
-                    if let Err(e) = acknowledge_peer(
-                        Arc::clone(&connection_manager),
-                        peer_id.clone(),
-                        leader_id.clone(),
-                    )
-                    .await
-                    {
-                        Log::log(&format!("Failed to acknowledge peer: {}", e).red(), None);
-                    }
-
-                    // Add peer to peers config
-                    if let Err(e) = peers_config.add_peer(peer_id.clone()).await {
-                        Log::log(&format!("Failed to add peer to config: {}", e).red(), None);
-                    }
-
-                    // Broadcast peer config to all other peers
-                    if let Err(failed_peers) = broadcast_peer_list(
-                        Arc::clone(&connection_manager),
-                        peers_config.state().await.peers.clone(),
-                    )
-                    .await
-                    {
-                        Log::log(
-                            &format!("Failed to broadcast peer list to peers: {:?}", failed_peers)
-                                .red(),
-                            None,
-                        );
-                    }
-
-                    // ===== MESSAGE HANDLER (SERVER) =====
-                    // Handle incoming messages from this peer
-                    loop {
-                        match receive_message_from_peer::<PeerMessage>(&mut reader).await {
-                            Ok(message) => {
-                                match message {
-                                    // STEP 1: Peer sends their version
-                                    PeerMessage::InitialSyncPush {
-                                        sync_state: peer_sync_state,
-                                    } => {
-                                        Log::log(
-                                            &format!("📥 Received initial sync from {}", peer_id)
-                                                .blue(),
-                                            None,
-                                        );
-
-                                        // STEP 2: Check winning files for both sides
-                                        let server_sync_state = sync_config.state().await;
-                                        let (
-                                            our_winning_files,
-                                            their_winning_files,
-                                            files_to_delete_from_server,
-                                            files_to_delete_from_peer,
-                                        ) = determine_winning_files(
-                                            &server_sync_state,
-                                            &peer_sync_state,
-                                        );
-
-                                        Log::log(
-                                            &format!(
-                                                "Sync result: {} to send, {} to receive, {} to delete locally, {} to delete remotely",
-                                                our_winning_files.len(),
-                                                their_winning_files.len(),
-                                                files_to_delete_from_server.len(),
-                                                files_to_delete_from_peer.len()
-                                            )
-                                            .blue(),
-                                            None,
-                                        );
-
-                                        // Handle file deletions first
-                                        if !files_to_delete_from_server.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-
-                                            for path in &files_to_delete_from_server {
-                                                Log::log(
-                                                    &format!("  🗑️  Deleting: {}", path).red(),
-                                                    None,
-                                                );
-                                            }
-
-                                            // Unified filesystem + state delete operation
-                                            if let Err(e) = sync_config
-                                                .sync_batch_delete_files(
-                                                    &abs_workspace_path,
-                                                    &files_to_delete_from_server,
-                                                    Some(&peer_sync_state),
-                                                )
-                                                .await
-                                            {
-                                                Log::log(
-                                                    &format!("Failed to delete files: {}", e).red(),
-                                                    None,
-                                                );
-                                            }
-
-                                            // Small delay to ensure file watcher events are processed
-                                            tokio::time::sleep(Duration::from_millis(100)).await;
-                                            ignore_file_events.store(false, Ordering::Relaxed);
-                                        }
-
-                                        // STEP 3: Send our winning files with content and request their winning files
-                                        if !our_winning_files.is_empty()
-                                            || !their_winning_files.is_empty()
-                                        {
-                                            // Read content of our winning files (paths are relative to workspace)
-                                            let mut my_winning_files_with_content: HashMap<
-                                                String,
-                                                Vec<u8>,
-                                            > = HashMap::new();
-                                            for file_path in &our_winning_files {
-                                                // Convert relative path to absolute path for file operations
-                                                let full_path = abs_workspace_path.join(file_path);
-                                                match fs::read(&full_path) {
-                                                    Ok(content) => {
-                                                        // Store with relative path as key
-                                                        my_winning_files_with_content
-                                                            .insert(file_path.clone(), content);
-                                                    }
-                                                    Err(e) => {
-                                                        Log::log(
-                                                            &format!("Failed to read workspace file {}: {}", file_path, e).red(),
-                                                            None,
-                                                        );
-                                                    }
-                                                }
-                                            }
-
-                                            if !my_winning_files_with_content.is_empty() {
-                                                Log::log(
-                                                    &format!(
-                                                        "Sending {} winning files to peer: {}",
-                                                        my_winning_files_with_content.len(),
-                                                        peer_id
-                                                    )
-                                                    .blue(),
-                                                    None,
-                                                );
-                                            }
-
-                                            if !their_winning_files.is_empty() {
-                                                Log::log(
-                                                    &format!(
-                                                        "Requesting {} winning files from peer: {}",
-                                                        their_winning_files.len(),
-                                                        peer_id
-                                                    )
-                                                    .blue(),
-                                                    None,
-                                                );
-                                            }
-
-                                            if let Err(e) = connection_manager
-                                                .send_to_peer(
-                                                    &peer_id,
-                                                    &ServerMessage::InitialSyncPushResponse {
-                                                        files_to_update:
-                                                            my_winning_files_with_content,
-                                                        files_to_delete: files_to_delete_from_peer,
-                                                        files_to_send_back: their_winning_files,
-                                                    },
-                                                )
-                                                .await
-                                            {
-                                                Log::log(
-                                                    &format!("Failed to send initial sync push response to peer {}: {}", peer_id, e).red(),
-                                                    None,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    PeerMessage::FileUpdatePush {
-                                        files_to_write,
-                                        paths_to_delete,
-                                    } => {
-                                        Log::log(
-                                            &format!(
-                                                "📥 Received from {}: {} files, {} deletions",
-                                                peer_id,
-                                                files_to_write.len(),
-                                                paths_to_delete.len()
-                                            )
-                                            .blue(),
-                                            None,
-                                        );
-
-                                        // Handle directory deletions
-                                        if !paths_to_delete.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-                                            for path in &paths_to_delete {
-                                                Log::log(
-                                                    &format!("  🗑️  Deleting: {}", path).red(),
-                                                    None,
-                                                );
-
-                                                // Recursively delete files in this directory
-                                                let _ = sync_config
-                                                    .delete_directory_recursive(path)
-                                                    .await;
-
-                                                // Delete directory from filesystem
-                                                let path_buf =
-                                                    PathBuf::from(&abs_workspace_path).join(path);
-                                                if path_buf.is_dir() {
-                                                    let _ = fs::remove_dir_all(&path_buf);
-                                                } else {
-                                                    let _ = fs::remove_file(&path_buf);
-                                                }
-                                            }
-                                        }
-
-                                        if !files_to_write.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-                                            for (path, _) in &files_to_write {
-                                                Log::log(
-                                                    &format!("  ✨ Creating/Updating: {}", path)
-                                                        .green(),
-                                                    None,
-                                                );
-                                            }
-
-                                            if let Err(e) = sync_config
-                                                .sync_batch_write_files(
-                                                    &abs_workspace_path,
-                                                    &files_to_write,
-                                                )
-                                                .await
-                                            {
-                                                Log::log(
-                                                    &format!("Failed to write files: {}", e).red(),
-                                                    None,
-                                                );
-                                            }
-                                        }
-
-                                        // Small delay to ensure file watcher events are processed
-                                        tokio::time::sleep(Duration::from_millis(100)).await;
-                                        ignore_file_events.store(false, Ordering::Relaxed);
-
-                                        // Broadcast to other peers
-                                        if !files_to_write.is_empty() || !paths_to_delete.is_empty()
-                                        {
-                                            Log::log(
-                                                &format!("📡 Forwarding to other peers").blue(),
-                                                None,
-                                            );
-                                        }
-
-                                        let failed_peers = connection_manager
-                                            .broadcast_except(
-                                                &ServerMessage::FileUpdatePush {
-                                                    files_to_write: files_to_write.clone(),
-                                                    paths_to_delete,
-                                                },
-                                                vec![peer_id.clone()],
-                                            )
-                                            .await;
-
-                                        if !failed_peers.is_empty() {
-                                            Log::log(
-                                                &format!(
-                                                    "Failed to broadcast file updates to {} peers",
-                                                    failed_peers.len()
-                                                )
-                                                .red(),
-                                                None,
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                    }
-
-                    // Remove peer from connection manager and peers config when connection is lost
-                    Log::log(&format!("Peer {} disconnected", peer_id).red(), None);
+            },
+            api_port,
+        ),
+        Command::Serve { bind, api_port } => (
+            InstanceRole::Leader,
+            Transport::Tcp { bind, port },
+            api_port,
+        ),
+        _ => unreachable!("every other command returned early"),
+    };
+
+    // Sync until shut down through the control socket
+    let result = sync_workspace(&abs_workspace_path, role, mode, transport, api_port).await;
+    if let Err(e) = result {
+        Log::error(e, None);
+        std::process::exit(1);
+    }
 
-                    // Remove peer from connection manager
-                    connection_manager.remove_connection(&peer_id).await;
+    Ok(())
+}
 
-                    // Remove peer from peers config
-                    if let Err(e) = peers_config.remove_peer(&peer_id).await {
-                        Log::log(
-                            &format!("Failed to remove peer {} from config: {}", peer_id, e)
-                                .bright_red()
-                                .bold(),
-                            None,
-                        );
-                    }
+/// Keep a log file in this directory, running without one if it can't be written
+fn attach_logs(dir: &Path) {
+    if let Err(e) = attach_log_file(dir) {
+        Log::warning(format!("Not writing a log file: {}", e), None);
+    }
+}
 
-                    // Broadcast peer config to all other peers
-                    if let Err(failed_peers) = broadcast_peer_list(
-                        connection_manager,
-                        peers_config.state().await.peers.clone(),
-                    )
-                    .await
-                    {
-                        Log::log(
-                            &format!("Failed to broadcast peer list to peers: {:?}", failed_peers)
-                                .red(),
-                            None,
-                        );
-                    }
-                });
-            }
+/// Serve the metrics of every workspace of the process in the background, if asked to
+async fn serve_metrics(port: Option<u16>) {
+    let Some(port) = port else {
+        return;
+    };
+    match start_metrics_server(port).await {
+        Ok(server) => {
+            tokio::spawn(server);
+            Log::info(
+                format!("Metrics on http://127.0.0.1:{}/metrics", port),
+                None,
+            );
         }
-        Command::Connect => {
-            // Discover mDNS service
-            let mdns = ServiceDaemon::new().expect("Failed to create mDNS daemon");
-            let service_type = "_synclite._tcp.local.";
-            let receiver = mdns.browse(service_type).expect("Failed to browse");
-
-            Log::info("Browsing for SyncLite servers...", None);
-
-            let addr = loop {
-                match receiver.recv() {
-                    Ok(mdns_sd::ServiceEvent::ServiceResolved(info)) => {
-                        Log::info(
-                            &format!("Resolved service: {}", info.get_fullname()).green(),
-                            None,
-                        );
-                        if let Some(ip) = info.get_addresses().iter().next() {
-                            let ip_addr: std::net::IpAddr = ip.to_string().parse().unwrap();
-                            let addr = SocketAddr::new(ip_addr, info.get_port());
-                            Log::info(&format!("Found server at: {}", addr).green(), None);
-                            break addr;
-                        }
-                    }
-                    _ => {}
-                }
-            };
-
-            let Ok(stream) = TcpStream::connect(addr).await else {
-                Log::error(&format!("Failed to connect to: {}", addr.to_string()), None);
-                std::process::exit(1);
-            };
-
-            let (mut reader, mut writer) = stream.into_split();
-
-            // Wait for connection acknowledgment from leader
-            match receive_message_from_peer::<ServerMessage>(&mut reader).await {
-                Ok(ServerMessage::ConnectionAck { peer_id, leader_id }) => {
-                    // Separate connection logs from the rest of the logs for clarity
-                    println!("\n{}\n", "-=".repeat(40).black().bold());
-                    Log::wrench(&format!("Connected to: {}", addr.to_string()), None);
-                    Log::info(&format!("Peer ID: {}", peer_id), None);
-                    println!("\n{}\n", "-=".repeat(40).black().bold());
-
-                    // Update peers config to set the leader
-                    if let Err(e) = peers_config.set_leader(leader_id).await {
-                        Log::error(&format!("Failed to set leader: {}", e), None);
-                    }
-
-                    // ===== INITIAL SYNC (PEER) =====
-                    // Send our current sync state to the server immediately after connecting
-                    {
-                        Log::log(&format!("Sending version to server").bright_cyan(), None);
-                        let sync_state = sync_config.state().await;
-                        let message = PeerMessage::InitialSyncPush { sync_state };
-
-                        if let Err(e) = send_message_to_peer(&mut writer, &message).await {
-                            Log::log(
-                                &format!("Failed to send initial sync state to server: {}", e)
-                                    .red()
-                                    .bold(),
-                                None,
-                            );
-
-                            std::process::exit(1);
-                        }
-                    }
-
-                    // ===== FILE WATCHER TASK (PEER) =====
-                    // Spawn a background task to watch the workspace directory for changes
-                    let ignore_file_events = Arc::new(AtomicBool::new(false));
-
-                    // Create a channel for the file watcher to send messages to the main connection handler
-                    let (file_change_tx, mut file_change_rx) = mpsc::channel::<PeerMessage>(100);
-
-                    {
-                        let sync_config = Arc::clone(&sync_config);
-                        let abs_workspace_path = abs_workspace_path.clone();
-                        let ignore_file_events = Arc::clone(&ignore_file_events);
-
-                        tokio::spawn(async move {
-                            // Create a tokio channel for async communication
-                            let (tx, mut rx) = mpsc::channel::<notify::Event>(100);
-
-                            // Create a custom event handler that sends to the tokio channel
-                            let event_handler = move |res: notify::Result<notify::Event>| {
-                                if let Ok(event) = res {
-                                    // Use blocking_send since notify runs in sync context
-                                    let _ = tx.blocking_send(event);
-                                }
-                            };
-
-                            // Create watcher with our custom event handler
-                            let mut watcher = notify::recommended_watcher(event_handler).unwrap();
-                            watcher
-                                .watch(&abs_workspace_path, RecursiveMode::Recursive)
-                                .unwrap();
-
-                            // Loop to handle file system events
-                            loop {
-                                // First event in a burst
-                                let Some(first_event) = rx.recv().await else {
-                                    continue;
-                                };
-
-                                // Skip processing if we're currently making programmatic changes
-                                if ignore_file_events.load(Ordering::Relaxed) {
-                                    continue;
-                                }
-
-                                // Wait to absorb additional events
-                                tokio::time::sleep(Duration::from_millis(150)).await;
-
-                                // Collect all events that arrived during/after the sleep
-                                let mut events = vec![first_event];
-                                while let Ok(event) = rx.try_recv() {
-                                    events.push(event);
-                                }
-
-                                // Group by file path, collecting ALL event kinds for each path
-                                let mut grouped: HashMap<String, Vec<EventKind>> = HashMap::new();
-
-                                for event in events {
-                                    for path in event.paths {
-                                        if let Some(p) = path.to_str() {
-                                            grouped
-                                                .entry(p.to_string())
-                                                .or_insert_with(Vec::new)
-                                                .push(event.kind.clone());
-                                        }
-                                    }
-                                }
-
-                                let mut files_to_update: HashMap<String, Vec<u8>> = HashMap::new();
-                                let mut paths_to_delete: Vec<String> = Vec::new();
-
-                                // Now handle each file **once** based on event history and current state
-                                for (path, event_kinds) in grouped {
-                                    let path_buf = PathBuf::from(&path);
-
-                                    // Skip .synclite directory
-                                    let is_synclite_dir = path.contains(".synclite");
-                                    if is_synclite_dir {
-                                        continue;
-                                    }
-
-                                    // Skip directories
-                                    // Calculate relative path - skip if path is not within workspace
-                                    let relative_path =
-                                        match path_buf.strip_prefix(&abs_workspace_path) {
-                                            Ok(rel) => rel.to_str().unwrap().to_string(),
-                                            Err(_) => continue, // Path not within workspace
-                                        };
-
-                                    // Check actual file system state
-                                    let file_exists = path_buf.exists();
-
-                                    // Handle Directory Logic
-                                    if file_exists && path_buf.is_dir() {
-                                        // Check if it's a Create event
-                                        let has_create = event_kinds.iter().any(|k| {
-                                            matches!(k, EventKind::Create(_))
-                                                || matches!(
-                                                    k,
-                                                    EventKind::Modify(
-                                                        notify::event::ModifyKind::Name(
-                                                            notify::event::RenameMode::To
-                                                        )
-                                                    )
-                                                )
-                                        });
-
-                                        if has_create {
-                                            // It's a directory creation/move! Scan it recursively.
-                                            let new_files = sync_config
-                                                .scan_and_add_directory(
-                                                    &abs_workspace_path,
-                                                    &relative_path,
-                                                )
-                                                .await;
-                                            files_to_update.extend(new_files);
-                                        }
-                                        // Skip regular processing for directories
-                                        continue;
-                                    }
-
-                                    // Analyze event history
-                                    let has_create = event_kinds
-                                        .iter()
-                                        .any(|k| matches!(k, EventKind::Create(_)));
-                                    let has_remove = event_kinds.iter().any(|k| {
-                                        matches!(k, EventKind::Remove(_))
-                                            || matches!(
-                                                k,
-                                                EventKind::Modify(notify::event::ModifyKind::Name(
-                                                    notify::event::RenameMode::From
-                                                ))
-                                            )
-                                    });
-                                    let has_modify = event_kinds
-                                        .iter()
-                                        .any(|k| matches!(k, EventKind::Modify(_)));
-
-                                    // Determine action based on event history and current state
-                                    match (file_exists, has_create, has_remove, has_modify) {
-                                        // File exists, saw both Create and Remove -> atomic write, treat as modify
-                                        (true, true, true, _) => {
-                                            if let Ok(hash) = calculate_file_hash(&path_buf) {
-                                                if let Err(e) = sync_config
-                                                    .update_file(&relative_path, hash)
-                                                    .await
-                                                {
-                                                    Log::log(
-                                                        &format!(
-                                                            "Failed to update file {}: {}",
-                                                            relative_path, e
-                                                        )
-                                                        .red(),
-                                                        None,
-                                                    );
-                                                }
-                                            }
-                                            if let Ok(content) = fs::read(&path_buf) {
-                                                files_to_update
-                                                    .insert(relative_path.clone(), content);
-                                            }
-                                        }
-                                        // File exists, saw Create but no Remove -> new file
-                                        (true, true, false, _) => {
-                                            if let Ok(hash) = calculate_file_hash(&path_buf) {
-                                                if let Err(e) = sync_config
-                                                    .add_file(
-                                                        relative_path.clone(),
-                                                        FileEntry {
-                                                            hash: Some(hash),
-                                                            is_deleted: false,
-                                                            last_modified: Utc::now(),
-                                                        },
-                                                    )
-                                                    .await
-                                                {
-                                                    Log::log(
-                                                        &format!(
-                                                            "Failed to add file {}: {}",
-                                                            relative_path, e
-                                                        )
-                                                        .red(),
-                                                        None,
-                                                    );
-                                                }
-                                            }
-                                            if let Ok(content) = fs::read(&path_buf) {
-                                                files_to_update
-                                                    .insert(relative_path.clone(), content);
-                                            }
-                                        }
-                                        // File exists, no Create event -> modification
-                                        (true, false, _, true) => {
-                                            if let Ok(hash) = calculate_file_hash(&path_buf) {
-                                                if let Err(e) = sync_config
-                                                    .update_file(&relative_path, hash)
-                                                    .await
-                                                {
-                                                    Log::log(
-                                                        &format!(
-                                                            "Failed to update file {}: {}",
-                                                            relative_path, e
-                                                        )
-                                                        .red(),
-                                                        None,
-                                                    );
-                                                }
-                                            }
-                                            if let Ok(content) = fs::read(&path_buf) {
-                                                files_to_update
-                                                    .insert(relative_path.clone(), content);
-                                            }
-                                        }
-                                        // File doesn't exist, saw Remove -> delete (could be file or directory)
-                                        (false, _, true, _) => {
-                                            // Try recursive delete (handles both files and directories)
-                                            let _ = sync_config
-                                                .delete_directory_recursive(&relative_path)
-                                                .await;
-
-                                            paths_to_delete.push(relative_path.clone());
-                                        }
-                                        // Any other case -> no action needed
-                                        _ => {}
-                                    }
-                                }
-
-                                // Send the file updates to the main connection handler via channel
-                                if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
-                                    Log::log(
-                                        &format!(
-                                            "📡 Sending to server: {} files, {} deletions",
-                                            files_to_update.len(),
-                                            paths_to_delete.len()
-                                        )
-                                        .blue(),
-                                        None,
-                                    );
-                                }
-
-                                if !files_to_update.is_empty() || !paths_to_delete.is_empty() {
-                                    let _ = file_change_tx
-                                        .send(PeerMessage::FileUpdatePush {
-                                            files_to_write: files_to_update,
-                                            paths_to_delete,
-                                        })
-                                        .await;
-                                }
-                            }
-                        });
-                    }
-
-                    // ===== MESSAGE HANDLER (PEER) =====
-                    // Listen for messages from the server AND file watcher changes
-                    loop {
-                        tokio::select! {
-                            // Handle incoming messages from server
-                            server_msg = receive_message_from_peer::<ServerMessage>(&mut reader) => {
-                                match server_msg {
-                                    Ok(message) => {
-                                        match message {
-                                    ServerMessage::PeerListUpdate { peers } => {
-                                        Log::log("Updated connected peers list".yellow(), None);
-
-                                        // Update local peers config with full peer list
-                                        if let Err(e) = peers_config.set_peers(peers).await {
-                                            Log::log(
-                                                &format!("Failed to update peers config: {}", e)
-                                                    .bright_red()
-                                                    .bold(),
-                                                None,
-                                            );
-                                        }
-                                    }
-
-                                    ServerMessage::InitialSyncPushResponse {
-                                        files_to_update,
-                                        files_to_delete,
-                                        files_to_send_back,
-                                    } => {
-                                        Log::log(
-                                            &format!(
-                                                "📥 Initial sync response: {} to receive, {} to delete, {} to send",
-                                                files_to_update.len(),
-                                                files_to_delete.len(),
-                                                files_to_send_back.len()
-                                            )
-                                            .blue(),
-                                            None,
-                                        );
-
-                                        // Handle file deletions first
-                                        if !files_to_delete.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-                                            for path in &files_to_delete {
-                                                Log::log(
-                                                    &format!("  🗑️  Deleting: {}", path).red(),
-                                                    None,
-                                                );
-                                            }
-
-                                            // Unified filesystem + state delete operation
-                                            if let Err(e) = sync_config
-                                                .sync_batch_delete_files(
-                                                    &abs_workspace_path,
-                                                    &files_to_delete,
-                                                    None,
-                                                )
-                                                .await
-                                            {
-                                                Log::log(
-                                                    &format!("Failed to delete files: {}", e).red(),
-                                                    None,
-                                                );
-                                            }
-                                        }
-
-                                        // Apply server's winning files to our file system
-                                        if !files_to_update.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-                                            for (path, _) in &files_to_update {
-                                                Log::log(
-                                                    &format!("  ✨ Creating/Updating: {}", path).green(),
-                                                    None,
-                                                );
-                                            }
-
-                                            // Unified filesystem + state write operation
-                                            if let Err(e) = sync_config
-                                                .sync_batch_write_files(
-                                                    &abs_workspace_path,
-                                                    &files_to_update,
-                                                )
-                                                .await
-                                            {
-                                                Log::log(
-                                                    &format!("Failed to write files: {}", e).red(),
-                                                    None,
-                                                );
-                                            }
-                                        }
-
-                                        // Wait for file watcher events to be processed
-                                        tokio::time::sleep(Duration::from_millis(100)).await;
-                                        ignore_file_events.store(false, Ordering::Relaxed);
-
-                                        let mut our_winning_files: HashMap<String, Vec<u8>> = HashMap::new();
-                                        for path in files_to_send_back {
-                                            // path is relative to workspace_path
-                                            // Convert to absolute path for file operations
-                                            let full_path = abs_workspace_path.join(&path);
-                                            match fs::read(&full_path) {
-                                                Ok(content) => {
-                                                    // Store with relative path as key
-                                                    our_winning_files.insert(path, content);
-                                                }
-                                                Err(e) => {
-                                                    Log::log(
-                                                        &format!(
-                                                            "Failed to read requested file {}: {}",
-                                                            path, e
-                                                        )
-                                                        .red(),
-                                                        None,
-                                                    );
-                                                }
-                                            }
-                                        }
-
-                                        // Send our winning files back to server
-                                        if !our_winning_files.is_empty() {
-                                            Log::log(
-                                                &format!("📤 Sending {} files to server", our_winning_files.len())
-                                                    .blue(),
-                                                None,
-                                            );
-                                            let message = PeerMessage::FileUpdatePush {
-                                                files_to_write: our_winning_files,
-                                                paths_to_delete: Vec::new(),
-                                            };
-                                            if let Err(e) =
-                                                send_message_to_peer(&mut writer, &message).await
-                                            {
-                                                Log::log(
-                                                    &format!(
-                                                        "Failed to send file content response: {}",
-                                                        e
-                                                    )
-                                                    .red(),
-                                                    None,
-                                                );
-                                            }
-                                        }
-                                    }
-
-                                    ServerMessage::FileUpdatePush {
-                                        files_to_write,
-                                        paths_to_delete,
-                                    } => {
-                                        Log::log(
-                                            &format!(
-                                                "📥 Received from server: {} files, {} deletions",
-                                                files_to_write.len(),
-                                                paths_to_delete.len()
-                                            )
-                                            .blue(),
-                                            None,
-                                        );
-
-                                        // Server is pushing updated files to us
-                                        if !files_to_write.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-                                            for (path, _) in &files_to_write {
-                                                Log::log(
-                                                    &format!("  ✨ Creating/Updating: {}", path).green(),
-                                                    None,
-                                                );
-                                            }
-
-                                            if let Err(e) = sync_config
-                                                .sync_batch_write_files(
-                                                    &abs_workspace_path,
-                                                    &files_to_write,
-                                                )
-                                                .await
-                                            {
-                                                Log::log(
-                                                    &format!("Failed to write files: {}", e).red(),
-                                                    None,
-                                                );
-
-                                            }
-
-                                        }
-
-
-                                       // Handle directory deletions
-                                       if !paths_to_delete.is_empty() {
-                                            ignore_file_events.store(true, Ordering::Relaxed);
-                                            for path in &paths_to_delete {
-                                                Log::log(
-                                                    &format!("  🗑️  Deleting: {}", path).red(),
-                                                    None,
-                                                );
-
-                                                // Recursively delete files in this directory
-                                                let _ = sync_config
-                                                    .delete_directory_recursive(path)
-                                                    .await;
-
-                                                // Delete directory from filesystem
-                                                let path_buf =
-                                                    PathBuf::from(&abs_workspace_path).join(path);
-                                                if path_buf.is_dir() {
-                                                    let _ = fs::remove_dir_all(&path_buf);
-                                                } else {
-                                                    let _ = fs::remove_file(&path_buf);
-                                                }
-                                            }
-                                        }
-
-                                        // Wait for file watcher events to be processed
-                                        tokio::time::sleep(Duration::from_millis(100)).await;
-                                        ignore_file_events.store(false, Ordering::Relaxed);
-                                    }
-
-                                            _ => {
-                                                Log::log(&format!("Received message: {:?}", message), None);
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        Log::log(
-                                            &format!("Connection to leader lost: {}", e)
-                                                .bright_red()
-                                                .bold(),
-                                            None,
-                                        );
-                                        break;
-                                    }
-                                }
-                            }
-                            // Handle file changes from the file watcher
-                            Some(file_change_msg) = file_change_rx.recv() => {
-                                // Send file change to server
-                                if let Err(e) = send_message_to_peer(&mut writer, &file_change_msg).await {
-                                    Log::log(
-                                        &format!("Failed to send file changes to server: {}", e).red(),
-                                        None,
-                                    );
-                                }
-                            }
-                        }
-                    }
-                }
-                Ok(other_message) => {
-                    Log::log(
-                        &format!("Unexpected message from leader: {:?}", other_message)
-                            .bright_red()
-                            .bold(),
-                        None,
-                    );
-                    std::process::exit(1);
-                }
-                Err(e) => {
-                    Log::error(
-                        &format!("Failed to receive acknowledgment from leader: {}", e)
-                            .bright_red()
-                            .bold(),
-                        None,
-                    );
-                    std::process::exit(1);
-                }
-            }
+        Err(e) => {
+            Log::error(e, None);
+            std::process::exit(1);
         }
     }
+}
 
-    Ok(())
+async fn sync_workspace(
+    abs_workspace_path: &Path,
+    role: InstanceRole,
+    mode: SyncMode,
+    transport: Transport,
+    api_port: Option<u16>,
+) -> Result<(), String> {
+    let mut builder = SyncEngine::builder(abs_workspace_path)
+        .role(role)
+        .mode(mode)
+        .transport(transport);
+    if let Some(port) = api_port {
+        builder = builder.api_port(port);
+    }
+    let mut engine = builder.build()?;
+    engine.start().await?;
+    engine.wait().await
 }
@@ -0,0 +1,5 @@
+mod registry;
+mod server;
+
+pub use registry::*;
+pub use server::*;
@@ -0,0 +1,341 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::IpAddr,
+    path::Path,
+    sync::{
+        Arc, LazyLock, Mutex, Weak,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+
+use crate::engine::SyncEvent;
+
+/// Every workspace syncing in this process and the hashing done by all of them
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+#[derive(Default)]
+struct Registry {
+    /// Workspace path -> metrics, dropped along with the engine of the workspace
+    workspaces: Mutex<BTreeMap<String, Weak<WorkspaceMetrics>>>,
+    hashed_files: AtomicU64,
+    hashed_bytes: AtomicU64,
+    hash_nanos: AtomicU64,
+}
+
+/// Bytes exchanged with a remote
+#[derive(Default, Clone, Copy)]
+struct PeerTraffic {
+    sent: u64,
+    received: u64,
+}
+
+/// Counters and gauges of a running workspace, exported with a `workspace` label
+#[derive(Default)]
+pub struct WorkspaceMetrics {
+    /// Remote address -> bytes exchanged with it. Peer IDs change with every connection,
+    /// addresses keep the number of series bounded.
+    traffic: Mutex<BTreeMap<IpAddr, PeerTraffic>>,
+    files_applied: AtomicU64,
+    files_deleted: AtomicU64,
+    conflicts: AtomicU64,
+    watcher_events: AtomicU64,
+    connected_peers: AtomicU64,
+    /// Sends waiting for or holding the connections of `PeerConnectionManager`
+    queued_sends: AtomicU64,
+    /// Unix time of the last completed sync with a peer, 0 before the first one
+    last_sync: AtomicI64,
+    /// Bytes of message chunks compressed before sending, and what they came down to
+    compression_input: AtomicU64,
+    compression_output: AtomicU64,
+    /// Bytes of message chunks sent as they were on a compressed connection
+    compression_skipped: AtomicU64,
+}
+
+/// A send counted in the queue depth until dropped, see `WorkspaceMetrics::queue_send`
+pub struct QueuedSend<'a>(&'a WorkspaceMetrics);
+
+impl Drop for QueuedSend<'_> {
+    fn drop(&mut self) {
+        self.0.queued_sends.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Start collecting the metrics of a workspace, replacing the ones of an earlier run
+pub fn register_workspace(abs_workspace_path: &Path) -> Arc<WorkspaceMetrics> {
+    let metrics = Arc::new(WorkspaceMetrics::default());
+    REGISTRY.workspaces.lock().unwrap().insert(
+        abs_workspace_path.display().to_string(),
+        Arc::downgrade(&metrics),
+    );
+    metrics
+}
+
+/// Count the time spent hashing `bytes` bytes of file content
+pub fn record_hash(bytes: u64, duration: Duration) {
+    REGISTRY.hashed_files.fetch_add(1, Ordering::Relaxed);
+    REGISTRY.hashed_bytes.fetch_add(bytes, Ordering::Relaxed);
+    REGISTRY
+        .hash_nanos
+        .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+}
+
+impl WorkspaceMetrics {
+    /// Count what an event says about the workspace
+    pub fn record(&self, event: &SyncEvent) {
+        match event {
+            SyncEvent::FileApplied { .. } => {
+                self.files_applied.fetch_add(1, Ordering::Relaxed);
+            }
+            SyncEvent::FileDeleted { .. } => {
+                self.files_deleted.fetch_add(1, Ordering::Relaxed);
+            }
+            SyncEvent::ConflictDetected { .. } => {
+                self.conflicts.fetch_add(1, Ordering::Relaxed);
+            }
+            SyncEvent::PeerConnected { .. } => {
+                self.connected_peers.fetch_add(1, Ordering::Relaxed);
+            }
+            SyncEvent::PeerDisconnected { .. } => {
+                let _ = self.connected_peers.fetch_update(
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                    |count| count.checked_sub(1),
+                );
+            }
+            SyncEvent::Synced { .. } => {
+                self.last_sync
+                    .store(Utc::now().timestamp(), Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Count the bytes of a frame written to a remote, after compression
+    pub fn sent(&self, remote: IpAddr, bytes: u64) {
+        self.traffic.lock().unwrap().entry(remote).or_default().sent += bytes;
+    }
+
+    /// Count the bytes of a frame read from a remote, before decompression
+    pub fn received(&self, remote: IpAddr, bytes: u64) {
+        self.traffic
+            .lock()
+            .unwrap()
+            .entry(remote)
+            .or_default()
+            .received += bytes;
+    }
+
+    /// Count a chunk of a message sent on a compressed connection, `sent` as long as `chunk` when stored as is
+    pub fn compressed(&self, chunk: usize, sent: usize) {
+        if sent < chunk {
+            self.compression_input
+                .fetch_add(chunk as u64, Ordering::Relaxed);
+            self.compression_output
+                .fetch_add(sent as u64, Ordering::Relaxed);
+        } else {
+            self.compression_skipped
+                .fetch_add(chunk as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Count an event reported by the file watcher, including the ones ignored
+    pub fn watcher_event(&self) {
+        self.watcher_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a send in the queue depth until the returned guard is dropped
+    pub fn queue_send(&self) -> QueuedSend<'_> {
+        self.queued_sends.fetch_add(1, Ordering::Relaxed);
+        QueuedSend(self)
+    }
+}
+
+/// Name, type and help of a metric with one value per workspace, and how to read it
+type WorkspaceMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&WorkspaceMetrics) -> i64,
+);
+
+const WORKSPACE_METRICS: [WorkspaceMetric; 10] = [
+    (
+        "synclite_files_applied_total",
+        "counter",
+        "Files received from peers and written to the workspace",
+        |m| m.files_applied.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_files_deleted_total",
+        "counter",
+        "Files deleted from the workspace because a peer deleted them",
+        |m| m.files_deleted.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_conflicts_total",
+        "counter",
+        "Files changed on both sides since the last sync",
+        |m| m.conflicts.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_watcher_events_total",
+        "counter",
+        "Events reported by the file watcher",
+        |m| m.watcher_events.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_connected_peers",
+        "gauge",
+        "Remote workspaces currently connected",
+        |m| m.connected_peers.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_send_queue_depth",
+        "gauge",
+        "Messages waiting for or being sent over the connections of a leader",
+        |m| m.queued_sends.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_last_sync_timestamp_seconds",
+        "gauge",
+        "Unix time of the last completed sync with a peer, 0 before the first one",
+        |m| m.last_sync.load(Ordering::Relaxed),
+    ),
+    (
+        "synclite_compression_input_bytes_total",
+        "counter",
+        "Bytes of messages compressed before sending",
+        |m| m.compression_input.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_compression_output_bytes_total",
+        "counter",
+        "Bytes the compressed parts of messages came down to",
+        |m| m.compression_output.load(Ordering::Relaxed) as i64,
+    ),
+    (
+        "synclite_compression_skipped_bytes_total",
+        "counter",
+        "Bytes of messages sent uncompressed on a compressed connection because they hardly shrank",
+        |m| m.compression_skipped.load(Ordering::Relaxed) as i64,
+    ),
+];
+
+/// Render the metrics of every running workspace in the Prometheus text format
+pub fn render_metrics() -> String {
+    let workspaces: Vec<(String, Arc<WorkspaceMetrics>)> = {
+        let mut workspaces = REGISTRY.workspaces.lock().unwrap();
+        workspaces.retain(|_, metrics| metrics.strong_count() > 0);
+        workspaces
+            .iter()
+            .filter_map(|(path, metrics)| Some((path.clone(), metrics.upgrade()?)))
+            .collect()
+    };
+
+    let mut out = Exposition::default();
+
+    out.family(
+        "synclite_bytes_sent_total",
+        "counter",
+        "Bytes written to a remote, as sent after compression",
+    );
+    for (path, metrics) in &workspaces {
+        for (remote, traffic) in metrics.traffic.lock().unwrap().iter() {
+            out.sample(
+                "synclite_bytes_sent_total",
+                &[("workspace", path), ("remote", &remote.to_string())],
+                traffic.sent,
+            );
+        }
+    }
+
+    out.family(
+        "synclite_bytes_received_total",
+        "counter",
+        "Bytes read from a remote, as received before decompression",
+    );
+    for (path, metrics) in &workspaces {
+        for (remote, traffic) in metrics.traffic.lock().unwrap().iter() {
+            out.sample(
+                "synclite_bytes_received_total",
+                &[("workspace", path), ("remote", &remote.to_string())],
+                traffic.received,
+            );
+        }
+    }
+
+    for (name, kind, help, value) in WORKSPACE_METRICS {
+        out.family(name, kind, help);
+        for (path, metrics) in &workspaces {
+            out.sample(name, &[("workspace", path)], value(metrics));
+        }
+    }
+
+    out.family(
+        "synclite_hashed_files_total",
+        "counter",
+        "File contents hashed",
+    );
+    out.sample(
+        "synclite_hashed_files_total",
+        &[],
+        REGISTRY.hashed_files.load(Ordering::Relaxed),
+    );
+    out.family(
+        "synclite_hashed_bytes_total",
+        "counter",
+        "Bytes of content hashed",
+    );
+    out.sample(
+        "synclite_hashed_bytes_total",
+        &[],
+        REGISTRY.hashed_bytes.load(Ordering::Relaxed),
+    );
+    out.family(
+        "synclite_hash_seconds_total",
+        "counter",
+        "Time spent hashing content",
+    );
+    out.sample(
+        "synclite_hash_seconds_total",
+        &[],
+        REGISTRY.hash_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+    );
+
+    out.0
+}
+
+/// Prometheus text format writer
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+/// Escape a label value, backslashes, quotes and newlines are not allowed as is
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,31 @@
+use std::net::Ipv4Addr;
+
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use tokio::net::TcpListener;
+
+use super::render_metrics;
+use crate::utils::Log;
+
+/// Content type of the Prometheus text format
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Listen on 127.0.0.1 and return the future serving `/metrics`, which stops when dropped
+pub async fn start_metrics_server(
+    port: u16,
+) -> Result<impl Future<Output = ()> + Send + 'static, String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .map_err(|e| format!("Failed to listen on 127.0.0.1:{}: {}", port, e))?;
+
+    let app = Router::new().route("/metrics", get(metrics));
+
+    Ok(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            Log::error(format!("Metrics listener stopped: {}", e), None);
+        }
+    })
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, TEXT_FORMAT)], render_metrics())
+}
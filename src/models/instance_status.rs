@@ -0,0 +1,145 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::{
+    models::SyncMode,
+    utils::{read_json, write_json},
+};
+
+/// How often a running instance refreshes `status.json`
+pub const STATUS_HEARTBEAT_SECS: u64 = 15;
+
+/// How many detected conflicts are kept in `status.json`
+const MAX_CONFLICTS: usize = 100;
+
+/// Role a workspace was started with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstanceRole {
+    Leader,
+    Peer,
+}
+
+/// A file modified on both sides since the last sync, resolved by Last Write Wins
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub path: String,
+    pub peer_id: String,
+    /// `local` or `remote`, the side whose version was kept
+    pub winner: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// What a running instance reports about itself in `.synclite/status.json`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstanceState {
+    pub pid: u32,
+    pub role: InstanceRole,
+    pub port: u16,
+    pub mode: SyncMode,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub connected_peers: Vec<String>,
+    /// Whether syncing was paused through the control socket
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub conflicts: Vec<SyncConflict>,
+}
+
+impl InstanceState {
+    /// Whether the instance refreshed its status recently enough to still be running
+    pub fn is_running(&self) -> bool {
+        Utc::now() - self.updated_at < Duration::seconds(3 * STATUS_HEARTBEAT_SECS as i64)
+    }
+}
+
+pub struct InstanceStatus {
+    state: Arc<Mutex<InstanceState>>,
+    storage_path: PathBuf,
+}
+
+impl InstanceStatus {
+    pub fn new(
+        storage_path: impl AsRef<Path>,
+        role: InstanceRole,
+        port: u16,
+        mode: SyncMode,
+    ) -> Self {
+        let storage_path = storage_path.as_ref().to_path_buf();
+
+        // Conflicts outlive restarts until they are looked at
+        let conflicts = Self::read(&storage_path)
+            .map(|previous| previous.conflicts)
+            .unwrap_or_default();
+
+        let now = Utc::now();
+        Self {
+            state: Arc::new(Mutex::new(InstanceState {
+                pid: std::process::id(),
+                role,
+                port,
+                mode,
+                started_at: now,
+                updated_at: now,
+                connected_peers: Vec::new(),
+                paused: false,
+                conflicts,
+            })),
+            storage_path,
+        }
+    }
+
+    /// Read the status last written by an instance of a workspace
+    pub fn read(storage_path: &Path) -> Option<InstanceState> {
+        read_json(&storage_path.join("status.json")).ok()
+    }
+
+    /// Set the currently connected peers and automatically save
+    pub async fn set_connected_peers(&self, peers: Vec<String>) -> Result<(), String> {
+        {
+            let mut state = self.state.lock().await;
+            state.connected_peers = peers;
+        }
+        self.save().await
+    }
+
+    /// Set whether syncing is paused and automatically save
+    pub async fn set_paused(&self, paused: bool) -> Result<(), String> {
+        {
+            let mut state = self.state.lock().await;
+            state.paused = paused;
+        }
+        self.save().await
+    }
+
+    /// Record conflicts resolved during a sync and automatically save
+    pub async fn add_conflicts(&self, conflicts: Vec<SyncConflict>) -> Result<(), String> {
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut state = self.state.lock().await;
+            state.conflicts.extend(conflicts);
+            let excess = state.conflicts.len().saturating_sub(MAX_CONFLICTS);
+            state.conflicts.drain(..excess);
+        }
+        self.save().await
+    }
+
+    /// Get a clone of the status for read-only access
+    pub async fn state(&self) -> InstanceState {
+        let state = self.state.lock().await;
+        state.clone()
+    }
+
+    /// Refresh the heartbeat and save the status to disk
+    pub async fn save(&self) -> Result<(), String> {
+        let mut state = self.state.lock().await;
+        state.updated_at = Utc::now();
+        write_json(&self.storage_path.join("status.json"), &*state)
+    }
+}
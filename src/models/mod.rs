@@ -1,5 +1,15 @@
+mod instance_status;
 mod peers_config;
 mod sync_config;
+mod sync_mode;
+mod sync_schedule;
+mod sync_snapshots;
+mod workspace_config;
 
+pub use instance_status::*;
 pub use peers_config::*;
 pub use sync_config::*;
+pub use sync_mode::*;
+pub use sync_schedule::*;
+pub use sync_snapshots::*;
+pub use workspace_config::*;
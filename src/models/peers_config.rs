@@ -1,23 +1,66 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use uuid::Uuid;
 
 use crate::utils::write_json;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeersState {
+    /// Stable identifier of this workspace, used to key the snapshots remotes keep of it
+    #[serde(default = "new_workspace_id")]
+    pub workspace_id: String,
     pub leader: Option<String>,
     pub peers: Vec<String>,
+    /// When this workspace last finished syncing with its leader
+    #[serde(default)]
+    pub last_synced: Option<DateTime<Utc>>,
+    /// Subtrees this workspace wants from its leader, empty means everything
+    #[serde(default)]
+    pub subscriptions: Vec<String>,
+    /// Folder shared between a leader and its peers, routes connections when a daemon serves several folders
+    #[serde(default)]
+    pub folder_id: Option<String>,
+    /// Workspaces of every peer that ever synced with this leader. Each of them must have
+    /// acknowledged a deletion before its tombstone is compacted early.
+    #[serde(default)]
+    pub known_workspaces: Vec<String>,
+}
+
+impl Default for PeersState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PeersState {
     pub fn new() -> Self {
         Self {
+            workspace_id: new_workspace_id(),
             leader: None,
             peers: vec![],
+            last_synced: None,
+            subscriptions: vec![],
+            folder_id: None,
+            known_workspaces: vec![],
         }
     }
+
+    /// The folder ID a leader serves this workspace under, defaults to the directory name
+    pub fn effective_folder_id(&self, abs_workspace_path: &Path) -> String {
+        self.folder_id.clone().unwrap_or_else(|| {
+            abs_workspace_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+}
+
+fn new_workspace_id() -> String {
+    Uuid::new_v4().to_string()
 }
 
 pub struct PeersConfig {
@@ -68,6 +111,32 @@ impl PeersConfig {
         self.save().await
     }
 
+    /// Record the time of the last completed sync and automatically save
+    pub async fn set_last_synced(&self, last_synced: DateTime<Utc>) -> Result<(), String> {
+        {
+            let mut state = self.state.lock().await;
+            state.last_synced = Some(last_synced);
+        }
+        self.save().await
+    }
+
+    /// Remember the workspace of a peer that synced with us and automatically save.
+    /// Peers without an ID are recorded too, they can never acknowledge a deletion.
+    pub async fn add_known_workspace(&self, workspace_id: &str) -> Result<(), String> {
+        {
+            let mut state = self.state.lock().await;
+            if state
+                .known_workspaces
+                .iter()
+                .any(|known| known == workspace_id)
+            {
+                return Ok(());
+            }
+            state.known_workspaces.push(workspace_id.to_string());
+        }
+        self.save().await
+    }
+
     /// Get a clone of the config for read-only access
     pub async fn state(&self) -> PeersState {
         let state = self.state.lock().await;
@@ -1,49 +1,129 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::sync::Mutex;
 
-use crate::sync::{calculate_file_hash, compute_sync_state};
-use crate::utils::{read_json, write_json};
+use crate::models::WorkspaceConfig;
+use crate::storage::{FileVersioning, VersioningPolicy};
+use crate::sync::{
+    SYNC_IGNORE_FILE, SyncIgnore, VerifyReport, calculate_file_hash, calculate_hash,
+    check_workspace, compute_sync_state, purge_tombstones, recorded_hashes, syncable_path,
+    verify_workspace,
+};
+use crate::utils::{Log, read_json, write_json};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FileEntry {
     pub hash: Option<String>,
     pub is_deleted: bool,
     pub last_modified: DateTime<Utc>,
+    /// When a tombstone was recorded, which can be long after `last_modified` for files deleted offline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl FileEntry {
+    /// The tombstone of this entry, keeping the modification time so LWW sees the deletion as the old change it may be.
+    /// Already deleted entries keep the time they were deleted.
     pub fn deleted(&self) -> Self {
         Self {
             hash: None,
             is_deleted: true,
             last_modified: self.last_modified,
+            deleted_at: match self.is_deleted {
+                true => self.deleted_at,
+                false => Some(Utc::now()),
+            },
         }
     }
+
+    /// When the tombstone was recorded, tombstones from before `deleted_at` existed fall back to their modification time
+    pub fn deleted_since(&self) -> DateTime<Utc> {
+        self.deleted_at.unwrap_or(self.last_modified)
+    }
 }
 
 pub type SyncState = HashMap<String, FileEntry>;
 
+/// How long tombstones are kept before they are compacted away
+pub const DEFAULT_TOMBSTONE_RETENTION_DAYS: i64 = 30;
+
 pub struct SyncConfig {
     state: Arc<Mutex<SyncState>>,
     storage_path: PathBuf,
+    tombstone_retention: RwLock<Duration>,
+    sync_ignore: SyncIgnore,
+    versioning: FileVersioning,
 }
 
 impl SyncConfig {
     pub fn new(storage_path: impl AsRef<Path>) -> Self {
+        let storage_path = storage_path.as_ref().to_path_buf();
+        let workspace_path = storage_path.parent().unwrap_or(&storage_path).to_path_buf();
+
         Self {
             state: Arc::new(Mutex::new(HashMap::new())),
-            storage_path: storage_path.as_ref().to_path_buf(),
+            tombstone_retention: RwLock::new(Duration::days(DEFAULT_TOMBSTONE_RETENTION_DAYS)),
+            sync_ignore: SyncIgnore::empty(workspace_path),
+            versioning: FileVersioning::new(&storage_path, VersioningPolicy::default()),
+            storage_path,
         }
     }
+
+    /// Override how long tombstones are kept before compaction
+    pub fn with_tombstone_retention(self, retention: Duration) -> Self {
+        self.set_tombstone_retention(retention);
+        self
+    }
+
+    /// Override how replaced and deleted files are kept under `.synclite/versions/`
+    pub fn with_versioning(mut self, policy: VersioningPolicy) -> Self {
+        self.versioning = FileVersioning::new(&self.storage_path, policy);
+        self
+    }
+
+    /// Change how long tombstones are kept while running
+    pub fn set_tombstone_retention(&self, retention: Duration) {
+        if let Ok(mut current) = self.tombstone_retention.write() {
+            *current = retention;
+        }
+    }
+
+    /// Apply the retention, versioning and ignore settings of the workspace config.
+    /// Changed ignore patterns take effect with the next `reload_ignore`.
+    pub fn apply_workspace_config(&self, config: &WorkspaceConfig) {
+        self.set_tombstone_retention(Duration::days(config.tombstone_retention_days as i64));
+        self.versioning.set_policy(config.versioning.clone());
+        self.sync_ignore.set_patterns(config.ignore.clone());
+    }
+
+    /// Stored versions of replaced and deleted files
+    pub fn versioning(&self) -> &FileVersioning {
+        &self.versioning
+    }
+
+    /// Tombstones older than this point in time may already have been compacted away
+    pub fn tombstone_horizon(&self) -> DateTime<Utc> {
+        let retention = self
+            .tombstone_retention
+            .read()
+            .map(|retention| *retention)
+            .unwrap_or_else(|_| Duration::days(DEFAULT_TOMBSTONE_RETENTION_DAYS));
+        Utc::now() - retention
+    }
+
+    /// Check whether a workspace-relative path is excluded by `.syncignore`
+    pub fn is_ignored(&self, relative_path: &str, is_dir: bool) -> bool {
+        self.sync_ignore.is_ignored(relative_path, is_dir)
+    }
 }
 
 impl SyncConfig {
     pub async fn load(&self) -> Result<(), String> {
+        self.sync_ignore.reload()?;
         *self.state.lock().await = read_json(&self.storage_path.join("state.json"))
             .map_err(|e| format!("Failed to load sync state: {}", e))?;
         Ok(())
@@ -55,11 +135,21 @@ impl SyncConfig {
 
             // This will include all the files that exist in the workspace including newly **Created** and **Modified** files
             let mut computed_state: SyncState = HashMap::new();
-            compute_sync_state(workspace_path, workspace_path, &mut computed_state)?;
+            compute_sync_state(
+                workspace_path,
+                workspace_path,
+                &self.sync_ignore,
+                &mut computed_state,
+            )?;
 
             for (path, file_entry) in &*saved_state {
+                // Newly ignored files simply stop being tracked, peers keep their copies
+                if self.sync_ignore.is_ignored(path, false) {
+                    continue;
+                }
+
                 // File existed before but doesn t know, it means it was **Deleted**
-                if computed_state.get(path).is_none() {
+                if !computed_state.contains_key(path) {
                     computed_state.insert(path.clone(), file_entry.deleted());
                 }
             }
@@ -72,6 +162,43 @@ impl SyncConfig {
         Ok(())
     }
 
+    /// Re-read `.syncignore` and reconcile the state with the new rules.
+    /// Newly ignored files stop being tracked (without tombstones) and newly
+    /// un-ignored files start being tracked. Returns the contents of the newly
+    /// tracked files so they can be sent to peers.
+    pub async fn reload_ignore(&self) -> Result<HashMap<String, Vec<u8>>, String> {
+        self.sync_ignore.reload()?;
+
+        let workspace_path = self.storage_path.parent().unwrap();
+        let mut computed_state: SyncState = HashMap::new();
+        compute_sync_state(
+            workspace_path,
+            workspace_path,
+            &self.sync_ignore,
+            &mut computed_state,
+        )?;
+
+        let mut new_files = HashMap::new();
+        self.batch_operations(|state| {
+            state.retain(|path, _| !self.sync_ignore.is_ignored(path, false));
+
+            for (path, file_entry) in computed_state {
+                let is_tracked = state.get(&path).is_some_and(|entry| !entry.is_deleted);
+                if is_tracked {
+                    continue;
+                }
+
+                if let Ok(content) = fs::read(workspace_path.join(&path)) {
+                    new_files.insert(path.clone(), content);
+                }
+                state.insert(path, file_entry);
+            }
+        })
+        .await?;
+
+        Ok(new_files)
+    }
+
     /// Add or update a file entry and auto-save
     pub async fn add_file(&self, path: String, file_entry: FileEntry) -> Result<(), String> {
         {
@@ -91,6 +218,7 @@ impl SyncConfig {
                     hash: None,
                     is_deleted: true,
                     last_modified: Utc::now(),
+                    deleted_at: Some(Utc::now()),
                 },
             );
         }
@@ -107,6 +235,7 @@ impl SyncConfig {
                     hash: Some(new_hash),
                     is_deleted: false,
                     last_modified: Utc::now(),
+                    deleted_at: None,
                 },
             );
         }
@@ -125,12 +254,140 @@ impl SyncConfig {
         self.save().await
     }
 
+    /// Purge tombstones older than the retention window, or that every known peer
+    /// has acknowledged, and auto-save. Returns the paths that were compacted away.
+    pub async fn compact_tombstones(
+        &self,
+        acknowledged: impl Fn(&str, &FileEntry) -> bool,
+    ) -> Result<Vec<String>, String> {
+        let purged = {
+            let mut state = self.state.lock().await;
+            purge_tombstones(&mut state, self.tombstone_horizon(), acknowledged)
+        };
+
+        if !purged.is_empty() {
+            self.save().await?;
+        }
+
+        Ok(purged)
+    }
+
     /// Get a clone of the state for read-only access
     pub async fn state(&self) -> SyncState {
         let state = self.state.lock().await;
         state.clone()
     }
 
+    /// Hashes recorded in the state for files about to be sent. Files that changed on disk
+    /// since they were recorded are left out, the watcher or `synclite verify` records them again.
+    pub async fn advertised_hashes(
+        &self,
+        files: &mut HashMap<String, Vec<u8>>,
+    ) -> HashMap<String, String> {
+        let state = self.state.lock().await;
+        let (hashes, withheld) = recorded_hashes(files, &state);
+        if !withheld.is_empty() {
+            Log::warning(
+                format!(
+                    "Not sending {} files that don't match the recorded state: {}",
+                    withheld.len(),
+                    withheld.join(", ")
+                ),
+                None,
+            );
+        }
+        hashes
+    }
+
+    /// Rehash the workspace and compare it with the recorded state, without changing anything
+    pub async fn verify(&self) -> Result<VerifyReport, String> {
+        let workspace_path = self.storage_path.parent().unwrap();
+        let state = self.state.lock().await;
+        verify_workspace(workspace_path, &self.sync_ignore, &state)
+    }
+
+    /// Compare the workspace with the recorded state from file metadata, without hashing anything
+    pub async fn check(&self) -> Result<VerifyReport, String> {
+        let workspace_path = self.storage_path.parent().unwrap();
+        let state = self.state.lock().await;
+        check_workspace(workspace_path, &self.sync_ignore, &state)
+    }
+
+    /// Pick up changes the file watcher missed and record them in the state.
+    /// Corrupted files are left alone, `synclite verify --repair` deals with them.
+    /// Returns the contents of changed files and the deleted paths so they can be sent to peers.
+    pub async fn rescan(&self) -> Result<(HashMap<String, Vec<u8>>, Vec<String>), String> {
+        let workspace_path = self.storage_path.parent().unwrap().to_path_buf();
+        let report = self.verify().await?;
+
+        let changed: Vec<String> = report
+            .modified
+            .into_iter()
+            .chain(report.untracked)
+            .collect();
+        let files = self.read_files(&workspace_path, &changed);
+
+        self.batch_operations(|state| {
+            for (path, content) in &files {
+                state.insert(
+                    path.clone(),
+                    FileEntry {
+                        hash: Some(calculate_hash(content)),
+                        is_deleted: false,
+                        last_modified: Utc::now(),
+                        deleted_at: None,
+                    },
+                );
+            }
+            for path in &report.missing {
+                state.insert(
+                    path.clone(),
+                    FileEntry {
+                        hash: None,
+                        is_deleted: true,
+                        last_modified: Utc::now(),
+                        deleted_at: Some(Utc::now()),
+                    },
+                );
+            }
+        })
+        .await?;
+
+        Ok((files, report.missing))
+    }
+
+    /// Read the current content of workspace files, skipping ignored and unreadable ones
+    pub fn read_files(&self, workspace_path: &Path, paths: &[String]) -> HashMap<String, Vec<u8>> {
+        paths
+            .iter()
+            .filter(|path| !self.is_ignored(path, false))
+            .filter_map(|path| {
+                fs::read(workspace_path.join(path))
+                    .ok()
+                    .map(|content| (path.clone(), content))
+            })
+            .collect()
+    }
+
+    /// Read the content of files another instance asked for. Only paths that are live in the
+    /// state are read, anything untracked, deleted or outside the workspace is skipped.
+    pub async fn read_tracked_files(
+        &self,
+        workspace_path: &Path,
+        paths: &[String],
+    ) -> HashMap<String, Vec<u8>> {
+        let tracked: Vec<String> = {
+            let state = self.state.lock().await;
+            paths
+                .iter()
+                .filter(|path| syncable_path(path).is_some())
+                .filter(|path| state.get(*path).is_some_and(|entry| !entry.is_deleted))
+                .cloned()
+                .collect()
+        };
+        self.read_files(workspace_path, &tracked)
+    }
+
     /// Manually save the current state to disk
     pub async fn save(&self) -> Result<(), String> {
         let state = self.state.lock().await;
@@ -160,7 +417,8 @@ impl SyncConfig {
             fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
         }
 
-        // Write file to filesystem
+        // Keep the previous content around, then write file to filesystem
+        self.preserve_before_write(workspace_path, relative_path, content);
         fs::write(&full_path, content).map_err(|e| format!("Failed to write file: {}", e))?;
 
         // Calculate hash and update state
@@ -181,16 +439,15 @@ impl SyncConfig {
         workspace_path: &Path,
         relative_path: &str,
     ) -> Result<(), String> {
-        let full_path = workspace_path.join(relative_path);
-
-        // Delete file from filesystem
-        fs::remove_file(&full_path).map_err(|e| format!("Failed to delete file: {}", e))?;
+        // Move file from filesystem into the versions folder
+        self.remove_with_versioning(workspace_path, relative_path)?;
 
         // Mark as deleted in state
         self.delete_file(relative_path).await
     }
 
-    /// Write multiple files to disk AND update sync state (batch unified operation)
+    /// Write multiple files to disk AND update sync state (batch unified operation).
+    /// Returns the paths that were written, ignored ones are skipped.
     ///
     /// # Arguments
     /// * `fs` - FileSystem reference for sandboxed operations
@@ -200,9 +457,15 @@ impl SyncConfig {
         &self,
         workspace_path: &Path,
         files: &HashMap<String, Vec<u8>>,
-    ) -> Result<(), String> {
+    ) -> Result<Vec<String>, String> {
+        // Never apply remote content to paths we ignore locally
+        let files: HashMap<&String, &Vec<u8>> = files
+            .iter()
+            .filter(|(relative_path, _)| !self.is_ignored(relative_path, false))
+            .collect();
+
         // Write all files to filesystem first
-        for (relative_path, content) in files {
+        for (relative_path, content) in &files {
             let full_path = workspace_path.join(relative_path);
 
             // Create parent directory if needed
@@ -212,28 +475,37 @@ impl SyncConfig {
                 })?;
             }
 
-            // Write file
+            // Keep the previous content around, then write file
+            self.preserve_before_write(workspace_path, relative_path, content);
             fs::write(&full_path, content)
                 .map_err(|e| format!("Failed to write file {}: {}", relative_path, e))?;
         }
 
         // Update all state entries in a batch
         self.batch_operations(|state| {
-            for (relative_path, _) in files {
+            for relative_path in files.keys() {
                 let full_path = workspace_path.join(relative_path);
                 if let Ok(hash) = calculate_file_hash(&full_path) {
                     state.insert(
-                        relative_path.clone(),
+                        (*relative_path).clone(),
                         FileEntry {
                             hash: Some(hash),
                             is_deleted: false,
                             last_modified: Utc::now(),
+                            deleted_at: None,
                         },
                     );
                 }
             }
         })
-        .await
+        .await?;
+
+        // A peer changed the ignore rules, pick them up right away
+        if files.keys().any(|path| path.as_str() == SYNC_IGNORE_FILE) {
+            self.reload_ignore().await?;
+        }
+
+        Ok(files.into_keys().cloned().collect())
     }
 
     /// Recursively mark files as deleted if they are inside the given directory
@@ -250,6 +522,7 @@ impl SyncConfig {
                     entry.is_deleted = true;
                     entry.hash = None;
                     entry.last_modified = Utc::now();
+                    entry.deleted_at = Some(Utc::now());
                     deleted_files.push(path.clone());
                 }
             }
@@ -263,6 +536,32 @@ impl SyncConfig {
         deleted_files
     }
 
+    /// Delete a file or directory from disk AND mark its files as deleted in sync state.
+    /// Anything excluded by `.syncignore` inside a deleted directory is left in place.
+    /// Returns the paths that were marked as deleted.
+    pub async fn sync_delete_path(
+        &self,
+        workspace_path: &Path,
+        relative_path: &str,
+    ) -> Vec<String> {
+        let full_path = workspace_path.join(relative_path);
+        if self.is_ignored(relative_path, full_path.is_dir()) {
+            return Vec::new();
+        }
+
+        // Recursively mark files in this directory as deleted
+        let deleted_files = self.delete_directory_recursive(relative_path).await;
+
+        // Delete from filesystem
+        if full_path.is_dir() {
+            self.remove_dir_except_ignored(workspace_path, &full_path);
+        } else {
+            let _ = self.remove_with_versioning(workspace_path, relative_path);
+        }
+
+        deleted_files
+    }
+
     /// Recursively scan a directory and add all files to the sync state.
     /// Returns a map of relative paths to file contents for broadcasting.
     pub async fn scan_and_add_directory(
@@ -290,7 +589,7 @@ impl SyncConfig {
         }
 
         let mut found_paths = Vec::new();
-        if let Err(_) = visit_dirs(&full_path, &mut found_paths) {
+        if visit_dirs(&full_path, &mut found_paths).is_err() {
             // Directory might have been deleted/moved quickly, ignore
             return new_files;
         }
@@ -304,8 +603,8 @@ impl SyncConfig {
                     Err(_) => continue,
                 };
 
-                // Skip .synclite directory
-                if rel_path.contains(".synclite") {
+                // Skip the .synclite directory and ignored files
+                if self.is_ignored(&rel_path, false) {
                     continue;
                 }
 
@@ -316,6 +615,7 @@ impl SyncConfig {
                             hash: Some(hash),
                             is_deleted: false,
                             last_modified: Utc::now(),
+                            deleted_at: None,
                         },
                     );
 
@@ -326,11 +626,13 @@ impl SyncConfig {
             }
         })
         .await
-        .unwrap_or_else(|e| eprintln!("Failed to update sync state: {}", e));
+        .unwrap_or_else(|e| Log::error(format!("Failed to update sync state: {}", e), None));
 
         new_files
     }
 
+    /// Delete multiple files from disk AND mark them as deleted in sync state (batch unified operation).
+    /// Returns the paths that were deleted, ignored ones are skipped.
     ///
     /// # Arguments
     /// * `fs` - FileSystem reference for sandboxed operations
@@ -342,20 +644,25 @@ impl SyncConfig {
         workspace_path: &Path,
         relative_paths: &[String],
         peer_sync_state: Option<&SyncState>,
-    ) -> Result<(), String> {
-        // Delete all files from filesystem first
-        for relative_path in relative_paths {
-            let full_path = workspace_path.join(relative_path);
+    ) -> Result<Vec<String>, String> {
+        // Never delete paths we ignore locally
+        let relative_paths: Vec<&String> = relative_paths
+            .iter()
+            .filter(|relative_path| !self.is_ignored(relative_path, false))
+            .collect();
+
+        // Delete all files from filesystem first, keeping a version of each
+        for relative_path in &relative_paths {
             // Ignore errors if file doesn't exist
-            let _ = fs::remove_file(&full_path);
+            let _ = self.remove_with_versioning(workspace_path, relative_path);
         }
 
         // Update all state entries in a batch
         self.batch_operations(|state| {
-            for relative_path in relative_paths {
+            for relative_path in &relative_paths {
                 let last_modified = if let Some(peer_state) = peer_sync_state {
                     peer_state
-                        .get(relative_path)
+                        .get(*relative_path)
                         .map(|e| e.last_modified)
                         .unwrap_or_else(Utc::now)
                 } else {
@@ -363,15 +670,82 @@ impl SyncConfig {
                 };
 
                 state.insert(
-                    relative_path.clone(),
+                    (*relative_path).clone(),
                     FileEntry {
                         hash: None,
                         is_deleted: true,
                         last_modified,
+                        deleted_at: Some(Utc::now()),
                     },
                 );
             }
         })
-        .await
+        .await?;
+
+        Ok(relative_paths.into_iter().cloned().collect())
+    }
+}
+
+// ===== VERSIONING HELPERS =====
+// Replaced and deleted files go through these so they can be restored later
+impl SyncConfig {
+    /// Store the current content of a file that is about to be overwritten with different content
+    fn preserve_before_write(&self, workspace_path: &Path, relative_path: &str, content: &[u8]) {
+        let full_path = workspace_path.join(relative_path);
+        match fs::read(&full_path) {
+            Ok(existing) if existing != content => {}
+            _ => return,
+        }
+
+        let result = self
+            .versioning
+            .preserve(workspace_path, relative_path)
+            .and_then(|_| self.versioning.cleanup_file(relative_path));
+        if let Err(e) = result {
+            Log::warning(e, None);
+        }
+    }
+
+    /// Move a file into the versions folder instead of deleting it outright
+    fn remove_with_versioning(
+        &self,
+        workspace_path: &Path,
+        relative_path: &str,
+    ) -> Result<(), String> {
+        self.versioning
+            .preserve_and_remove(workspace_path, relative_path)?;
+
+        if let Err(e) = self.versioning.cleanup_file(relative_path) {
+            Log::warning(e, None);
+        }
+
+        Ok(())
+    }
+
+    /// Remove a directory tree, skipping ignored entries (and the directories that still contain them)
+    fn remove_dir_except_ignored(&self, workspace_path: &Path, dir: &Path) {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_dir = path.is_dir();
+                let rel_path = match path.strip_prefix(workspace_path) {
+                    Ok(p) => p.to_string_lossy().to_string(),
+                    Err(_) => continue,
+                };
+
+                if self.is_ignored(&rel_path, is_dir) {
+                    continue;
+                }
+
+                if is_dir {
+                    self.remove_dir_except_ignored(workspace_path, &path);
+                } else {
+                    let _ = self.remove_with_versioning(workspace_path, &rel_path);
+                }
+            }
+        }
+
+        // Fails (and is kept) if ignored files are still inside
+        let _ = fs::remove_dir(dir);
     }
 }
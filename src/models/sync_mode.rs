@@ -0,0 +1,59 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Direction in which a workspace exchanges changes with the rest of the network
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncMode {
+    /// Local and remote changes flow both ways
+    #[default]
+    SendReceive,
+    /// Local is the source of truth, remote changes are flagged and not applied
+    SendOnly,
+    /// Remote changes are applied, local changes are flagged and not propagated
+    ReceiveOnly,
+    /// Like receive-only, and the initial sync forces an exact copy of the leader (extra files are deleted)
+    Mirror,
+}
+
+impl SyncMode {
+    /// Whether local changes are propagated to the other side
+    pub fn sends_local_changes(&self) -> bool {
+        matches!(self, SyncMode::SendReceive | SyncMode::SendOnly)
+    }
+
+    /// Whether changes coming from the other side are applied locally
+    pub fn accepts_remote_changes(&self) -> bool {
+        !matches!(self, SyncMode::SendOnly)
+    }
+}
+
+impl FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "send-receive" => Ok(SyncMode::SendReceive),
+            "send-only" => Ok(SyncMode::SendOnly),
+            "receive-only" => Ok(SyncMode::ReceiveOnly),
+            "mirror" => Ok(SyncMode::Mirror),
+            _ => Err(format!(
+                "Unknown sync mode '{}', expected send-receive, send-only, receive-only or mirror",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SyncMode::SendReceive => "send-receive",
+            SyncMode::SendOnly => "send-only",
+            SyncMode::ReceiveOnly => "receive-only",
+            SyncMode::Mirror => "mirror",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,48 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Daily local times during which a workspace doesn't sync, e.g. office hours on a metered link
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoSyncWindow {
+    /// Start of the window, as `HH:MM`
+    #[serde(serialize_with = "write_clock", deserialize_with = "read_clock")]
+    pub from: NaiveTime,
+    /// End of the window, earlier than `from` when it runs past midnight
+    #[serde(serialize_with = "write_clock", deserialize_with = "read_clock")]
+    pub to: NaiveTime,
+    /// Days the window starts on, every day when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<Weekday>,
+}
+
+impl NoSyncWindow {
+    /// Whether the window covers a local date and time
+    pub fn contains(&self, now: NaiveDateTime) -> bool {
+        let starts_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let time = now.time();
+        if self.from < self.to {
+            starts_on(now.weekday()) && self.from <= time && time < self.to
+        } else {
+            // The evening part started today, the morning part the day before
+            (starts_on(now.weekday()) && time >= self.from)
+                || (starts_on(now.weekday().pred()) && time < self.to)
+        }
+    }
+}
+
+/// Whether any of the windows covers a local date and time
+pub fn in_no_sync_window(windows: &[NoSyncWindow], now: NaiveDateTime) -> bool {
+    windows.iter().any(|window| window.contains(now))
+}
+
+fn write_clock<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&time.format("%H:%M"))
+}
+
+fn read_clock<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&value, "%H:%M").map_err(|_| {
+        serde::de::Error::custom(format!("invalid time \"{}\", expected HH:MM", value))
+    })
+}
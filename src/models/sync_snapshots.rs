@@ -0,0 +1,113 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    models::SyncState,
+    utils::{read_json, write_json},
+};
+
+/// Workspace IDs are UUIDs, anything longer can't be one
+const MAX_WORKSPACE_ID_LEN: usize = 64;
+
+/// Check that a workspace ID received from a peer is safe to use as a file name.
+/// Empty IDs come from peers that predate them and are accepted.
+pub fn check_workspace_id(workspace_id: &str) -> Result<(), String> {
+    let valid = workspace_id.len() <= MAX_WORKSPACE_ID_LEN
+        && workspace_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(format!("Invalid workspace ID: {:?}", workspace_id)),
+    }
+}
+
+/// The state both sides of a connection last agreed on
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncSnapshot {
+    pub synced_at: DateTime<Utc>,
+    pub state: SyncState,
+}
+
+/// Stores one snapshot per remote workspace under `.synclite/snapshots/`
+pub struct SyncSnapshots {
+    snapshots_path: PathBuf,
+}
+
+impl SyncSnapshots {
+    pub fn new(storage_path: impl AsRef<Path>) -> Self {
+        Self {
+            snapshots_path: storage_path.as_ref().join("snapshots"),
+        }
+    }
+
+    /// Get the last agreed state with a remote workspace, if we ever synced with it
+    pub fn load(&self, workspace_id: &str) -> Option<SyncSnapshot> {
+        if workspace_id.is_empty() || check_workspace_id(workspace_id).is_err() {
+            return None;
+        }
+        read_json(&self.snapshot_path(workspace_id)).ok()
+    }
+
+    /// Record the state we currently agree on with a remote workspace
+    pub fn record(&self, workspace_id: &str, state: SyncState) -> Result<(), String> {
+        if workspace_id.is_empty() {
+            return Ok(());
+        }
+        check_workspace_id(workspace_id)?;
+
+        fs::create_dir_all(&self.snapshots_path)
+            .map_err(|e| format!("Failed to create snapshots directory: {}", e))?;
+
+        let snapshot = SyncSnapshot {
+            synced_at: Utc::now(),
+            state,
+        };
+        write_json(&self.snapshot_path(workspace_id), &snapshot)
+    }
+
+    /// Get the last agreed state with every remote workspace we synced with, by workspace ID
+    pub fn load_all(&self) -> HashMap<String, SyncSnapshot> {
+        let Ok(entries) = fs::read_dir(&self.snapshots_path) else {
+            return HashMap::new();
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let workspace_id = path.file_stem()?.to_string_lossy().to_string();
+                Some((workspace_id, read_json(&path).ok()?))
+            })
+            .collect()
+    }
+
+    /// List every remote workspace we synced with and when we last agreed with it
+    pub fn list(&self) -> Vec<(String, DateTime<Utc>)> {
+        let Ok(entries) = fs::read_dir(&self.snapshots_path) else {
+            return Vec::new();
+        };
+
+        let mut synced: Vec<(String, DateTime<Utc>)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let workspace_id = path.file_stem()?.to_string_lossy().to_string();
+                let snapshot: SyncSnapshot = read_json(&path).ok()?;
+                Some((workspace_id, snapshot.synced_at))
+            })
+            .collect();
+
+        synced.sort_by_key(|(_, synced_at)| std::cmp::Reverse(*synced_at));
+        synced
+    }
+
+    fn snapshot_path(&self, workspace_id: &str) -> PathBuf {
+        self.snapshots_path.join(format!("{}.json", workspace_id))
+    }
+}
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    models::{DEFAULT_TOMBSTONE_RETENTION_DAYS, NoSyncWindow, SyncMode},
+    storage::VersioningPolicy,
+    sync::check_ignore_patterns,
+    utils::{read_json, write_json},
+};
+
+/// Name of the workspace config file in the storage directory
+pub const WORKSPACE_CONFIG_FILE: &str = "config.json";
+
+/// Port of the local HTTP API when enabled without one
+pub const DEFAULT_API_PORT: u16 = 8484;
+
+/// Largest message accepted from a peer unless configured otherwise, in MiB
+pub const DEFAULT_MAX_FRAME_MIB: u32 = 1024;
+
+/// Memory the messages of a connection may hold unless configured otherwise, in MiB
+pub const DEFAULT_CONNECTION_MEMORY_MIB: u32 = 2048;
+
+/// Local HTTP API of a running workspace, see the `api` module
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Port the API listens on, always on 127.0.0.1
+    pub port: u16,
+    /// Key clients must send, generated into `.synclite/api_key` when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_API_PORT,
+            key: None,
+        }
+    }
+}
+
+/// Upload and download limits in KiB/s, unlimited when not set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_kibps: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_kibps: Option<u64>,
+}
+
+/// Bandwidth the workspace may use, see `BandwidthLimiter`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthConfig {
+    /// Shared by all peers
+    pub total: RateLimits,
+    /// Each peer on the local network (private, loopback and link-local addresses)
+    pub lan: RateLimits,
+    /// Each peer elsewhere
+    pub wan: RateLimits,
+    /// Peers at these addresses, replacing the `lan` or `wan` limits
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub peers: BTreeMap<IpAddr, RateLimits>,
+}
+
+impl BandwidthConfig {
+    /// Limits of a single peer at this address
+    pub fn peer_limits(&self, ip: IpAddr, lan: bool) -> RateLimits {
+        match self.peers.get(&ip) {
+            Some(limits) => *limits,
+            None if lan => self.lan,
+            None => self.wan,
+        }
+    }
+}
+
+/// Algorithm compressing the messages of a connection, see `Link`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Smaller output, the default
+    Zstd,
+    /// Faster, for fast links and slow machines
+    Lz4,
+}
+
+impl Compression {
+    /// Every algorithm, in the order offered by default
+    pub const ALL: [Compression; 2] = [Compression::Zstd, Compression::Lz4];
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Zstd => write!(f, "zstd"),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+/// Per-workspace settings stored in `.synclite/config.json`.
+///
+/// Network settings (`port`, `bind`, `leader`, `mode`, `api`) are read when the workspace starts and
+/// lose against command-line options. Everything else is reloaded while running.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// Port a leader listens on, or a peer looks for its leader on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Address a leader listens on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind: Option<IpAddr>,
+    /// Leader a peer connects to instead of discovering it over mDNS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leader: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<SyncMode>,
+    /// How long watcher events are gathered before a burst is processed
+    pub debounce_ms: u64,
+    /// How long watcher events are ignored after sync wrote to the workspace itself
+    pub ignore_window_ms: u64,
+    /// Gitignore-style patterns excluded on top of `.syncignore`
+    pub ignore: Vec<String>,
+    pub versioning: VersioningPolicy,
+    pub tombstone_retention_days: u32,
+    /// Also compact tombstones as soon as every known peer has the deletion, before the retention runs out
+    #[serde(skip_serializing_if = "is_false")]
+    pub compact_acknowledged_tombstones: bool,
+    /// Serve the local HTTP API, off when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<ApiConfig>,
+    /// Times syncing is paused on its own, see `NoSyncWindow`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub no_sync: Vec<NoSyncWindow>,
+    #[serde(skip_serializing_if = "is_unlimited")]
+    pub bandwidth: BandwidthConfig,
+    /// Algorithms this workspace compresses with, by preference, none turns compression off.
+    /// Applies to connections made after a change.
+    #[serde(skip_serializing_if = "is_default_compression")]
+    pub compression: Vec<Compression>,
+    /// Largest message accepted from a peer in MiB, a peer announcing more is disconnected.
+    /// Applies to connections made after a change, like `connection_memory_mib`.
+    pub max_frame_mib: u32,
+    /// Memory in MiB the messages of one connection may hold, received or being received
+    pub connection_memory_mib: u32,
+}
+
+fn is_default_compression(compression: &[Compression]) -> bool {
+    *compression == Compression::ALL
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_unlimited(bandwidth: &BandwidthConfig) -> bool {
+    *bandwidth == BandwidthConfig::default()
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            bind: None,
+            leader: None,
+            mode: None,
+            debounce_ms: 150,
+            ignore_window_ms: 100,
+            ignore: Vec::new(),
+            versioning: VersioningPolicy::default(),
+            tombstone_retention_days: DEFAULT_TOMBSTONE_RETENTION_DAYS as u32,
+            compact_acknowledged_tombstones: false,
+            api: None,
+            no_sync: Vec::new(),
+            bandwidth: BandwidthConfig::default(),
+            compression: Compression::ALL.to_vec(),
+            max_frame_mib: DEFAULT_MAX_FRAME_MIB,
+            connection_memory_mib: DEFAULT_CONNECTION_MEMORY_MIB,
+        }
+    }
+}
+
+impl WorkspaceConfig {
+    /// Read and validate the config of a workspace, defaults if it has none
+    pub fn load(storage_path: &Path) -> Result<Self, String> {
+        let config_path = storage_path.join(WORKSPACE_CONFIG_FILE);
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let config: Self = read_json(&config_path)?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid {}: {}", config_path.display(), e))?;
+        Ok(config)
+    }
+
+    pub fn save(&self, storage_path: &Path) -> Result<(), String> {
+        write_json(&storage_path.join(WORKSPACE_CONFIG_FILE), self)
+    }
+
+    /// Reject values that parse but can't work
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == Some(0) {
+            return Err("port must be between 1 and 65535".to_string());
+        }
+        if self.leader.is_some_and(|leader| leader.port() == 0) {
+            return Err("leader needs a port between 1 and 65535".to_string());
+        }
+        if !(10..=10_000).contains(&self.debounce_ms) {
+            return Err("debounce_ms must be between 10 and 10000".to_string());
+        }
+        if self.ignore_window_ms > 10_000 {
+            return Err("ignore_window_ms must be at most 10000".to_string());
+        }
+        if self.tombstone_retention_days == 0 {
+            return Err("tombstone_retention_days must be at least 1".to_string());
+        }
+        if let Some(api) = &self.api {
+            if api.port == 0 {
+                return Err("api port must be between 1 and 65535".to_string());
+            }
+            if api.key.as_ref().is_some_and(|key| key.trim().is_empty()) {
+                return Err("api key can't be empty".to_string());
+            }
+        }
+        if self.no_sync.iter().any(|window| window.from == window.to) {
+            return Err("no_sync windows must end at another time than they start".to_string());
+        }
+        let bandwidth = &self.bandwidth;
+        let all_limits = [&bandwidth.total, &bandwidth.lan, &bandwidth.wan]
+            .into_iter()
+            .chain(bandwidth.peers.values());
+        for limits in all_limits {
+            if limits.upload_kibps == Some(0) || limits.download_kibps == Some(0) {
+                return Err("bandwidth limits must be at least 1 KiB/s".to_string());
+            }
+        }
+        // Message lengths are sent as 31 bits
+        if !(1..=2047).contains(&self.max_frame_mib) {
+            return Err("max_frame_mib must be between 1 and 2047".to_string());
+        }
+        if self.connection_memory_mib < self.max_frame_mib {
+            return Err("connection_memory_mib must be at least max_frame_mib".to_string());
+        }
+        if self.connection_memory_mib > 1024 * 1024 {
+            return Err("connection_memory_mib must be at most 1048576".to_string());
+        }
+        if let VersioningPolicy::Simple { keep: 0, .. } = self.versioning {
+            return Err("versioning keep must be at least 1".to_string());
+        }
+        check_ignore_patterns(&self.ignore)
+    }
+
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    pub fn ignore_window(&self) -> Duration {
+        Duration::from_millis(self.ignore_window_ms)
+    }
+
+    /// Settings that differ from `other` and only take effect on restart
+    pub fn restart_required_changes(&self, other: &WorkspaceConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.port != other.port {
+            changed.push("port");
+        }
+        if self.bind != other.bind {
+            changed.push("bind");
+        }
+        if self.leader != other.leader {
+            changed.push("leader");
+        }
+        if self.mode != other.mode {
+            changed.push("mode");
+        }
+        if self.api != other.api {
+            changed.push("api");
+        }
+        changed
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::models::{BandwidthConfig, RateLimits};
+
+/// Token bucket letting bytes through at a set rate, with up to a second worth of burst
+pub struct TokenBucket {
+    /// Bytes per second, 0 for no limit
+    rate: AtomicU64,
+    /// Bytes that may go out right away, negative once borrowed against the time to come,
+    /// and when that was last worked out
+    tokens: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: AtomicU64::new(rate),
+            tokens: Mutex::new((rate as f64, Instant::now())),
+        }
+    }
+
+    /// Change the rate, 0 lifts the limit. Transfers in progress slow down or speed up right away.
+    pub fn set_rate(&self, rate: u64) {
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Wait until `bytes` may go through
+    pub async fn take(&self, bytes: usize) {
+        let wait = {
+            let rate = self.rate() as f64;
+            let mut tokens = self.tokens.lock().unwrap();
+            let now = Instant::now();
+            if rate == 0.0 {
+                *tokens = (0.0, now);
+                return;
+            }
+
+            let refilled = tokens.0 + now.duration_since(tokens.1).as_secs_f64() * rate;
+            let left = refilled.min(rate) - bytes as f64;
+            *tokens = (left, now);
+            if left >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-left / rate)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// The buckets one direction of a connection goes through, unlimited when empty
+#[derive(Clone, Default)]
+pub struct Throttle(Vec<Arc<TokenBucket>>);
+
+impl Throttle {
+    /// Wait until every bucket lets `bytes` through
+    pub async fn take(&self, bytes: usize) {
+        for bucket in &self.0 {
+            bucket.take(bytes).await;
+        }
+    }
+
+    /// Whether any bucket currently limits the rate
+    pub fn is_limited(&self) -> bool {
+        self.0.iter().any(|bucket| bucket.rate() > 0)
+    }
+}
+
+/// Throttles of a connection to a peer
+#[derive(Clone, Default)]
+pub struct ConnectionThrottle {
+    pub upload: Throttle,
+    pub download: Throttle,
+}
+
+/// The buckets of a connection, kept to adjust their rates
+struct PeerBuckets {
+    ip: IpAddr,
+    upload: Weak<TokenBucket>,
+    download: Weak<TokenBucket>,
+}
+
+/// Bandwidth limits of a workspace: totals shared by every connection, and limits of each
+/// connection picked by the address of the peer.
+/// Includes
+/// - Throttling a new connection: `connection`
+/// - Adjusting the limits of every connection while running: `apply`
+pub struct BandwidthLimiter {
+    config: Mutex<BandwidthConfig>,
+    upload: Arc<TokenBucket>,
+    download: Arc<TokenBucket>,
+    peers: Mutex<Vec<PeerBuckets>>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(config: &BandwidthConfig) -> Self {
+        Self {
+            config: Mutex::new(config.clone()),
+            upload: Arc::new(TokenBucket::new(upload_rate(&config.total))),
+            download: Arc::new(TokenBucket::new(download_rate(&config.total))),
+            peers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Throttles for a connection with a peer at this address
+    pub fn connection(&self, ip: IpAddr) -> ConnectionThrottle {
+        // Holding the connections while reading the limits, `apply` sees this one or is seen by it
+        let mut peers = self.peers.lock().unwrap();
+        let limits = self
+            .config
+            .lock()
+            .unwrap()
+            .peer_limits(ip, is_lan_address(ip));
+        let upload = Arc::new(TokenBucket::new(upload_rate(&limits)));
+        let download = Arc::new(TokenBucket::new(download_rate(&limits)));
+
+        peers.retain(|peer| peer.upload.strong_count() > 0);
+        peers.push(PeerBuckets {
+            ip,
+            upload: Arc::downgrade(&upload),
+            download: Arc::downgrade(&download),
+        });
+
+        ConnectionThrottle {
+            upload: Throttle(vec![Arc::clone(&self.upload), upload]),
+            download: Throttle(vec![Arc::clone(&self.download), download]),
+        }
+    }
+
+    /// Switch to new limits, connections already open included
+    pub fn apply(&self, config: &BandwidthConfig) {
+        *self.config.lock().unwrap() = config.clone();
+        self.upload.set_rate(upload_rate(&config.total));
+        self.download.set_rate(download_rate(&config.total));
+
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|peer| peer.upload.strong_count() > 0);
+        for peer in peers.iter() {
+            let limits = config.peer_limits(peer.ip, is_lan_address(peer.ip));
+            if let Some(upload) = peer.upload.upgrade() {
+                upload.set_rate(upload_rate(&limits));
+            }
+            if let Some(download) = peer.download.upgrade() {
+                download.set_rate(download_rate(&limits));
+            }
+        }
+    }
+}
+
+fn upload_rate(limits: &RateLimits) -> u64 {
+    limits.upload_kibps.unwrap_or(0) * 1024
+}
+
+fn download_rate(limits: &RateLimits) -> u64 {
+    limits.download_kibps.unwrap_or(0) * 1024
+}
+
+/// Whether an address belongs to the local network: private, loopback or link-local
+pub fn is_lan_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_lan_address(IpAddr::V4(ip)),
+            None => ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local(),
+        },
+    }
+}
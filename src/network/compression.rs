@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+
+use crate::models::Compression;
+
+/// zstd level, fast enough to keep up with a LAN while still shrinking JSON several times
+const ZSTD_LEVEL: i32 = 1;
+
+/// Chunk codes in a chunked frame, see `send_message_over`
+const STORED: u8 = 0;
+const ZSTD: u8 = 1;
+const LZ4: u8 = 2;
+
+/// Pick the algorithm for a connection: the first of ours the other side offered, if any
+pub fn negotiate_compression(
+    preferred: &[Compression],
+    offered: &[Compression],
+) -> Option<Compression> {
+    preferred
+        .iter()
+        .find(|compression| offered.contains(compression))
+        .copied()
+}
+
+/// Compress a chunk, returning its code and the bytes to send.
+/// Chunks saving less than a tenth are stored as they are, not worth decompressing on the other side.
+pub(crate) fn compress_chunk(compression: Compression, chunk: &[u8]) -> (u8, Cow<'_, [u8]>) {
+    let compressed = match compression {
+        Compression::Zstd => zstd::bulk::compress(chunk, ZSTD_LEVEL).map(|bytes| (ZSTD, bytes)),
+        Compression::Lz4 => Ok((LZ4, lz4_flex::block::compress(chunk))),
+    };
+    match compressed {
+        Ok((code, bytes)) if bytes.len() * 10 <= chunk.len() * 9 => (code, Cow::Owned(bytes)),
+        _ => (STORED, Cow::Borrowed(chunk)),
+    }
+}
+
+/// Decode a chunk into `chunk`, which must come out exactly full
+pub(crate) fn decompress_chunk(code: u8, bytes: &[u8], chunk: &mut [u8]) -> Result<(), String> {
+    let written = match code {
+        STORED if bytes.len() == chunk.len() => {
+            chunk.copy_from_slice(bytes);
+            chunk.len()
+        }
+        STORED => return Err("stored chunk of the wrong size".to_string()),
+        ZSTD => zstd::bulk::decompress_to_buffer(bytes, chunk)
+            .map_err(|e| format!("invalid zstd chunk: {}", e))?,
+        LZ4 => lz4_flex::block::decompress_into(bytes, chunk)
+            .map_err(|e| format!("invalid lz4 chunk: {}", e))?,
+        code => return Err(format!("unknown chunk encoding {}", code)),
+    };
+    match written == chunk.len() {
+        true => Ok(()),
+        false => Err("chunk decompressed to the wrong size".to_string()),
+    }
+}
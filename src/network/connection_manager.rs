@@ -1,24 +1,79 @@
 use serde::{Serialize, de::DeserializeOwned};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+use crate::{
+    metrics::WorkspaceMetrics,
+    models::Compression,
+    network::{
+        MemoryBudget, MemoryReservation, ProtocolError, ServerMessage, Throttle, compress_chunk,
+        decompress_chunk,
+    },
+    utils::Log,
+};
+
+/// How messages travel over one direction of a connection: the bandwidth they may use and, when
+/// sending, the compression agreed in the handshake. Received messages say themselves whether
+/// they are compressed.
+#[derive(Clone, Default)]
+pub struct Link {
+    pub throttle: Throttle,
+    pub compression: Option<Compression>,
+    /// Where the compression and the bytes written or read are counted
+    pub metrics: Option<Arc<WorkspaceMetrics>>,
+    /// Address of the remote, the bytes exchanged with it are counted under it in `metrics`
+    pub remote: Option<IpAddr>,
+}
+
+impl Link {
+    /// Count the bytes of a frame written to the remote in the metrics of the workspace
+    fn count_sent(&self, bytes: u64) {
+        if let (Some(metrics), Some(remote)) = (&self.metrics, self.remote) {
+            metrics.sent(remote, bytes);
+        }
+    }
 
-use crate::{network::ServerMessage, utils::Log};
+    /// Count the bytes of a frame read from the remote in the metrics of the workspace
+    fn count_received(&self, bytes: u64) {
+        if let (Some(metrics), Some(remote)) = (&self.metrics, self.remote) {
+            metrics.received(remote, bytes);
+        }
+    }
+}
+
+/// An active connection to a peer
+struct PeerConnection {
+    /// Locked by each send on its own, so a slow peer never holds up the others
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    /// How messages are sent to the peer
+    upload: Link,
+    /// Subtrees the peer subscribed to, empty means everything
+    subscriptions: Vec<String>,
+    /// Stable identifier of the peer's workspace, known once it sent its initial sync
+    workspace_id: Option<String>,
+}
 
 /// Manages active connections to all peers
 #[derive(Clone)]
 pub struct PeerConnectionManager {
-    /// Map of peer_id -> write stream for sending messages
-    connections: Arc<Mutex<HashMap<String, OwnedWriteHalf>>>,
+    /// Map of peer_id -> connection for sending messages, only locked to look connections up
+    connections: Arc<Mutex<HashMap<String, PeerConnection>>>,
+    /// Where the bytes sent and the sends waiting for a connection are counted
+    metrics: Option<Arc<WorkspaceMetrics>>,
 }
 
 /// Connection manager for peer-to-peer communication
 /// Includes
 /// - Adding and removing connections: `add_connection` and `remove_connection`
-/// - Sending messages to specific peers: `send_to_peer`
-/// - Broadcasting messages to all peers: `broadcast_message`
+/// - Setting the subtrees a peer subscribed to: `set_subscriptions`
+/// - Tracking the workspace behind each connection: `set_workspace_id` and `get_connected_workspaces`
+/// - Sending messages to specific peers: `send_to_peer` and `send_to_peer_with_progress`
+/// - Broadcasting messages to all peers (filtered by their subscriptions): `broadcast_message`
 /// - Broadcasting messages to all peers except the ones in the exclude list: `broadcast_except`
 /// - Getting the number of active connections: `connection_count`
 /// - Getting the list of connected peer IDs: `get_connected_peers`
@@ -27,13 +82,69 @@ impl PeerConnectionManager {
     pub fn new() -> Self {
         Self {
             connections: Arc::new(Mutex::new(HashMap::new())),
+            metrics: None,
+        }
+    }
+
+    /// A manager counting its traffic in the metrics of a workspace
+    pub fn with_metrics(metrics: Arc<WorkspaceMetrics>) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..Self::new()
         }
     }
 
-    /// Add a new peer connection
-    pub async fn add_connection(&self, peer_id: String, writer: OwnedWriteHalf) {
+    /// Add a new peer connection, sending to it over `upload`
+    pub async fn add_connection(&self, peer_id: String, writer: OwnedWriteHalf, upload: Link) {
         let mut connections = self.connections.lock().await;
-        connections.insert(peer_id, writer);
+        connections.insert(
+            peer_id,
+            PeerConnection {
+                writer: Arc::new(Mutex::new(writer)),
+                upload,
+                subscriptions: Vec::new(),
+                workspace_id: None,
+            },
+        );
+    }
+
+    /// Set the subtrees a peer wants to receive
+    pub async fn set_subscriptions(&self, peer_id: &str, subscriptions: Vec<String>) {
+        let mut connections = self.connections.lock().await;
+        if let Some(connection) = connections.get_mut(peer_id) {
+            connection.subscriptions = subscriptions;
+        }
+    }
+
+    /// Set the stable identifier of a peer's workspace
+    pub async fn set_workspace_id(&self, peer_id: &str, workspace_id: String) {
+        let mut connections = self.connections.lock().await;
+        if let Some(connection) = connections.get_mut(peer_id) {
+            connection.workspace_id = Some(workspace_id);
+        }
+    }
+
+    /// Get the workspace identifiers and subscriptions of every peer that completed its initial sync
+    pub async fn get_connected_workspaces(&self) -> Vec<(String, Vec<String>)> {
+        let connections = self.connections.lock().await;
+        connections
+            .values()
+            .filter_map(|connection| {
+                connection
+                    .workspace_id
+                    .clone()
+                    .map(|workspace_id| (workspace_id, connection.subscriptions.clone()))
+            })
+            .collect()
+    }
+
+    /// Get the subtrees a peer wants to receive, empty means everything
+    pub async fn get_subscriptions(&self, peer_id: &str) -> Vec<String> {
+        let connections = self.connections.lock().await;
+        connections
+            .get(peer_id)
+            .map(|connection| connection.subscriptions.clone())
+            .unwrap_or_default()
     }
 
     /// Remove a peer connection
@@ -48,101 +159,100 @@ impl PeerConnectionManager {
         peer_id: &str,
         message: &ServerMessage,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut connections = self.connections.lock().await;
+        self.send_to_peer_with_progress(peer_id, message, |_, _| {})
+            .await
+    }
 
-        if let Some(writer) = connections.get_mut(peer_id) {
-            send_message_to_peer(writer, message).await.map_err(
-                |e| -> Box<dyn std::error::Error + Send + Sync> {
-                    format!("Failed to send message to peer {}: {}", peer_id, e).into()
-                },
-            )?;
-            Ok(())
-        } else {
-            Err(std::io::Error::new(
+    /// Send a message to a specific peer, reporting the bytes sent as in `send_message_with_progress`
+    pub async fn send_to_peer_with_progress(
+        &self,
+        peer_id: &str,
+        message: &ServerMessage,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let _queued = self.metrics.as_ref().map(|metrics| metrics.queue_send());
+        let Some((writer, upload)) = self.connection_writer(peer_id).await else {
+            return Err(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 format!("No active connection to peer {}", peer_id),
             )
-            .into())
-        }
+            .into());
+        };
+
+        send_message_over(&mut *writer.lock().await, message, &upload, progress)
+            .await
+            .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> {
+                format!("Failed to send message to peer {}: {}", peer_id, e).into()
+            })
     }
 
-    /// Broadcast a message to all connected peers
+    /// Broadcast a message to all connected peers, each receiving only what they subscribed to
     pub async fn broadcast_message(&self, message: &ServerMessage) -> Vec<String> {
-        let connection_count = self.connection_count().await;
-        if connection_count == 0 {
-            return Vec::new();
-        }
-
-        let mut connections = self.connections.lock().await;
-        let mut failed_peers = Vec::new();
-
-        // Collect peer IDs to avoid borrowing issues
-        let peer_ids: Vec<String> = connections.keys().cloned().collect();
-
-        // Send messages while holding the lock (avoid recursive lock)
-        for peer_id in peer_ids {
-            if let Some(writer) = connections.get_mut(&peer_id) {
-                if let Err(e) = send_message_to_peer(writer, message).await {
-                    eprintln!("Failed to send message to peer {}: {}", peer_id, e);
-                    failed_peers.push(peer_id.clone());
-                }
-            }
-        }
-
-        // Remove failed connections
-        for peer_id in &failed_peers {
-            connections.remove(peer_id);
-        }
-
-        if !failed_peers.is_empty() {
-            Log::warning(
-                &format!("Failed to notify {} peers", failed_peers.len()),
-                None,
-            );
-        }
-
-        failed_peers
+        self.broadcast_except(message, Vec::new()).await
     }
 
-    /// Broadcast a message to all peers except the ones in the exclude list (useful for forwarding)
+    /// Broadcast a message to all peers except the ones in the exclude list (useful for forwarding),
+    /// each receiving only what they subscribed to
     pub async fn broadcast_except(
         &self,
         message: &ServerMessage,
         exclude_peer: Vec<String>,
     ) -> Vec<String> {
-        let connection_count = self.connection_count().await;
-        if connection_count == 0 {
+        let _queued = self.metrics.as_ref().map(|metrics| metrics.queue_send());
+
+        // Take what each send needs and release the connections before writing anything
+        let recipients: Vec<(String, Arc<Mutex<OwnedWriteHalf>>, Link, ServerMessage)> = {
+            let connections = self.connections.lock().await;
+            connections
+                .iter()
+                .filter(|(peer_id, _)| !exclude_peer.contains(peer_id))
+                .filter_map(|(peer_id, connection)| {
+                    let message = message.filtered_for(&connection.subscriptions)?;
+                    Some((
+                        peer_id.clone(),
+                        Arc::clone(&connection.writer),
+                        connection.upload.clone(),
+                        message,
+                    ))
+                })
+                .collect()
+        };
+        if recipients.is_empty() {
             return Vec::new();
         }
 
-        let mut connections = self.connections.lock().await;
-        let mut failed_peers = Vec::new();
+        // Send to every peer at once, each at the pace of its own link
+        let mut sends = JoinSet::new();
+        for (peer_id, writer, upload, message) in recipients {
+            sends.spawn(async move {
+                let sent =
+                    send_message_over(&mut *writer.lock().await, &message, &upload, |_, _| {})
+                        .await
+                        .map_err(|e| e.to_string());
+                (peer_id, sent)
+            });
+        }
 
-        // Collect peer IDs to avoid borrowing issues
-        let peer_ids: Vec<String> = connections
-            .keys()
-            .filter(|&id| !exclude_peer.contains(id))
-            .cloned()
-            .collect();
-
-        // Send messages while holding the lock (avoid recursive lock)
-        for peer_id in peer_ids {
-            if let Some(writer) = connections.get_mut(&peer_id) {
-                if let Err(e) = send_message_to_peer(writer, message).await {
-                    eprintln!("Failed to send message to peer {}: {}", peer_id, e);
-                    failed_peers.push(peer_id.clone());
-                }
+        let mut failed_peers = Vec::new();
+        while let Some(sent) = sends.join_next().await {
+            if let Ok((peer_id, Err(e))) = sent {
+                Log::warning(
+                    format!("Failed to send message to peer {}: {}", peer_id, e),
+                    None,
+                );
+                failed_peers.push(peer_id);
             }
         }
 
         // Remove failed connections
-        for peer_id in &failed_peers {
-            connections.remove(peer_id);
-        }
-
         if !failed_peers.is_empty() {
+            let mut connections = self.connections.lock().await;
+            for peer_id in &failed_peers {
+                connections.remove(peer_id);
+            }
+
             Log::warning(
-                &format!("Failed to notify {} peers", failed_peers.len()),
+                format!("Failed to notify {} peers", failed_peers.len()),
                 None,
             );
         }
@@ -150,6 +260,14 @@ impl PeerConnectionManager {
         failed_peers
     }
 
+    /// Get the writer and link of a connection, to send over without holding the connections
+    async fn connection_writer(&self, peer_id: &str) -> Option<(Arc<Mutex<OwnedWriteHalf>>, Link)> {
+        let connections = self.connections.lock().await;
+        connections
+            .get(peer_id)
+            .map(|connection| (Arc::clone(&connection.writer), connection.upload.clone()))
+    }
+
     /// Get the number of active connections
     pub async fn connection_count(&self) -> usize {
         let connections = self.connections.lock().await;
@@ -177,19 +295,108 @@ impl Default for PeerConnectionManager {
 
 // Helper functions for sending and receiving messages
 
+/// Bytes written or read at a time, progress is reported between chunks.
+/// Also the size chunks of a message are compressed in.
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Bytes written or read at a time under a bandwidth limit, small enough to keep the rate smooth
+const THROTTLED_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Messages smaller than this are sent uncompressed, there's little to gain
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// Set in the length prefix of a message split into chunks that may be compressed
+const CHUNKED_FRAME: u32 = 1 << 31;
+
 // Send message to a peer
 pub async fn send_message_to_peer<T: Serialize>(
     writer: &mut OwnedWriteHalf,
     message: &T,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let serialized = serde_json::to_string(message)?;
-    let message_bytes = serialized.as_bytes();
+    send_message_with_progress(writer, message, |_, _| {}).await
+}
+
+/// Send a message to a peer, calling `progress` with the bytes sent so far and the size of the message
+pub async fn send_message_with_progress<T: Serialize>(
+    writer: &mut OwnedWriteHalf,
+    message: &T,
+    progress: impl FnMut(u64, u64),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    send_message_over(writer, message, &Link::default(), progress).await
+}
+
+/// Send a message to a peer over a throttled or compressed link, reporting progress as
+/// `send_message_with_progress` in bytes of the uncompressed message.
+///
+/// A message is a length prefix followed by its JSON. When compressed, the prefix has its top bit
+/// set and the JSON is sent in chunks of `CHUNK_SIZE`, each as an encoding byte, its length and
+/// its bytes.
+pub async fn send_message_over<T: Serialize>(
+    writer: &mut OwnedWriteHalf,
+    message: &T,
+    link: &Link,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let message_bytes = serde_json::to_vec(message)?;
+    if message_bytes.len() >= CHUNKED_FRAME as usize {
+        return Err(format!(
+            "Message of {} bytes is too large to send",
+            message_bytes.len()
+        )
+        .into());
+    }
     let length = message_bytes.len() as u32;
 
-    // Send length prefix followed by message
-    writer.write_u32(length).await?;
-    writer.write_all(message_bytes).await?;
+    let compression = link
+        .compression
+        .filter(|_| message_bytes.len() >= MIN_COMPRESSED_SIZE);
+    let Some(compression) = compression else {
+        // Send length prefix followed by message
+        writer.write_u32(length).await?;
+        let mut sent = 0;
+        for chunk in message_bytes.chunks(chunk_size(&link.throttle)) {
+            link.throttle.take(chunk.len()).await;
+            writer.write_all(chunk).await?;
+            sent += chunk.len() as u64;
+            progress(sent, length as u64);
+        }
+        writer.flush().await?;
+        link.count_sent(4 + length as u64);
+        return Ok(());
+    };
+
+    writer.write_u32(CHUNKED_FRAME | length).await?;
+    let mut sent = 0;
+    let mut wire_bytes = 0;
+    let mut frame_bytes = 4;
+    for chunk in message_bytes.chunks(CHUNK_SIZE) {
+        let (encoding, encoded) = compress_chunk(compression, chunk);
+        if let Some(metrics) = &link.metrics {
+            metrics.compressed(chunk.len(), encoded.len());
+        }
+        writer.write_u8(encoding).await?;
+        writer.write_u32(encoded.len() as u32).await?;
+        for piece in encoded.chunks(chunk_size(&link.throttle)) {
+            link.throttle.take(piece.len()).await;
+            writer.write_all(piece).await?;
+        }
+        sent += chunk.len() as u64;
+        wire_bytes += encoded.len() as u64;
+        frame_bytes += 1 + 4 + encoded.len() as u64;
+        progress(sent, length as u64);
+    }
     writer.flush().await?;
+    link.count_sent(frame_bytes);
+
+    if message_bytes.len() >= CHUNK_SIZE {
+        Log::debug(
+            format!(
+                "Compressed a message of {} bytes to {} with {}",
+                length, wire_bytes, compression
+            ),
+            None,
+        );
+    }
 
     Ok(())
 }
@@ -198,15 +405,99 @@ pub async fn send_message_to_peer<T: Serialize>(
 pub async fn receive_message_from_peer<T: DeserializeOwned>(
     reader: &mut OwnedReadHalf,
 ) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
-    // Read length prefix
-    let length = reader.read_u32().await?;
+    receive_message_with_progress(reader, |_, _| {}).await
+}
+
+/// Receive a message from a peer, calling `progress` with the bytes received so far and the size of the message
+pub async fn receive_message_with_progress<T: DeserializeOwned>(
+    reader: &mut OwnedReadHalf,
+    progress: impl FnMut(u64, u64),
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    let budget = MemoryBudget::default();
+    let (message, _reservation) =
+        receive_message_limited(reader, &Link::default(), &budget, progress).await?;
+    Ok(message)
+}
 
-    // Read message
-    let mut message_bytes = vec![0u8; length as usize];
-    reader.read_exact(&mut message_bytes).await?;
+/// Receive a message from a peer no faster than the throttle of `link` allows and within the memory
+/// `budget` of its connection, reporting progress as `receive_message_with_progress` in bytes of the
+/// uncompressed message. Reading slowly makes TCP slow the sender down.
+///
+/// The message keeps its share of the budget until the returned reservation is dropped.
+/// A message breaking the limits or the framing fails with a `ProtocolError`, after which
+/// the connection should be dropped.
+pub async fn receive_message_limited<T: DeserializeOwned>(
+    reader: &mut OwnedReadHalf,
+    link: &Link,
+    budget: &MemoryBudget,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(T, MemoryReservation), Box<dyn std::error::Error + Send + Sync>> {
+    let throttle = &link.throttle;
+    // Read length prefix, checked before anything is held for the message
+    let prefix = reader.read_u32().await?;
+    let length = (prefix & !CHUNKED_FRAME) as usize;
+    let reservation = budget.reserve(length as u64).await?;
+
+    // Read message, growing the buffer with what actually arrives rather than what was announced
+    let mut message_bytes = Vec::new();
+    let mut received = 0;
+    if prefix & CHUNKED_FRAME == 0 {
+        while received < length {
+            let chunk = grow(&mut message_bytes, chunk_size(throttle), length);
+            throttle.take(chunk.len()).await;
+            reader.read_exact(chunk).await?;
+            received += chunk.len();
+            progress(received as u64, length as u64);
+        }
+        link.count_received(4 + length as u64);
+    } else {
+        let mut encoded = Vec::new();
+        let mut frame_bytes = 4;
+        while received < length {
+            let chunk = grow(&mut message_bytes, CHUNK_SIZE, length);
+            let encoding = reader.read_u8().await?;
+            // Only chunks that shrank are compressed, anything longer is corrupt
+            let encoded_length = reader.read_u32().await? as usize;
+            if encoded_length > chunk.len() {
+                return Err(
+                    ProtocolError("compressed chunk larger than its content".to_string()).into(),
+                );
+            }
+            encoded.resize(encoded_length, 0);
+            for piece in encoded.chunks_mut(chunk_size(throttle)) {
+                throttle.take(piece.len()).await;
+                reader.read_exact(piece).await?;
+            }
+            decompress_chunk(encoding, &encoded, chunk).map_err(ProtocolError)?;
+            received += chunk.len();
+            frame_bytes += 1 + 4 + encoded_length as u64;
+            progress(received as u64, length as u64);
+        }
+        link.count_received(frame_bytes);
+    }
 
-    let message_str = String::from_utf8(message_bytes)?;
-    let message: T = serde_json::from_str(&message_str)?;
+    let message: T = serde_json::from_slice(&message_bytes)
+        .map_err(|e| ProtocolError(format!("malformed message: {}", e)))?;
 
-    Ok(message)
+    Ok((message, reservation))
+}
+
+/// Extend `buffer` by up to `by` zeroed bytes without going past `length`, returning the new part.
+/// Capacity doubles as the buffer grows but never exceeds `length`.
+fn grow(buffer: &mut Vec<u8>, by: usize, length: usize) -> &mut [u8] {
+    let start = buffer.len();
+    let end = (start + by).min(length);
+    if end > buffer.capacity() {
+        let capacity = (buffer.capacity() * 2).clamp(end, length);
+        buffer.reserve_exact(capacity - start);
+    }
+    buffer.resize(end, 0);
+    &mut buffer[start..]
+}
+
+fn chunk_size(throttle: &Throttle) -> usize {
+    match throttle.is_limited() {
+        true => THROTTLED_CHUNK_SIZE,
+        false => CHUNK_SIZE,
+    }
 }
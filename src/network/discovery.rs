@@ -0,0 +1,73 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use colored::Colorize;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+
+use crate::utils::Log;
+
+/// mDNS service type advertised by leaders
+pub const SERVICE_TYPE: &str = "_synclite._tcp.local.";
+
+/// How often a browse checks whether it was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Browse the local network for a leader and return its address.
+/// Waits forever when no timeout is given.
+pub fn discover_leader(timeout: Option<Duration>) -> Result<SocketAddr, String> {
+    browse_for_leader(timeout, &AtomicBool::new(false))
+}
+
+/// Browse the local network for a leader until one is found or `cancelled` is set
+pub fn discover_leader_until(cancelled: &AtomicBool) -> Result<SocketAddr, String> {
+    browse_for_leader(None, cancelled)
+}
+
+fn browse_for_leader(
+    timeout: Option<Duration>,
+    cancelled: &AtomicBool,
+) -> Result<SocketAddr, String> {
+    let mdns = ServiceDaemon::new().map_err(|e| format!("Failed to create mDNS daemon: {}", e))?;
+    let receiver = mdns
+        .browse(SERVICE_TYPE)
+        .map_err(|e| format!("Failed to browse: {}", e))?;
+
+    Log::info("Browsing for SyncLite servers...", None);
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            let _ = mdns.shutdown();
+            return Err("Discovery cancelled".to_string());
+        }
+
+        let wait = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err("No SyncLite server found on the network".to_string());
+                }
+                remaining.min(CANCEL_POLL_INTERVAL)
+            }
+            None => CANCEL_POLL_INTERVAL,
+        };
+        let event = receiver.recv_timeout(wait).ok();
+
+        if let Some(ServiceEvent::ServiceResolved(info)) = event {
+            Log::info(
+                format!("Resolved service: {}", info.get_fullname()).green(),
+                None,
+            );
+            if let Some(ip) = info.get_addresses().iter().next()
+                && let Ok(ip_addr) = ip.to_string().parse::<IpAddr>()
+            {
+                let addr = SocketAddr::new(ip_addr, info.get_port());
+                Log::info(format!("Found server at: {}", addr).green(), None);
+                return Ok(addr);
+            }
+        }
+    }
+}
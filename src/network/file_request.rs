@@ -0,0 +1,187 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::Path,
+    time::Duration,
+};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    models::{PeersState, SyncConfig, SyncState},
+    network::{PeerMessage, ServerMessage, receive_message_from_peer, send_message_to_peer},
+    sync::{calculate_hash, reject_corrupted},
+    utils::Log,
+};
+
+/// How long to wait for the leader to answer a file request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connect to a leader just long enough to fetch some files, used to repair corrupted copies.
+/// Returns the received files along with the hashes the leader advertised for them.
+pub async fn request_files(
+    addr: SocketAddr,
+    folder_id: Option<String>,
+    workspace_id: String,
+    paths: Vec<String>,
+) -> Result<(HashMap<String, Vec<u8>>, HashMap<String, String>), String> {
+    let requested = paths.iter().cloned().collect();
+    fetch_files(
+        addr,
+        folder_id,
+        workspace_id,
+        PeerMessage::FileRequest { paths },
+        requested,
+    )
+    .await
+}
+
+/// Send our state to a leader so it returns its copy of every tracked file we edited locally.
+/// Returns the received files along with the hashes the leader advertised for them.
+pub async fn request_reverted_files(
+    addr: SocketAddr,
+    folder_id: Option<String>,
+    workspace_id: String,
+    sync_state: SyncState,
+) -> Result<(HashMap<String, Vec<u8>>, HashMap<String, String>), String> {
+    let tracked = sync_state.keys().cloned().collect();
+    fetch_files(
+        addr,
+        folder_id,
+        workspace_id,
+        PeerMessage::RevertRequest { sync_state },
+        tracked,
+    )
+    .await
+}
+
+/// Make one request over a repair connection, accepting only files among `expected`
+async fn fetch_files(
+    addr: SocketAddr,
+    folder_id: Option<String>,
+    workspace_id: String,
+    request: PeerMessage,
+    expected: HashSet<String>,
+) -> Result<(HashMap<String, Vec<u8>>, HashMap<String, String>), String> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?;
+    let (mut reader, mut writer) = stream.into_split();
+
+    let exchange = async {
+        // A repair connection isn't registered as a peer, so nothing else arrives on it
+        send_message_to_peer(
+            &mut writer,
+            &PeerMessage::RepairFolder {
+                folder_id,
+                workspace_id,
+            },
+        )
+        .await
+        .map_err(|e| format!("Failed to reach folder: {}", e))?;
+
+        match receive_message_from_peer::<ServerMessage>(&mut reader).await {
+            Ok(ServerMessage::ConnectionAck { .. }) => {}
+            Ok(ServerMessage::ConnectionRejected { reason }) => return Err(reason),
+            Ok(other) => return Err(format!("Unexpected message from leader: {:?}", other)),
+            Err(e) => return Err(format!("Failed to receive acknowledgment: {}", e)),
+        }
+
+        send_message_to_peer(&mut writer, &request)
+            .await
+            .map_err(|e| format!("Failed to request files: {}", e))?;
+
+        match receive_message_from_peer::<ServerMessage>(&mut reader).await {
+            Ok(ServerMessage::RequestedFiles { files, hashes }) => {
+                if let Some(path) = files.keys().find(|path| !expected.contains(*path)) {
+                    return Err(format!(
+                        "Leader sent a file that wasn't requested: {}",
+                        path
+                    ));
+                }
+                Ok((files, hashes))
+            }
+            Ok(ServerMessage::ConnectionRejected { reason }) => Err(reason),
+            Ok(other) => Err(format!("Unexpected message from leader: {:?}", other)),
+            Err(e) => Err(format!("Connection to leader lost: {}", e)),
+        }
+    };
+
+    tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| "Timed out waiting for the leader to send the files".to_string())?
+}
+
+/// Replace corrupted files of a workspace with the leader's copies, writing them through its state.
+/// Returns the repaired paths, files that couldn't be fetched intact are left as they are.
+pub async fn repair_files(
+    sync_config: &SyncConfig,
+    workspace_path: &Path,
+    addr: SocketAddr,
+    peers_state: &PeersState,
+    paths: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let (mut files, hashes) = request_files(
+        addr,
+        peers_state.folder_id.clone(),
+        peers_state.workspace_id.clone(),
+        paths,
+    )
+    .await?;
+
+    let rejected = reject_corrupted(&mut files, &hashes);
+    if !rejected.is_empty() {
+        Log::warning(
+            format!(
+                "{} files arrived corrupted and were skipped",
+                rejected.len()
+            ),
+            None,
+        );
+    }
+
+    // The leader may hold the same bad copy
+    files.retain(|path, content| {
+        std::fs::read(workspace_path.join(path))
+            .map(|local| calculate_hash(&local) != calculate_hash(content))
+            .unwrap_or(true)
+    });
+
+    sync_config
+        .sync_batch_write_files(workspace_path, &files)
+        .await
+        .map_err(|e| format!("Failed to write repaired files: {}", e))
+}
+
+/// Replace the tracked files we edited locally with the leader's copies, writing them through our
+/// state so the edits are kept as versions. New local files are left alone.
+pub async fn revert_files(
+    sync_config: &SyncConfig,
+    workspace_path: &Path,
+    addr: SocketAddr,
+    peers_state: &PeersState,
+) -> Result<Vec<String>, String> {
+    let (mut files, hashes) = request_reverted_files(
+        addr,
+        peers_state.folder_id.clone(),
+        peers_state.workspace_id.clone(),
+        sync_config.state().await,
+    )
+    .await?;
+
+    let rejected = reject_corrupted(&mut files, &hashes);
+    if !rejected.is_empty() {
+        Log::warning(
+            format!(
+                "{} files arrived corrupted and were not reverted",
+                rejected.len()
+            ),
+            None,
+        );
+    }
+
+    sync_config
+        .sync_batch_write_files(workspace_path, &files)
+        .await
+        .map_err(|e| format!("Failed to write reverted files: {}", e))
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use colored::Colorize;
+use log::Level;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use tokio::{
+    net::{
+        TcpListener,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{Mutex, mpsc},
+};
+
+use crate::{
+    models::Compression,
+    network::{
+        FrameLimits, Link, MemoryBudget, PeerMessage, ProtocolError, SERVICE_TYPE, ServerMessage,
+        generate_peer_id, receive_message_limited, send_message_to_peer,
+    },
+    utils::Log,
+};
+
+/// Port leaders listen on unless configured otherwise
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Leaders accept connections on every interface unless configured otherwise
+pub const DEFAULT_BIND: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// How long a new connection has to name the folder it wants
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest message accepted before a connection named its folder, anyone reaching the port can send one
+const JOIN_MAX_BYTES: u64 = 64 * 1024;
+
+/// A peer connection accepted on the shared port, handed to the leader serving its folder
+pub struct IncomingPeer {
+    pub reader: OwnedReadHalf,
+    pub writer: OwnedWriteHalf,
+    pub addr: SocketAddr,
+    /// Compression algorithms the peer offered when joining
+    pub compression: Vec<Compression>,
+    /// Set when the connection only requests files to repair the workspace with this id,
+    /// it isn't a peer of the folder
+    pub repair: Option<String>,
+}
+
+/// Routes accepted connections to the leader serving the requested folder.
+/// A standalone leader registers a single folder, a daemon one per leader workspace.
+#[derive(Clone, Default)]
+pub struct FolderRouter {
+    folders: Arc<Mutex<HashMap<String, mpsc::Sender<IncomingPeer>>>>,
+}
+
+impl FolderRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start serving a folder, returns the connections meant for it
+    pub async fn register(&self, folder_id: &str) -> Result<mpsc::Receiver<IncomingPeer>, String> {
+        let mut folders = self.folders.lock().await;
+        if folders
+            .get(folder_id)
+            .is_some_and(|sender| !sender.is_closed())
+        {
+            return Err(format!("Folder {} is already served", folder_id));
+        }
+
+        let (tx, rx) = mpsc::channel::<IncomingPeer>(16);
+        folders.insert(folder_id.to_string(), tx);
+        Ok(rx)
+    }
+
+    /// Stop serving a folder, new connections for it are rejected
+    pub async fn unregister(&self, folder_id: &str) {
+        self.folders.lock().await.remove(folder_id);
+    }
+
+    /// Find the leader for a folder. Without a folder ID the only served folder is picked.
+    async fn route(&self, folder_id: Option<&str>) -> Result<mpsc::Sender<IncomingPeer>, String> {
+        let mut folders = self.folders.lock().await;
+
+        // Leaders that stopped without unregistering can't take connections anymore
+        folders.retain(|_, sender| !sender.is_closed());
+
+        match folder_id {
+            Some(folder_id) => folders
+                .get(folder_id)
+                .cloned()
+                .ok_or_else(|| format!("Folder {} is not served here", folder_id)),
+            None if folders.len() == 1 => Ok(folders.values().next().unwrap().clone()),
+            None => Err(
+                "Several folders are served here, set folder_id in .synclite/peers.json"
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Listen on a port shared by every folder served in this process and advertise it over mDNS.
+/// Each connection names its folder with `JoinFolder` and is handed to that folder's leader.
+pub async fn serve_folders(listener: TcpListener, router: FolderRouter) -> Result<(), String> {
+    let addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to get listening address: {}", e))?;
+    let port = addr.port();
+
+    // Publish mDNS service, kept alive as long as we accept connections
+    let service_id = generate_peer_id();
+    let mdns = ServiceDaemon::new().map_err(|e| format!("Failed to create mDNS daemon: {}", e))?;
+    let instance_name = format!("SyncLite-{}", service_id);
+    let host_name = format!("{}.local.", service_id);
+    let my_ip = local_ip_address::local_ip()
+        .map_err(|e| format!("Failed to get local IP address: {}", e))?;
+    let service_info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance_name,
+        &host_name,
+        my_ip.to_string().as_str(),
+        port,
+        None,
+    )
+    .map_err(|e| format!("Invalid mDNS service info: {}", e))?;
+    mdns.register(service_info)
+        .map_err(|e| format!("Failed to register mDNS service: {}", e))?;
+
+    Log::wrench(format!("Listening on: {}", addr), None);
+    Log::info(format!("Advertising service: {}", instance_name), None);
+
+    loop {
+        let (stream, peer_addr) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Failed to accept connection: {}", e))?;
+        let router = router.clone();
+
+        tokio::spawn(async move {
+            let (mut reader, mut writer) = stream.into_split();
+
+            let budget = MemoryBudget::new(FrameLimits {
+                max_frame_bytes: JOIN_MAX_BYTES,
+                memory_budget_bytes: JOIN_MAX_BYTES,
+            });
+            let (folder_id, compression, repair) = match tokio::time::timeout(
+                JOIN_TIMEOUT,
+                receive_message_limited::<PeerMessage>(
+                    &mut reader,
+                    &Link::default(),
+                    &budget,
+                    |_, _| {},
+                ),
+            )
+            .await
+            {
+                Ok(Ok((
+                    PeerMessage::JoinFolder {
+                        folder_id,
+                        compression,
+                    },
+                    _,
+                ))) => (folder_id, compression, None),
+                Ok(Ok((
+                    PeerMessage::RepairFolder {
+                        folder_id,
+                        workspace_id,
+                    },
+                    _,
+                ))) => (folder_id, Vec::new(), Some(workspace_id)),
+                Ok(Err(e)) if e.is::<ProtocolError>() => {
+                    Log::log_at(
+                        Level::Warn,
+                        format!("Dropping {}: {}", peer_addr, e).red(),
+                        None,
+                    );
+                    return;
+                }
+                _ => {
+                    Log::log_at(
+                        Level::Warn,
+                        format!("Dropping {}: no folder requested", peer_addr).red(),
+                        None,
+                    );
+                    return;
+                }
+            };
+
+            let sender = match router.route(folder_id.as_deref()).await {
+                Ok(sender) => sender,
+                Err(reason) => {
+                    Log::log_at(
+                        Level::Warn,
+                        format!("Rejecting {}: {}", peer_addr, reason).red(),
+                        None,
+                    );
+                    let _ = send_message_to_peer(
+                        &mut writer,
+                        &ServerMessage::ConnectionRejected { reason },
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            let _ = sender
+                .send(IncomingPeer {
+                    reader,
+                    writer,
+                    addr: peer_addr,
+                    compression,
+                    repair,
+                })
+                .await;
+        });
+    }
+}
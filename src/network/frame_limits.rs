@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::models::WorkspaceConfig;
+
+/// Limits on what a peer may make us hold while receiving its messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameLimits {
+    /// Largest message accepted, by the length it announces
+    pub max_frame_bytes: u64,
+    /// Most memory the messages of one connection may hold at a time, received or being received
+    pub memory_budget_bytes: u64,
+}
+
+impl FrameLimits {
+    /// Limits set in the workspace config
+    pub fn for_workspace(config: &WorkspaceConfig) -> Self {
+        Self {
+            max_frame_bytes: config.max_frame_mib as u64 * 1024 * 1024,
+            memory_budget_bytes: config.connection_memory_mib as u64 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self::for_workspace(&WorkspaceConfig::default())
+    }
+}
+
+/// Memory the messages of a connection may take, shared by the messages received and not yet
+/// handled and the one being received.
+/// Includes
+/// - Checking the length a message announces: `check`
+/// - Holding memory for a message until it's handled: `reserve`
+#[derive(Clone)]
+pub struct MemoryBudget {
+    limits: FrameLimits,
+    /// KiB left
+    kib: Arc<Semaphore>,
+}
+
+/// Memory held for a received message, given back to the budget when dropped
+pub struct MemoryReservation {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl MemoryBudget {
+    pub fn new(limits: FrameLimits) -> Self {
+        let kib = limits
+            .memory_budget_bytes
+            .div_ceil(1024)
+            .min(Semaphore::MAX_PERMITS as u64);
+        Self {
+            limits,
+            kib: Arc::new(Semaphore::new(kib as usize)),
+        }
+    }
+
+    pub fn limits(&self) -> FrameLimits {
+        self.limits
+    }
+
+    /// Reject a message announcing more than the limits allow
+    pub fn check(&self, length: u64) -> Result<(), ProtocolError> {
+        if length > self.limits.max_frame_bytes {
+            return Err(ProtocolError(format!(
+                "message of {} bytes exceeds the maximum of {} bytes",
+                length, self.limits.max_frame_bytes
+            )));
+        }
+        if length > self.limits.memory_budget_bytes {
+            return Err(ProtocolError(format!(
+                "message of {} bytes exceeds the connection memory budget of {} bytes",
+                length, self.limits.memory_budget_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Hold memory for a message of `length` bytes, waiting for earlier messages to be handled
+    pub async fn reserve(&self, length: u64) -> Result<MemoryReservation, ProtocolError> {
+        self.check(length)?;
+        let kib = length.div_ceil(1024) as u32;
+        if kib == 0 {
+            return Ok(MemoryReservation { _permit: None });
+        }
+        let permit = Arc::clone(&self.kib)
+            .acquire_many_owned(kib)
+            .await
+            .map_err(|_| ProtocolError("connection memory budget closed".to_string()))?;
+        Ok(MemoryReservation {
+            _permit: Some(permit),
+        })
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(FrameLimits::default())
+    }
+}
+
+/// A peer sent something that breaks the protocol, its connection can't be trusted any further
+#[derive(Debug)]
+pub struct ProtocolError(pub String);
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
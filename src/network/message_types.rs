@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::models::SyncState;
+use crate::{
+    models::{Compression, PeersConfig, SyncConfig, SyncMode, SyncState},
+    sync::{is_subscribed, normalize_subscriptions},
+};
 
 // Messages sent by the **server** to peers
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,10 +15,20 @@ pub enum ServerMessage {
     ConnectionAck {
         peer_id: String,
         leader_id: String,
+        // Algorithm both sides compress what they send with, picked from what the peer offered
+        #[serde(default)]
+        compression: Option<Compression>,
+    },
+    // Step 1: Or refuses it, e.g. when it doesn't serve the requested folder
+    ConnectionRejected {
+        reason: String,
     },
     // Step 3: Server sends the files to update and the files to delete and requests files it needs from the peer
     InitialSyncPushResponse {
         files_to_update: HashMap<String, Vec<u8>>,
+        // SHA-256 of every file in `files_to_update`, checked before anything is written
+        #[serde(default)]
+        hashes: HashMap<String, String>,
         files_to_delete: Vec<String>,
         files_to_send_back: Vec<String>,
     },
@@ -25,21 +39,136 @@ pub enum ServerMessage {
     },
     FileUpdatePush {
         files_to_write: HashMap<String, Vec<u8>>,
+        // SHA-256 of every file in `files_to_write`, checked before anything is written
+        #[serde(default)]
+        hashes: HashMap<String, String>,
         paths_to_delete: Vec<String>,
     },
+    // Ask the peer to send files again, after they arrived corrupted
+    FileRequest {
+        paths: Vec<String>,
+    },
+    // Answer to a `FileRequest` or `RevertRequest` made over a repair connection
+    RequestedFiles {
+        files: HashMap<String, Vec<u8>>,
+        // SHA-256 recorded for every file in `files`, checked before anything is written
+        hashes: HashMap<String, String>,
+    },
+    // Ask the peer to repeat its initial sync, e.g. after the leader resumed syncing
+    ResyncRequest,
+}
+
+impl ServerMessage {
+    /// Narrow a message down to what a peer subscribed to.
+    /// Returns `None` when nothing relevant to the peer is left.
+    pub fn filtered_for(&self, subscriptions: &[String]) -> Option<ServerMessage> {
+        match self {
+            ServerMessage::FileUpdatePush {
+                files_to_write,
+                hashes,
+                paths_to_delete,
+            } if !subscriptions.is_empty() => {
+                let files_to_write: HashMap<String, Vec<u8>> = files_to_write
+                    .iter()
+                    .filter(|(path, _)| is_subscribed(subscriptions, path))
+                    .map(|(path, content)| (path.clone(), content.clone()))
+                    .collect();
+                let hashes: HashMap<String, String> = hashes
+                    .iter()
+                    .filter(|(path, _)| files_to_write.contains_key(*path))
+                    .map(|(path, hash)| (path.clone(), hash.clone()))
+                    .collect();
+                let paths_to_delete: Vec<String> = paths_to_delete
+                    .iter()
+                    .filter(|path| is_subscribed(subscriptions, path))
+                    .cloned()
+                    .collect();
+
+                if files_to_write.is_empty() && paths_to_delete.is_empty() {
+                    None
+                } else {
+                    Some(ServerMessage::FileUpdatePush {
+                        files_to_write,
+                        hashes,
+                        paths_to_delete,
+                    })
+                }
+            }
+            _ => Some(self.clone()),
+        }
+    }
 }
 
 // Messages sent by **peers** to the server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PeerMessage {
+    // Step 0: Peer names the folder it wants to sync, `None` lets a leader serving a single folder pick it
+    JoinFolder {
+        folder_id: Option<String>,
+        // Compression algorithms the peer can use, by preference
+        #[serde(default)]
+        compression: Vec<Compression>,
+    },
+    // Step 0: Or connects only to request files with `FileRequest`, without joining the sync
+    RepairFolder {
+        folder_id: Option<String>,
+        // Workspace asking for the files, a leader has no other copy to repair itself from
+        #[serde(default)]
+        workspace_id: String,
+    },
+
     // Step 2: Peer sends their initial sync state
     InitialSyncPush {
         sync_state: SyncState,
+        // When the peer last completed a sync, used to detect files whose tombstones were compacted
+        #[serde(default)]
+        last_synced: Option<DateTime<Utc>>,
+        // The peer's sync mode, a mirror peer is forced to match the leader exactly
+        #[serde(default)]
+        mode: SyncMode,
+        // Subtrees the peer wants to receive, empty means everything
+        #[serde(default)]
+        subscriptions: Vec<String>,
+        // Stable identifier of the peer's workspace, keys the leader's snapshot of the peer
+        #[serde(default)]
+        workspace_id: String,
     },
 
     // step 4: Peer sends the files back to the server and initial sync is complete
     FileUpdatePush {
         files_to_write: HashMap<String, Vec<u8>>,
+        // SHA-256 of every file in `files_to_write`, checked before anything is written
+        #[serde(default)]
+        hashes: HashMap<String, String>,
         paths_to_delete: Vec<String>,
     },
+
+    // Other
+    // Ask the server to send files again, after they arrived corrupted
+    FileRequest {
+        paths: Vec<String>,
+    },
+    // Ask the server for its copy of every file whose content differs from this state,
+    // to revert local edits
+    RevertRequest {
+        sync_state: SyncState,
+    },
+}
+
+impl PeerMessage {
+    /// Build the initial sync message describing a peer's workspace
+    pub async fn initial_sync_push(
+        sync_config: &SyncConfig,
+        peers_config: &PeersConfig,
+        mode: SyncMode,
+    ) -> PeerMessage {
+        let peers_state = peers_config.state().await;
+        PeerMessage::InitialSyncPush {
+            sync_state: sync_config.state().await,
+            last_synced: peers_state.last_synced,
+            mode,
+            subscriptions: normalize_subscriptions(&peers_state.subscriptions),
+            workspace_id: peers_state.workspace_id,
+        }
+    }
 }
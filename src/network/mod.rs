@@ -1,9 +1,21 @@
+mod bandwidth;
+mod compression;
 mod connection_manager;
+mod discovery;
+mod file_request;
+mod folder_router;
+mod frame_limits;
 mod generate_peer_id;
 mod message_types;
 mod peer_registry;
 
+pub use bandwidth::*;
+pub use compression::*;
 pub use connection_manager::*;
+pub use discovery::*;
+pub use file_request::*;
+pub use folder_router::*;
+pub use frame_limits::*;
 pub use generate_peer_id::*;
 pub use message_types::*;
 pub use peer_registry::*;
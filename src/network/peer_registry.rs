@@ -1,7 +1,9 @@
 use std::sync::Arc;
 
 use crate::{
+    models::{Compression, SyncSnapshots, SyncState},
     network::{PeerConnectionManager, ServerMessage},
+    sync::filter_subscribed,
     utils::Log,
 };
 
@@ -11,6 +13,7 @@ pub async fn acknowledge_peer(
     connection_manager: Arc<PeerConnectionManager>,
     peer_id: String,
     leader_id: String,
+    compression: Option<Compression>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     connection_manager
         .send_to_peer(
@@ -18,6 +21,7 @@ pub async fn acknowledge_peer(
             &ServerMessage::ConnectionAck {
                 peer_id: peer_id.clone(),
                 leader_id,
+                compression,
             },
         )
         .await?;
@@ -25,6 +29,20 @@ pub async fn acknowledge_peer(
     Ok(())
 }
 
+/// Record the state we currently agree on with every connected peer,
+/// narrowed down to the subtrees each of them subscribed to
+pub async fn record_peer_snapshots(
+    connection_manager: Arc<PeerConnectionManager>,
+    sync_snapshots: &SyncSnapshots,
+    state: &SyncState,
+) -> Result<(), String> {
+    for (workspace_id, subscriptions) in connection_manager.get_connected_workspaces().await {
+        sync_snapshots.record(&workspace_id, filter_subscribed(state, &subscriptions))?;
+    }
+
+    Ok(())
+}
+
 pub async fn broadcast_peer_list(
     connection_manager: Arc<PeerConnectionManager>,
     peers: Vec<String>,
@@ -32,8 +50,8 @@ pub async fn broadcast_peer_list(
     let peers_changed_message = ServerMessage::PeerListUpdate { peers };
 
     // Broadcast to all existing peers (except the new one)
-    Log::log(
-        &format!(
+    Log::debug(
+        format!(
             "Notifying {} existing peers about new peer list update.",
             connection_manager.connection_count().await,
         ),
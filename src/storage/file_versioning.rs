@@ -0,0 +1,340 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+/// Separator between a file name and the time its version was stored
+const VERSION_SEPARATOR: char = '~';
+
+/// Format of the timestamp appended to stored versions
+const VERSION_TIME_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+
+/// Versions stored before timestamps had milliseconds
+const LEGACY_VERSION_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Separates the counter added when a version with the same timestamp already exists
+const VERSION_COUNTER_SEPARATOR: char = '_';
+
+/// How replaced and deleted files are kept under `.synclite/versions/`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum VersioningPolicy {
+    /// Replaced and deleted files are gone for good
+    None,
+    /// Keep only the most recent copy of each file, for `cleanout_days` (0 keeps them forever)
+    Trash { cleanout_days: u32 },
+    /// Keep the `keep` most recent copies of each file, for `cleanout_days` (0 keeps them forever)
+    Simple { keep: usize, cleanout_days: u32 },
+    /// Keep one version per 30 seconds for the first hour, one per hour for the first day,
+    /// one per day for the first 30 days and one per week until `max_age_days`
+    Staggered { max_age_days: u32 },
+}
+
+impl Default for VersioningPolicy {
+    fn default() -> Self {
+        VersioningPolicy::Trash { cleanout_days: 30 }
+    }
+}
+
+/// A stored copy of a workspace file
+#[derive(Clone, Debug)]
+pub struct FileVersion {
+    /// Absolute path of the stored copy
+    pub path: PathBuf,
+    /// The tag used to pick this version on the command line
+    pub tag: String,
+    pub created: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// Stores replaced and deleted files so they can be restored later
+pub struct FileVersioning {
+    versions_path: PathBuf,
+    policy: RwLock<VersioningPolicy>,
+}
+
+impl FileVersioning {
+    pub fn new(storage_path: impl AsRef<Path>, policy: VersioningPolicy) -> Self {
+        Self {
+            versions_path: storage_path.as_ref().join("versions"),
+            policy: RwLock::new(policy),
+        }
+    }
+
+    pub fn policy(&self) -> VersioningPolicy {
+        self.policy
+            .read()
+            .map(|policy| policy.clone())
+            .unwrap_or_default()
+    }
+
+    /// Switch to another policy, stored versions are cleaned up by it from the next cleanup on
+    pub fn set_policy(&self, policy: VersioningPolicy) {
+        if let Ok(mut current) = self.policy.write() {
+            *current = policy;
+        }
+    }
+
+    /// Copy a workspace file into the versions folder before it gets overwritten.
+    /// Does nothing if the file doesn't exist or versioning is disabled.
+    pub fn preserve(&self, workspace_path: &Path, relative_path: &str) -> Result<(), String> {
+        let full_path = workspace_path.join(relative_path);
+        if self.policy() == VersioningPolicy::None || !full_path.is_file() {
+            return Ok(());
+        }
+
+        let version_path = self.new_version_path(relative_path)?;
+        fs::copy(&full_path, &version_path)
+            .map_err(|e| format!("Failed to store version of {}: {}", relative_path, e))?;
+
+        Ok(())
+    }
+
+    /// Move a workspace file into the versions folder instead of deleting it.
+    /// Falls back to a plain delete when versioning is disabled.
+    pub fn preserve_and_remove(
+        &self,
+        workspace_path: &Path,
+        relative_path: &str,
+    ) -> Result<(), String> {
+        let full_path = workspace_path.join(relative_path);
+        if !full_path.is_file() {
+            return Ok(());
+        }
+
+        if self.policy() == VersioningPolicy::None {
+            return fs::remove_file(&full_path)
+                .map_err(|e| format!("Failed to delete file {}: {}", relative_path, e));
+        }
+
+        let version_path = self.new_version_path(relative_path)?;
+        if fs::rename(&full_path, &version_path).is_err() {
+            // Renaming across filesystems fails, copy and delete instead
+            fs::copy(&full_path, &version_path)
+                .map_err(|e| format!("Failed to store version of {}: {}", relative_path, e))?;
+            fs::remove_file(&full_path)
+                .map_err(|e| format!("Failed to delete file {}: {}", relative_path, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// List the stored versions of a file, newest first
+    pub fn list_versions(&self, relative_path: &str) -> Result<Vec<FileVersion>, String> {
+        let relative_path = relative_path.replace('\\', "/");
+        let version_base = self.versions_path.join(&relative_path);
+        let (Some(dir), Some(file_name)) = (version_base.parent(), version_base.file_name()) else {
+            return Ok(Vec::new());
+        };
+        let prefix = format!("{}{}", file_name.to_string_lossy(), VERSION_SEPARATOR);
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let mut versions = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(tag) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            let Some((created, counter)) = parse_version_tag(tag) else {
+                continue;
+            };
+
+            let version = FileVersion {
+                path: entry.path(),
+                tag: tag.to_string(),
+                created,
+                size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+            };
+            versions.push((version, counter));
+        }
+
+        versions.sort_by_key(|(v, counter)| std::cmp::Reverse((v.created, *counter)));
+        Ok(versions.into_iter().map(|(v, _)| v).collect())
+    }
+
+    /// Put a stored version back into the workspace. `tag` may be `latest`.
+    /// The current file, if any, is preserved first so the restore can be undone.
+    pub fn restore(
+        &self,
+        workspace_path: &Path,
+        relative_path: &str,
+        tag: &str,
+    ) -> Result<FileVersion, String> {
+        let versions = self.list_versions(relative_path)?;
+        let version = if tag == "latest" {
+            versions.into_iter().next()
+        } else {
+            versions.into_iter().find(|v| v.tag == tag)
+        }
+        .ok_or_else(|| format!("No version '{}' found for {}", tag, relative_path))?;
+
+        self.preserve(workspace_path, relative_path)?;
+
+        let full_path = workspace_path.join(relative_path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        fs::copy(&version.path, &full_path)
+            .map_err(|e| format!("Failed to restore {}: {}", relative_path, e))?;
+
+        Ok(version)
+    }
+
+    /// Apply the policy to the stored versions of a single file.
+    /// Returns the number of versions removed.
+    pub fn cleanup_file(&self, relative_path: &str) -> Result<usize, String> {
+        let versions = self.list_versions(relative_path)?;
+        let expired = expired_versions(&self.policy(), versions, Utc::now());
+
+        for version in &expired {
+            fs::remove_file(&version.path).map_err(|e| {
+                format!("Failed to remove version {}: {}", version.path.display(), e)
+            })?;
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Apply the policy to every stored version.
+    /// Returns the number of versions removed.
+    pub fn cleanup(&self) -> Result<usize, String> {
+        let mut versioned_files = Vec::new();
+        collect_versioned_files(
+            &self.versions_path,
+            &self.versions_path,
+            &mut versioned_files,
+        );
+        versioned_files.sort();
+        versioned_files.dedup();
+
+        let mut removed = 0;
+        for relative_path in versioned_files {
+            removed += self.cleanup_file(&relative_path)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Path for a new version of a file, creating its parent directory.
+    /// A counter keeps versions stored within the same millisecond apart.
+    fn new_version_path(&self, relative_path: &str) -> Result<PathBuf, String> {
+        let stamp = Utc::now().format(VERSION_TIME_FORMAT).to_string();
+        let base = format!("{}{}{}", relative_path, VERSION_SEPARATOR, stamp);
+        let mut version_path = self.versions_path.join(&base);
+        let mut counter = 1;
+        while version_path.exists() {
+            version_path = self
+                .versions_path
+                .join(format!("{}{}{}", base, VERSION_COUNTER_SEPARATOR, counter));
+            counter += 1;
+        }
+
+        if let Some(parent) = version_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create versions directory: {}", e))?;
+        }
+
+        Ok(version_path)
+    }
+}
+
+/// Read when a version was stored, and its counter, from its tag
+fn parse_version_tag(tag: &str) -> Option<(DateTime<Utc>, u32)> {
+    let (stamp, counter) = match tag.split_once(VERSION_COUNTER_SEPARATOR) {
+        Some((stamp, counter)) => (stamp, counter.parse().ok()?),
+        None => (tag, 0),
+    };
+    let created = NaiveDateTime::parse_from_str(stamp, VERSION_TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(stamp, LEGACY_VERSION_TIME_FORMAT))
+        .ok()?;
+    Some((created.and_utc(), counter))
+}
+
+/// Decide which of a file's versions (newest first) the policy no longer keeps
+fn expired_versions(
+    policy: &VersioningPolicy,
+    versions: Vec<FileVersion>,
+    now: DateTime<Utc>,
+) -> Vec<FileVersion> {
+    let too_old = |version: &FileVersion, days: u32| {
+        days > 0 && now - version.created > Duration::days(days as i64)
+    };
+
+    match policy {
+        VersioningPolicy::None => Vec::new(),
+        VersioningPolicy::Trash { cleanout_days } => versions
+            .into_iter()
+            .enumerate()
+            .filter(|(i, v)| *i > 0 || too_old(v, *cleanout_days))
+            .map(|(_, v)| v)
+            .collect(),
+        VersioningPolicy::Simple {
+            keep,
+            cleanout_days,
+        } => versions
+            .into_iter()
+            .enumerate()
+            .filter(|(i, v)| *i >= *keep || too_old(v, *cleanout_days))
+            .map(|(_, v)| v)
+            .collect(),
+        VersioningPolicy::Staggered { max_age_days } => {
+            let mut expired = Vec::new();
+            let mut last_kept: Option<DateTime<Utc>> = None;
+
+            for version in versions {
+                let age = now - version.created;
+                if age > Duration::days(*max_age_days as i64) {
+                    expired.push(version);
+                    continue;
+                }
+
+                let interval = if age < Duration::hours(1) {
+                    Duration::seconds(30)
+                } else if age < Duration::days(1) {
+                    Duration::hours(1)
+                } else if age < Duration::days(30) {
+                    Duration::days(1)
+                } else {
+                    Duration::weeks(1)
+                };
+
+                match last_kept {
+                    Some(kept) if kept - version.created < interval => expired.push(version),
+                    _ => last_kept = Some(version.created),
+                }
+            }
+
+            expired
+        }
+    }
+}
+
+/// Collect the workspace-relative paths of every file that has stored versions
+fn collect_versioned_files(versions_root: &Path, dir: &Path, files: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_versioned_files(versions_root, &path, files);
+            continue;
+        }
+
+        let Ok(relative) = path.strip_prefix(versions_root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        if let Some((original, _)) = relative.rsplit_once(VERSION_SEPARATOR) {
+            files.push(original.to_string());
+        }
+    }
+}
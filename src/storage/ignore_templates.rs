@@ -0,0 +1,32 @@
+/// Starter `.syncignore` files offered by `synclite init --ignore <template>`
+const IGNORE_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "default",
+        "# Editor and OS files\n.DS_Store\nThumbs.db\n*.swp\n*~\n",
+    ),
+    (
+        "rust",
+        "# Build output\ntarget/\n\n# Editor and OS files\n.DS_Store\nThumbs.db\n*.swp\n*~\n",
+    ),
+    (
+        "node",
+        "# Dependencies and build output\nnode_modules/\ndist/\n*.log\n\n# Editor and OS files\n.DS_Store\nThumbs.db\n*.swp\n*~\n",
+    ),
+    (
+        "python",
+        "# Virtual environments and caches\n.venv/\n__pycache__/\n*.pyc\n\n# Editor and OS files\n.DS_Store\nThumbs.db\n*.swp\n*~\n",
+    ),
+];
+
+/// Contents of a named ignore template
+pub fn ignore_template(name: &str) -> Option<&'static str> {
+    IGNORE_TEMPLATES
+        .iter()
+        .find(|(template, _)| *template == name)
+        .map(|(_, contents)| *contents)
+}
+
+/// Names of the available ignore templates
+pub fn ignore_template_names() -> Vec<&'static str> {
+    IGNORE_TEMPLATES.iter().map(|(name, _)| *name).collect()
+}
@@ -1,13 +1,33 @@
 use crate::{
     app,
-    models::{FileEntry, PeersState},
+    models::{FileEntry, PeersState, WORKSPACE_CONFIG_FILE, WorkspaceConfig},
     utils::{Log, write_json},
 };
 
 use colored::Colorize;
-use std::{collections::HashMap, fs, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{IsTerminal, stdin},
+    path::Path,
+};
+
+/// What to do with a workspace that has no .synclite folder yet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitPolicy {
+    /// Ask for confirmation on the terminal
+    #[default]
+    Prompt,
+    /// Initialise without asking (`--yes`)
+    Yes,
+    /// Refuse to initialise (`--no-init`)
+    Never,
+}
 
 pub enum InitialiseStorageError {
+    NotInitialised,
     FailedToCheckConflicts(String),
     PromptDirectoryCreation(String),
     FailedToCreateStorageDirectory(String),
@@ -16,7 +36,10 @@ pub enum InitialiseStorageError {
 }
 
 /// Initialize storage in the specified directory
-pub fn initialise_storage(abs_workspace_path: &Path) -> Result<(), InitialiseStorageError> {
+pub fn initialise_storage(
+    abs_workspace_path: &Path,
+    policy: InitPolicy,
+) -> Result<(), InitialiseStorageError> {
     Log::wrench("Checking Directory Status:".bright_yellow(), None);
 
     let abs_storage_dir = abs_workspace_path.join(".synclite");
@@ -25,26 +48,30 @@ pub fn initialise_storage(abs_workspace_path: &Path) -> Result<(), InitialiseSto
         Log::info(".synclite folder detected", Some(5));
     } else {
         Log::info("No .synclite folder detected", Some(5));
+        if policy == InitPolicy::Never {
+            return Err(InitialiseStorageError::NotInitialised);
+        }
         check_conflicts_and_cleanup(abs_workspace_path)
-            .map_err(|e| InitialiseStorageError::FailedToCheckConflicts(e))?;
-        prompt_directory_creation()
-            .map_err(|e| InitialiseStorageError::PromptDirectoryCreation(e))?;
+            .map_err(InitialiseStorageError::FailedToCheckConflicts)?;
+        if policy == InitPolicy::Prompt {
+            prompt_directory_creation().map_err(InitialiseStorageError::PromptDirectoryCreation)?;
+        }
         create_storage_directory(&abs_storage_dir)
-            .map_err(|e| InitialiseStorageError::FailedToCreateStorageDirectory(e))?;
+            .map_err(InitialiseStorageError::FailedToCreateStorageDirectory)?;
     }
 
-    print!("\n");
+    Log::newline();
     Log::wrench(
         "Checking contents of .synclite folder:".bright_yellow(),
         None,
     );
 
     create_storage_files(&abs_storage_dir)
-        .map_err(|e| InitialiseStorageError::FailedToCreateStorageFiles(e))?;
+        .map_err(InitialiseStorageError::FailedToCreateStorageFiles)?;
 
     // Register the directory in the global registry
     app::add_directory(abs_workspace_path)
-        .map_err(|e| InitialiseStorageError::FailedToAddDirectoryToRegistry(e))?;
+        .map_err(InitialiseStorageError::FailedToAddDirectoryToRegistry)?;
 
     Ok(())
 }
@@ -58,25 +85,23 @@ fn check_conflicts_and_cleanup(abs_workspace_path: &Path) -> Result<(), String>
     let (has_conflict, conflicting_dir) = app::check_path_conflicts(abs_workspace_path)
         .map_err(|e| format!("Failed to check path conflicts: {}", e))?;
 
-    if has_conflict {
-        if let Some(conflicting_path) = conflicting_dir {
-            Log::error(
-                "Cannot initialize synclite here due to a conflict with an existing synclite directory.",
-                None,
-            );
-            Log::info(
-                &format!("Conflicting synclite directory: {}", conflicting_path),
-                Some(5),
-            );
-            Log::info("Please either:", Some(5));
-            Log::info("  1. Use the existing synclite directory, or", Some(6));
-            Log::info("  2. Remove the existing .synclite directory, or", Some(6));
-            Log::info(
-                "  3. Choose a different directory outside of the synclite workspace",
-                Some(6),
-            );
-            return Err("Path conflict detected".into());
-        }
+    if has_conflict && let Some(conflicting_path) = conflicting_dir {
+        Log::error(
+            "Cannot initialize synclite here due to a conflict with an existing synclite directory.",
+            None,
+        );
+        Log::info(
+            format!("Conflicting synclite directory: {}", conflicting_path),
+            Some(5),
+        );
+        Log::info("Please either:", Some(5));
+        Log::info("  1. Use the existing synclite directory, or", Some(6));
+        Log::info("  2. Remove the existing .synclite directory, or", Some(6));
+        Log::info(
+            "  3. Choose a different directory outside of the synclite workspace",
+            Some(6),
+        );
+        return Err("Path conflict detected".into());
     }
 
     Log::success("Directory is valid for initialization", Some(5));
@@ -87,15 +112,22 @@ fn check_conflicts_and_cleanup(abs_workspace_path: &Path) -> Result<(), String>
 /// Get user confirmation to proceed with initialization
 /// Returns Ok(()) if the user confirms, Err(String) if the user does not confirm
 fn prompt_directory_creation() -> Result<(), String> {
+    // Nobody can answer, e.g. under systemd, in a container or a script
+    if !stdin().is_terminal() {
+        return Err(
+            "no terminal to confirm on, pass --yes or run synclite init <path> first".into(),
+        );
+    }
+
     Log::question("Do you want to initialize it? (y/n): ", Some(5));
     let mut answer = String::new();
-    std::io::stdin()
+    stdin()
         .read_line(&mut answer)
         .map_err(|e| format!("Failed to read line: {}", e))?;
     if answer.trim().to_lowercase() == "y" || answer.trim().to_lowercase() == "yes" {
-        return Ok(());
+        Ok(())
     } else {
-        return Err("User did not confirm initialization".into());
+        Err("User did not confirm initialization".into())
     }
 }
 
@@ -105,7 +137,7 @@ fn create_storage_directory(abs_storage_dir: &Path) -> Result<(), String> {
         .map_err(|e| format!("Failed to create .synclite directory: {}", e))?;
 
     Log::info(
-        &format!("Created .synclite directory: {}", abs_storage_dir.display()),
+        format!("Created .synclite directory: {}", abs_storage_dir.display()),
         Some(5),
     );
 
@@ -115,12 +147,12 @@ fn create_storage_directory(abs_storage_dir: &Path) -> Result<(), String> {
     Ok(())
 }
 
-/// Create configuration files (peers.json and state.json) if they do not exist
+/// Create configuration files (peers.json, config.json and state.json) if they do not exist
 fn create_storage_files(abs_storage_dir: &Path) -> Result<(), String> {
     // Create the peers file
     if !abs_storage_dir.join("peers.json").exists() {
         Log::info(
-            &format!(
+            format!(
                 "Creating peers.json file at: {}",
                 abs_storage_dir.join("peers.json").display()
             ),
@@ -129,7 +161,7 @@ fn create_storage_files(abs_storage_dir: &Path) -> Result<(), String> {
         write_json(&abs_storage_dir.join("peers.json"), &PeersState::new())?;
     } else {
         Log::info(
-            &format!(
+            format!(
                 "File peers.json already exists: {}, correcting contents...",
                 abs_storage_dir.join("peers.json").display()
             ),
@@ -137,10 +169,33 @@ fn create_storage_files(abs_storage_dir: &Path) -> Result<(), String> {
         );
     }
 
+    // Create the workspace config with its defaults, so they are easy to find and tune
+    let config_path = abs_storage_dir.join(WORKSPACE_CONFIG_FILE);
+    if !config_path.exists() {
+        Log::info(
+            format!(
+                "Creating {} file at: {}",
+                WORKSPACE_CONFIG_FILE,
+                config_path.display()
+            ),
+            Some(5),
+        );
+        WorkspaceConfig::default().save(abs_storage_dir)?;
+    } else {
+        Log::info(
+            format!(
+                "{} already exists: {}",
+                WORKSPACE_CONFIG_FILE,
+                config_path.display()
+            ),
+            Some(5),
+        );
+    }
+
     // Create the state file
     if !abs_storage_dir.join("state.json").exists() {
         Log::info(
-            &format!(
+            format!(
                 "Creating state.json file at: {}",
                 abs_storage_dir.join("state.json").display()
             ),
@@ -152,7 +207,7 @@ fn create_storage_files(abs_storage_dir: &Path) -> Result<(), String> {
         )?;
     } else {
         Log::info(
-            &format!(
+            format!(
                 "state.json already exists: {}",
                 abs_storage_dir.join("state.json").display()
             ),
@@ -1,2 +1,6 @@
+mod file_versioning;
+mod ignore_templates;
 mod initialise_storage;
-pub use initialise_storage::{InitialiseStorageError, initialise_storage};
+pub use file_versioning::*;
+pub use ignore_templates::*;
+pub use initialise_storage::{InitPolicy, InitialiseStorageError, initialise_storage};
@@ -1,13 +1,18 @@
-use std::{fs, path::Path};
+use std::{fs, path::Path, time::Instant};
 
 use chrono::DateTime;
 use sha2::{Digest, Sha256};
 
-use crate::models::{FileEntry, SyncState};
+use crate::{
+    metrics::record_hash,
+    models::{FileEntry, SyncState},
+    sync::SyncIgnore,
+};
 
 pub fn compute_sync_state(
     workspace_path: &Path,
     current_path: &Path,
+    sync_ignore: &SyncIgnore,
     sync_state: &mut SyncState,
 ) -> Result<(), String> {
     let entries = fs::read_dir(current_path).map_err(|e| {
@@ -23,21 +28,22 @@ pub fn compute_sync_state(
 
         let entry_path = entry.path();
 
-        // Skip .synclite directory
-        if entry_path.file_name().unwrap() == ".synclite" {
+        // Calculate relative path from base directory
+        let relative_path = entry_path
+            .strip_prefix(workspace_path)
+            .map_err(|e| format!("Failed to calculate relative path: {}", e))?
+            .to_string_lossy()
+            .to_string();
+
+        // Skip the .synclite directory and anything matched by .syncignore
+        let is_dir = entry_path.is_dir();
+        if sync_ignore.is_ignored(&relative_path, is_dir) {
             continue;
         }
 
-        if entry_path.is_dir() {
-            compute_sync_state(workspace_path, &entry_path, sync_state)?;
+        if is_dir {
+            compute_sync_state(workspace_path, &entry_path, sync_ignore, sync_state)?;
         } else {
-            // Calculate relative path from base directory
-            let relative_path = entry_path
-                .strip_prefix(workspace_path)
-                .map_err(|e| format!("Failed to calculate relative path: {}", e))?
-                .to_string_lossy()
-                .to_string();
-
             let hash = calculate_file_hash(&entry_path)?;
 
             let last_modified = if let Ok(time) = fs::metadata(&entry_path)
@@ -52,9 +58,7 @@ pub fn compute_sync_state(
             {
                 time
             } else {
-                return Err(format!(
-                    "Not supported on this platform, consider using a different operating system :)"
-                ));
+                return Err("Not supported on this platform, consider using a different operating system :)".to_string());
             };
 
             sync_state.insert(
@@ -63,6 +67,7 @@ pub fn compute_sync_state(
                     hash: Some(hash),
                     is_deleted: false,
                     last_modified: DateTime::from(last_modified),
+                    deleted_at: None,
                 },
             );
         }
@@ -75,9 +80,15 @@ pub fn calculate_file_hash(path: &Path) -> Result<String, String> {
     let contents =
         fs::read(path).map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
 
+    Ok(calculate_hash(&contents))
+}
+
+pub fn calculate_hash(contents: &[u8]) -> String {
+    let started = Instant::now();
     let mut hasher = Sha256::new();
-    hasher.update(&contents);
+    hasher.update(contents);
     let result = hasher.finalize();
+    record_hash(contents.len() as u64, started.elapsed());
 
-    Ok(format!("{:x}", result))
+    format!("{:x}", result)
 }
@@ -0,0 +1,83 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::models::SyncState;
+use crate::sync::calculate_hash;
+
+/// How many times a corrupted file is requested again before giving up on it
+const MAX_REREQUESTS: u32 = 2;
+
+/// Advertise the hash recorded in the state for every file about to be sent, so the receiver
+/// checks the content against what this side synced rather than whatever is on disk now.
+/// Files whose content no longer matches their record are taken out and returned.
+pub fn recorded_hashes(
+    files: &mut HashMap<String, Vec<u8>>,
+    state: &SyncState,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut hashes = HashMap::new();
+    let mut withheld = Vec::new();
+
+    for (path, content) in files.iter() {
+        match state.get(path).and_then(|entry| entry.hash.as_ref()) {
+            Some(hash) if *hash == calculate_hash(content) => {
+                hashes.insert(path.clone(), hash.clone());
+            }
+            _ => withheld.push(path.clone()),
+        }
+    }
+
+    for path in &withheld {
+        files.remove(path);
+    }
+
+    (hashes, withheld)
+}
+
+/// Drop every file whose content doesn't match the hash advertised by the sender,
+/// including files sent without a hash. Returns the rejected paths.
+pub fn reject_corrupted(
+    files: &mut HashMap<String, Vec<u8>>,
+    hashes: &HashMap<String, String>,
+) -> Vec<String> {
+    let rejected: Vec<String> = files
+        .iter()
+        .filter(|(path, content)| hashes.get(*path) != Some(&calculate_hash(content)))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    for path in &rejected {
+        files.remove(path);
+    }
+
+    rejected
+}
+
+/// Keeps track of corrupted files requested again over a connection,
+/// so a sender that keeps sending bad content isn't asked forever
+#[derive(Default)]
+pub struct ReRequests {
+    attempts: HashMap<String, u32>,
+}
+
+impl ReRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter rejected files down to the ones still worth requesting again
+    pub fn to_request(&mut self, rejected: Vec<String>) -> Vec<String> {
+        rejected
+            .into_iter()
+            .filter(|path| {
+                let attempts = self.attempts.entry(path.clone()).or_insert(0);
+                *attempts += 1;
+                *attempts <= MAX_REREQUESTS
+            })
+            .collect()
+    }
+
+    /// Forget the files that finally arrived intact
+    pub fn received<'a>(&mut self, paths: impl IntoIterator<Item = &'a String>) {
+        let received: HashSet<&String> = paths.into_iter().collect();
+        self.attempts.retain(|path, _| !received.contains(path));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::models::SyncState;
+use crate::models::{SyncMode, SyncSnapshot, SyncState};
 
 /// This function returns a tuple of four vectors which mean the following:
 /// 1. The files that **sync_state_2** needs to get from the **sync_state_1**
@@ -71,3 +71,124 @@ pub fn determine_winning_files(
         files_to_delete_from_2,
     )
 }
+
+/// Same four vectors as [`determine_winning_files`], but **sync_state_2** is forced to become
+/// an exact copy of **sync_state_1**: every differing file is sent and every extra file is
+/// deleted, regardless of timestamps. Nothing is ever taken from **sync_state_2**.
+pub fn determine_mirror_files(
+    sync_state_1: &SyncState,
+    sync_state_2: &SyncState,
+) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>) {
+    let mut winning_files_1 = Vec::new();
+    let mut files_to_delete_from_2 = Vec::new();
+
+    for (path, file_1) in sync_state_1 {
+        if file_1.is_deleted {
+            continue;
+        }
+        match sync_state_2.get(path) {
+            Some(file_2) if !file_2.is_deleted && file_2.hash == file_1.hash => {}
+            _ => winning_files_1.push(path.clone()),
+        }
+    }
+
+    for (path, file_2) in sync_state_2 {
+        if file_2.is_deleted {
+            continue;
+        }
+        let is_live_in_1 = sync_state_1
+            .get(path)
+            .is_some_and(|file_1| !file_1.is_deleted);
+        if !is_live_in_1 {
+            files_to_delete_from_2.push(path.clone());
+        }
+    }
+
+    (
+        winning_files_1,
+        Vec::new(),
+        Vec::new(),
+        files_to_delete_from_2,
+    )
+}
+
+/// Files tracked by **sync_state_2** whose content differs from the copy **sync_state_1** has,
+/// including files **sync_state_2** deleted. Files only **sync_state_2** knows are left out.
+pub fn find_local_edits(sync_state_1: &SyncState, sync_state_2: &SyncState) -> Vec<String> {
+    let mut edited: Vec<String> = sync_state_2
+        .iter()
+        .filter(|(path, file_2)| {
+            sync_state_1.get(*path).is_some_and(|file_1| {
+                !file_1.is_deleted && (file_2.is_deleted || file_2.hash != file_1.hash)
+            })
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    edited.sort();
+    edited
+}
+
+/// Drop the parts of a sync plan (as returned by [`determine_winning_files`]) that the
+/// sync modes of the two sides don't allow
+pub fn filter_by_sync_modes(
+    plan: (Vec<String>, Vec<String>, Vec<String>, Vec<String>),
+    mode_1: SyncMode,
+    mode_2: SyncMode,
+) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>) {
+    let (
+        mut winning_files_1,
+        mut winning_files_2,
+        mut files_to_delete_from_1,
+        mut files_to_delete_from_2,
+    ) = plan;
+
+    // Changes flowing from 2 to 1
+    if !mode_2.sends_local_changes() || !mode_1.accepts_remote_changes() {
+        winning_files_2.clear();
+        files_to_delete_from_1.clear();
+    }
+
+    // Changes flowing from 1 to 2
+    if !mode_1.sends_local_changes() || !mode_2.accepts_remote_changes() {
+        winning_files_1.clear();
+        files_to_delete_from_2.clear();
+    }
+
+    (
+        winning_files_1,
+        winning_files_2,
+        files_to_delete_from_1,
+        files_to_delete_from_2,
+    )
+}
+
+/// Find the files both sides changed since they last agreed on **snapshot**.
+/// Last Write Wins still picks a winner, these are reported so the losing edit can be looked at.
+pub fn find_conflicts(
+    sync_state_1: &SyncState,
+    sync_state_2: &SyncState,
+    snapshot: &SyncSnapshot,
+) -> Vec<String> {
+    let mut conflicts: Vec<String> = sync_state_1
+        .iter()
+        .filter(|(path, file_1)| {
+            let Some(file_2) = sync_state_2.get(*path) else {
+                return false;
+            };
+            if file_1.is_deleted || file_2.is_deleted || file_1.hash == file_2.hash {
+                return false;
+            }
+
+            let agreed_hash = snapshot
+                .state
+                .get(*path)
+                .filter(|agreed| !agreed.is_deleted)
+                .and_then(|agreed| agreed.hash.as_ref());
+            agreed_hash != file_1.hash.as_ref() && agreed_hash != file_2.hash.as_ref()
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    conflicts.sort();
+    conflicts
+}
@@ -1,4 +1,14 @@
 mod compute_state;
+mod integrity;
 mod merge_states;
+mod subscriptions;
+mod sync_ignore;
+mod tombstones;
+mod verify;
 pub use compute_state::*;
+pub use integrity::*;
 pub use merge_states::*;
+pub use subscriptions::*;
+pub use sync_ignore::*;
+pub use tombstones::*;
+pub use verify::*;
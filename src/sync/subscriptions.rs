@@ -0,0 +1,39 @@
+use crate::models::SyncState;
+
+/// Normalize subscribed subtrees (`./docs/` -> `docs`), dropping empty entries
+pub fn normalize_subscriptions(subscriptions: &[String]) -> Vec<String> {
+    subscriptions
+        .iter()
+        .map(|path| {
+            let path = path.replace('\\', "/");
+            let path = path.strip_prefix("./").unwrap_or(&path);
+            path.trim_matches('/').to_string()
+        })
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+/// Check whether a path is relevant to a peer that only subscribed to some subtrees.
+/// An empty subscription list means the peer wants everything. Ancestors of a subscribed
+/// subtree also match so that deleting a parent directory still reaches the peer.
+pub fn is_subscribed(subscriptions: &[String], relative_path: &str) -> bool {
+    if subscriptions.is_empty() {
+        return true;
+    }
+
+    let relative_path = relative_path.replace('\\', "/");
+    subscriptions.iter().any(|subscription| {
+        relative_path == *subscription
+            || relative_path.starts_with(&format!("{}/", subscription))
+            || subscription.starts_with(&format!("{}/", relative_path))
+    })
+}
+
+/// Keep only the entries of a state that fall inside the subscribed subtrees
+pub fn filter_subscribed(sync_state: &SyncState, subscriptions: &[String]) -> SyncState {
+    sync_state
+        .iter()
+        .filter(|(path, _)| is_subscribed(subscriptions, path))
+        .map(|(path, entry)| (path.clone(), entry.clone()))
+        .collect()
+}
@@ -0,0 +1,137 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::RwLock,
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Name of the ignore file at the root of the workspace
+pub const SYNC_IGNORE_FILE: &str = ".syncignore";
+
+/// Name of the storage directory that is never synced
+pub const STORAGE_DIR: &str = ".synclite";
+
+/// Gitignore-style exclusion rules loaded from the workspace `.syncignore` file
+///
+/// Supports negation (`!keep.log`), directory patterns (`build/`) and `**` globs.
+/// The `.synclite` storage directory at the workspace root is always ignored.
+/// Patterns from the workspace config come first, so `.syncignore` can re-include their matches.
+pub struct SyncIgnore {
+    workspace_path: PathBuf,
+    patterns: RwLock<Vec<String>>,
+    matcher: RwLock<Gitignore>,
+}
+
+impl SyncIgnore {
+    /// Rules that only exclude the storage directory
+    pub fn empty(workspace_path: impl AsRef<Path>) -> Self {
+        Self {
+            workspace_path: workspace_path.as_ref().to_path_buf(),
+            patterns: RwLock::new(Vec::new()),
+            matcher: RwLock::new(Gitignore::empty()),
+        }
+    }
+
+    /// Replace the patterns applied on top of `.syncignore`, effective from the next reload
+    pub fn set_patterns(&self, patterns: Vec<String>) {
+        if let Ok(mut current) = self.patterns.write() {
+            *current = patterns;
+        }
+    }
+
+    /// Re-read `.syncignore` from disk, keeping the previous rules if it can't be parsed
+    pub fn reload(&self) -> Result<(), String> {
+        let patterns = self
+            .patterns
+            .read()
+            .map_err(|_| "Ignore patterns lock is poisoned".to_string())?
+            .clone();
+        let matcher = build_matcher(&self.workspace_path, &patterns)?;
+        *self
+            .matcher
+            .write()
+            .map_err(|_| "Ignore rules lock is poisoned".to_string())? = matcher;
+        Ok(())
+    }
+
+    /// Check whether a path relative to the workspace root is excluded from syncing.
+    /// Paths that could point outside the workspace are never synced.
+    pub fn is_ignored(&self, relative_path: &str, is_dir: bool) -> bool {
+        let Some(relative_path) = syncable_path(relative_path) else {
+            return true;
+        };
+
+        // Only the storage directory at the root is special, not files that merely contain its name
+        if relative_path == STORAGE_DIR || relative_path.starts_with(&format!("{}/", STORAGE_DIR)) {
+            return true;
+        }
+
+        match self.matcher.read() {
+            Ok(matcher) => matcher
+                .matched_path_or_any_parents(Path::new(&relative_path), is_dir)
+                .is_ignore(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Normalize a workspace-relative path, which may come from another instance.
+/// Returns `None` for empty and rooted paths and for paths with `..` components.
+pub fn syncable_path(relative_path: &str) -> Option<String> {
+    let relative_path = relative_path.replace('\\', "/");
+    let mut parts = Vec::new();
+    for component in Path::new(&relative_path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Check that paths requested by another instance stay inside the workspace
+pub fn check_syncable_paths(paths: &[String]) -> Result<(), String> {
+    match paths.iter().find(|path| syncable_path(path).is_none()) {
+        Some(path) => Err(format!("Refusing a path outside the workspace: {}", path)),
+        None => Ok(()),
+    }
+}
+
+/// Check that patterns are valid gitignore syntax
+pub fn check_ignore_patterns(patterns: &[String]) -> Result<(), String> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| format!("Invalid ignore pattern '{}': {}", pattern, e))?;
+    }
+    Ok(())
+}
+
+fn build_matcher(workspace_path: &Path, patterns: &[String]) -> Result<Gitignore, String> {
+    let ignore_path = workspace_path.join(SYNC_IGNORE_FILE);
+    if patterns.is_empty() && !ignore_path.exists() {
+        return Ok(Gitignore::empty());
+    }
+
+    let mut builder = GitignoreBuilder::new(workspace_path);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| format!("Invalid ignore pattern '{}': {}", pattern, e))?;
+    }
+    if ignore_path.exists()
+        && let Some(e) = builder.add(&ignore_path)
+    {
+        return Err(format!("Failed to parse {}: {}", ignore_path.display(), e));
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build ignore rules: {}", e))
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::models::{FileEntry, SyncSnapshot, SyncState};
+
+/// Remove every tombstone recorded before `cutoff` from the state, along with the ones
+/// `acknowledged` reports every known peer already has.
+/// Returns the paths that were purged.
+pub fn purge_tombstones(
+    sync_state: &mut SyncState,
+    cutoff: DateTime<Utc>,
+    acknowledged: impl Fn(&str, &FileEntry) -> bool,
+) -> Vec<String> {
+    let purged: Vec<String> = sync_state
+        .iter()
+        .filter(|(path, entry)| {
+            entry.is_deleted && (entry.deleted_since() < cutoff || acknowledged(path, entry))
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    for path in &purged {
+        sync_state.remove(path);
+    }
+
+    purged
+}
+
+/// Whether every known peer has the deletion of `path`: each of them has a snapshot, keyed by
+/// workspace ID, and none of those still has the file. Without any known peer nothing is acknowledged.
+pub fn acknowledged_by_snapshots(
+    path: &str,
+    known_workspaces: &[String],
+    snapshots: &HashMap<String, SyncSnapshot>,
+) -> bool {
+    !known_workspaces.is_empty()
+        && known_workspaces.iter().all(|workspace_id| {
+            snapshots.get(workspace_id).is_some_and(|snapshot| {
+                snapshot
+                    .state
+                    .get(path)
+                    .is_none_or(|agreed| agreed.is_deleted)
+            })
+        })
+}
+
+/// Find the files a returning peer would resurrect because their tombstones were already purged.
+///
+/// A file the peer already had at its last completed sync was also known to us at that point,
+/// so if we no longer have any entry for it, it was deleted and its tombstone compacted away.
+/// Those files must be deleted on the peer instead of being pulled back in.
+pub fn find_resurrected_files(
+    local_state: &SyncState,
+    peer_state: &SyncState,
+    peer_last_synced: DateTime<Utc>,
+) -> Vec<String> {
+    peer_state
+        .iter()
+        .filter(|(path, entry)| {
+            !entry.is_deleted
+                && entry.last_modified <= peer_last_synced
+                && !local_state.contains_key(*path)
+        })
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Find the deletions made on either side since the last agreed snapshot that LWW would undo.
+///
+/// A file deleted while SyncLite wasn't running only gets a tombstone at the next start, stamped
+/// with the file's old modification time, so it loses against the other side's copy. If that copy
+/// is still the one both sides agreed on, the deletion is the newer change and must win. A copy
+/// modified since the snapshot still wins. We have no entry at all for a file we agreed on when
+/// its tombstone was compacted away since. The peer also drops files it starts ignoring from its
+/// state, so only its tombstones count as deletions.
+/// Returns (deleted locally, deleted by the peer).
+pub fn find_offline_deletions(
+    local_state: &SyncState,
+    peer_state: &SyncState,
+    snapshot: &SyncSnapshot,
+) -> (Vec<String>, Vec<String>) {
+    let mut deleted_locally = Vec::new();
+    let mut deleted_by_peer = Vec::new();
+
+    for (path, agreed) in &snapshot.state {
+        if agreed.is_deleted {
+            continue;
+        }
+
+        let local = local_state.get(path);
+        let peer = peer_state.get(path);
+        let is_agreed = |entry: Option<&FileEntry>| {
+            entry.is_some_and(|entry| !entry.is_deleted && entry.hash == agreed.hash)
+        };
+
+        if local.is_none_or(|entry| entry.is_deleted) && is_agreed(peer) {
+            deleted_locally.push(path.clone());
+        } else if peer.is_some_and(|entry| entry.is_deleted) && is_agreed(local) {
+            deleted_by_peer.push(path.clone());
+        }
+    }
+
+    (deleted_locally, deleted_by_peer)
+}
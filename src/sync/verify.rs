@@ -0,0 +1,158 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    models::SyncState,
+    sync::{SyncIgnore, compute_sync_state},
+};
+
+/// Differences between the files on disk and the hashes recorded in the sync state
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Content changed although the file wasn't modified since it was recorded
+    pub corrupted: Vec<String>,
+    /// Content changed by a later modification, a local edit not picked up yet
+    pub modified: Vec<String>,
+    /// Tracked files that no longer exist
+    pub missing: Vec<String>,
+    /// Files on disk the state doesn't track
+    pub untracked: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupted.is_empty()
+            && self.modified.is_empty()
+            && self.missing.is_empty()
+            && self.untracked.is_empty()
+    }
+}
+
+/// Rehash the workspace and compare it with the recorded state
+pub fn verify_workspace(
+    workspace_path: &Path,
+    sync_ignore: &SyncIgnore,
+    sync_state: &SyncState,
+) -> Result<VerifyReport, String> {
+    let mut on_disk: SyncState = HashMap::new();
+    compute_sync_state(workspace_path, workspace_path, sync_ignore, &mut on_disk)?;
+
+    let mut report = VerifyReport::default();
+
+    for (path, recorded) in sync_state {
+        if recorded.is_deleted || sync_ignore.is_ignored(path, false) {
+            continue;
+        }
+
+        match on_disk.get(path) {
+            None => report.missing.push(path.clone()),
+            Some(current) if current.hash != recorded.hash => {
+                if current.last_modified > recorded.last_modified {
+                    report.modified.push(path.clone());
+                } else {
+                    report.corrupted.push(path.clone());
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    for path in on_disk.keys() {
+        let is_tracked = sync_state.get(path).is_some_and(|entry| !entry.is_deleted);
+        if !is_tracked {
+            report.untracked.push(path.clone());
+        }
+    }
+
+    report.corrupted.sort();
+    report.modified.sort();
+    report.missing.sort();
+    report.untracked.sort();
+
+    Ok(report)
+}
+
+/// Compare the workspace with the recorded state from file metadata only, without reading any content.
+/// Files modified since they were recorded are reported as modified, finding corrupted ones takes
+/// `verify_workspace`.
+pub fn check_workspace(
+    workspace_path: &Path,
+    sync_ignore: &SyncIgnore,
+    sync_state: &SyncState,
+) -> Result<VerifyReport, String> {
+    let mut on_disk = HashMap::new();
+    modification_times(workspace_path, workspace_path, sync_ignore, &mut on_disk)?;
+
+    let mut report = VerifyReport::default();
+
+    for (path, recorded) in sync_state {
+        if recorded.is_deleted || sync_ignore.is_ignored(path, false) {
+            continue;
+        }
+
+        match on_disk.get(path) {
+            None => report.missing.push(path.clone()),
+            Some(modified) if *modified > recorded.last_modified => {
+                report.modified.push(path.clone())
+            }
+            Some(_) => {}
+        }
+    }
+
+    for path in on_disk.keys() {
+        let is_tracked = sync_state.get(path).is_some_and(|entry| !entry.is_deleted);
+        if !is_tracked {
+            report.untracked.push(path.clone());
+        }
+    }
+
+    report.modified.sort();
+    report.missing.sort();
+    report.untracked.sort();
+
+    Ok(report)
+}
+
+/// Collect when every file of the workspace was last modified, skipping ignored paths
+fn modification_times(
+    workspace_path: &Path,
+    current_path: &Path,
+    sync_ignore: &SyncIgnore,
+    times: &mut HashMap<String, DateTime<Utc>>,
+) -> Result<(), String> {
+    let entries = fs::read_dir(current_path)
+        .map_err(|e| format!("Failed to read directory {}: {}", current_path.display(), e))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        let entry_path = entry.path();
+        let relative_path = entry_path
+            .strip_prefix(workspace_path)
+            .map_err(|e| format!("Failed to calculate relative path: {}", e))?
+            .to_string_lossy()
+            .to_string();
+
+        let is_dir = entry_path.is_dir();
+        if sync_ignore.is_ignored(&relative_path, is_dir) {
+            continue;
+        }
+
+        if is_dir {
+            modification_times(workspace_path, &entry_path, sync_ignore, times)?;
+        } else {
+            let modified = fs::metadata(&entry_path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|e| {
+                    format!(
+                        "Failed to get metadata for file {}: {}",
+                        entry_path.display(),
+                        e
+                    )
+                })?;
+            times.insert(relative_path, DateTime::from(modified));
+        }
+    }
+
+    Ok(())
+}
@@ -1,13 +1,20 @@
-use chrono::Utc;
 use colored::*;
-use std::fmt::Display;
+use log::Level;
+use serde::Serialize;
+use std::{fmt::Display, panic::Location};
 
-/// CLI output utilities with consistent styling
+use super::logger::{LogLine, LogStyle, is_pretty, logger, progress_bars};
+
+/// CLI output utilities with consistent styling, written through the process logger.
+/// Every line is tagged with the module it comes from, which `--log-level` filters on.
 pub struct Log;
 
 // Presets
 impl Log {
     pub fn banner() {
+        if !is_pretty() {
+            return;
+        }
         let ascii_art = r#"
                             .__  .__  __          
   _________.__. ____   ____ |  | |__|/  |_  ____  
@@ -19,70 +26,109 @@ impl Log {
         println!("{}", ascii_art.bright_yellow().bold());
     }
 
-    pub fn usage() {
-        println!();
-        println!("{}", "Usage:".bright_white().bold());
-        println!(
-            "  {} {} {}",
-            "synclite".bright_cyan(),
-            "<command>".bright_yellow(),
-            "<path>".bright_magenta()
-        );
-        println!();
-        println!("{}", "Commands:".bright_white().bold());
-        println!(
-            "  {}  {}",
-            "serve".bright_green().bold(),
-            "Start a sync server in the specified directory".dimmed()
-        );
-        println!(
-            "  {}  {}",
-            "connect".bright_green().bold(),
-            "Connect to a sync server".dimmed()
-        );
-        println!();
-        println!("{}", "Options:".bright_white().bold());
-        println!(
-            "  {}, {}  {}",
-            "-p".bright_yellow(),
-            "--port".bright_yellow(),
-            "Specify port number (default: 8080)".dimmed()
-        );
-        println!();
+    /// An empty line between blocks in the pretty format
+    pub fn newline() {
+        if is_pretty() {
+            progress_bars().suspend(|| println!());
+        }
+    }
+
+    /// Set a block of related lines apart in the pretty format
+    pub fn separator() {
+        if is_pretty() {
+            progress_bars().suspend(|| println!("\n{}\n", "-=".repeat(40).black().bold()));
+        }
     }
 }
 
 // Utilities
 impl Log {
+    #[track_caller]
     pub fn log(message: impl Display, indent: Option<usize>) {
-        let time_str = Utc::now().time().to_string();
-        let time = &time_str[..13];
+        Self::write(Level::Info, LogStyle::Timestamped, message, indent, None);
+    }
+
+    /// A timestamped line at another level than `log`
+    #[track_caller]
+    pub fn log_at(level: Level, message: impl Display, indent: Option<usize>) {
+        Self::write(level, LogStyle::Timestamped, message, indent, None);
+    }
+
+    /// A timestamped line carrying a structured payload, added to JSON lines as `event`
+    #[track_caller]
+    pub fn event(level: Level, message: impl Display, event: &impl Serialize) {
+        let fields = serde_json::to_value(event).ok();
+        Self::write(level, LogStyle::Timestamped, message, None, fields);
+    }
 
-        println!(
-            "{}[{}]: {}",
-            " ".repeat(indent.unwrap_or(0)),
-            time.bright_black().bold(),
-            message
-        );
+    #[track_caller]
+    pub fn debug(message: impl Display, indent: Option<usize>) {
+        Self::write(Level::Debug, LogStyle::Timestamped, message, indent, None);
     }
 
+    #[track_caller]
     pub fn info(message: impl Display, indent: Option<usize>) {
-        println!("{}ℹ️  {}", " ".repeat(indent.unwrap_or(0)), message);
+        Self::write(Level::Info, LogStyle::Info, message, indent, None);
     }
+    #[track_caller]
     pub fn wrench(message: impl Display, indent: Option<usize>) {
-        println!("{}🔧 {}", " ".repeat(indent.unwrap_or(0)), message);
+        Self::write(Level::Info, LogStyle::Wrench, message, indent, None);
     }
 
+    #[track_caller]
     pub fn error(message: impl Display, indent: Option<usize>) {
-        eprintln!("{}❌ {}", " ".repeat(indent.unwrap_or(0)), message);
+        Self::write(Level::Error, LogStyle::Error, message, indent, None);
     }
+    #[track_caller]
     pub fn warning(message: impl Display, indent: Option<usize>) {
-        println!("{}⚠️ {}", " ".repeat(indent.unwrap_or(0)), message);
+        Self::write(Level::Warn, LogStyle::Warning, message, indent, None);
     }
+    #[track_caller]
     pub fn success(message: impl Display, indent: Option<usize>) {
-        println!("{}✅ {}", " ".repeat(indent.unwrap_or(0)), message);
+        Self::write(Level::Info, LogStyle::Success, message, indent, None);
     }
+    #[track_caller]
     pub fn question(message: impl Display, indent: Option<usize>) {
-        println!("{}🤔 {}", " ".repeat(indent.unwrap_or(0)), message);
+        Self::write(Level::Info, LogStyle::Question, message, indent, None);
+    }
+
+    #[track_caller]
+    fn write(
+        level: Level,
+        style: LogStyle,
+        message: impl Display,
+        indent: Option<usize>,
+        fields: Option<serde_json::Value>,
+    ) {
+        let target = caller_target(Location::caller());
+        let logger = logger();
+        if style != LogStyle::Question && !logger.enabled(level, &target) {
+            return;
+        }
+
+        logger.write(LogLine {
+            level,
+            target: &target,
+            style,
+            indent: indent.unwrap_or(0),
+            message: message.to_string(),
+            fields,
+        });
+    }
+}
+
+/// Module path of the file a line was logged from, e.g. `src/engine/peer.rs` is `synclite::engine::peer`
+fn caller_target(location: &Location) -> String {
+    let path = location.file().replace('\\', "/");
+    let path = path
+        .rsplit_once("src/")
+        .map(|(_, path)| path)
+        .unwrap_or(&path)
+        .trim_end_matches(".rs");
+    let path = path.trim_end_matches("/mod");
+
+    match path {
+        "lib" | "main" => "synclite".to_string(),
+        _ => format!("synclite::{}", path.replace('/', "::")),
     }
 }
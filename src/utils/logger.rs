@@ -0,0 +1,406 @@
+use chrono::{SecondsFormat, Utc};
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use log::{Level, LevelFilter, Metadata, Record};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{IsTerminal, Write, stdout},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+/// Name of the log file in a logs directory, rotated copies get a `.1` to `.5` suffix
+pub const LOG_FILE_NAME: &str = "synclite.log";
+
+/// Size at which the log file is rotated
+const LOG_FILE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// How many rotated log files are kept next to the current one
+const LOG_FILES_KEPT: usize = 5;
+
+/// Crate name the module paths of our own log lines start with
+const CRATE_TARGET: &str = "synclite";
+
+/// How log lines are written to the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Colors and emoji, meant for a terminal
+    Pretty,
+    /// One timestamped line per message with its level and module, meant for journald and grep
+    Plain,
+    /// One JSON object per line, meant for log shippers
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Unknown log format '{}', expected pretty, plain or json",
+                s
+            )),
+        }
+    }
+}
+
+/// Which log lines are written: a default level, optionally followed by per-module levels,
+/// e.g. `info,engine::peer=debug,mdns_sd=off`. The most specific module wins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_level = |level: &str| {
+            LevelFilter::from_str(level).map_err(|_| {
+                format!(
+                    "Unknown log level '{}', expected off, error, warn, info, debug or trace",
+                    level
+                )
+            })
+        };
+
+        let mut filter = LogFilter::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module
+                        .trim()
+                        .trim_start_matches(&format!("{}::", CRATE_TARGET));
+                    if module.is_empty() {
+                        return Err(format!("Missing module in log filter '{}'", directive));
+                    }
+                    filter
+                        .modules
+                        .push((module.to_string(), parse_level(level.trim())?));
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl LogFilter {
+    /// Most verbose level enabled for a module path like `synclite::engine::peer`
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let relative = target
+            .strip_prefix(CRATE_TARGET)
+            .and_then(|rest| rest.strip_prefix("::"));
+        let matches = |module: &str, path: &str| {
+            path == module
+                || path
+                    .strip_prefix(module)
+                    .is_some_and(|rest| rest.starts_with("::"))
+        };
+
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                matches(module, target) || relative.is_some_and(|path| matches(module, path))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+/// How a line is decorated in the pretty format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum LogStyle {
+    /// `[time]: message`
+    Timestamped,
+    Info,
+    Wrench,
+    Error,
+    Warning,
+    Success,
+    /// Asks the user something, shown whatever the filter says
+    Question,
+    /// Lines from other crates, `[time]: target: message`
+    Dependency,
+}
+
+/// One message on its way to the console and the log file
+pub(super) struct LogLine<'a> {
+    pub level: Level,
+    pub target: &'a str,
+    pub style: LogStyle,
+    pub indent: usize,
+    /// May carry colors, they are stripped outside the pretty format
+    pub message: String,
+    /// Structured payload added to JSON lines
+    pub fields: Option<serde_json::Value>,
+}
+
+/// A size-rotated log file
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(LOG_FILE_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(Self { path, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 + 1 > LOG_FILE_MAX_BYTES {
+            self.rotate();
+        }
+        if writeln!(self.file, "{}", line).is_ok() {
+            self.size += line.len() as u64 + 1;
+        }
+    }
+
+    /// Shift `synclite.log.N` to `.N+1`, dropping the oldest, and start a new file
+    fn rotate(&mut self) {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let _ = fs::remove_file(rotated(LOG_FILES_KEPT));
+        for n in (1..LOG_FILES_KEPT).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        let _ = fs::rename(&self.path, rotated(1));
+
+        if let Ok(file) = File::create(&self.path) {
+            self.file = file;
+            self.size = 0;
+        }
+    }
+}
+
+/// Writes log lines to the console and, once attached, to a log file
+pub(super) struct Logger {
+    filter: LogFilter,
+    format: LogFormat,
+    file: Mutex<Option<LogFile>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// The logger set up by `init_logging`, or pretty output at info level if there is none
+pub(super) fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger {
+        filter: LogFilter::default(),
+        format: LogFormat::Pretty,
+        file: Mutex::new(None),
+    })
+}
+
+/// Set up logging for the process, also receiving the log lines of dependencies.
+/// Without a format, the pretty one is used in a terminal and the plain one otherwise.
+pub fn init_logging(filter: LogFilter, format: Option<LogFormat>) -> Result<(), String> {
+    let format = format.unwrap_or(if stdout().is_terminal() {
+        LogFormat::Pretty
+    } else {
+        LogFormat::Plain
+    });
+    let max_level = filter.max_level();
+    LOGGER
+        .set(Logger {
+            filter,
+            format,
+            file: Mutex::new(None),
+        })
+        .map_err(|_| "Logging is already set up".to_string())?;
+
+    log::set_logger(logger()).map_err(|e| format!("Failed to set up logging: {}", e))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Also write log lines to `synclite.log` in this directory, rotating it as it grows
+pub fn attach_log_file(dir: &Path) -> Result<(), String> {
+    let file = LogFile::open(dir)?;
+    *logger().file.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+static PROGRESS_BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// Where progress bars are drawn, hidden unless the pretty format is shown in a terminal.
+/// Pretty lines are printed above the bars.
+pub(crate) fn progress_bars() -> &'static MultiProgress {
+    PROGRESS_BARS.get_or_init(|| {
+        let target = if is_pretty() && stdout().is_terminal() {
+            ProgressDrawTarget::stdout()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+        MultiProgress::with_draw_target(target)
+    })
+}
+
+/// Whether console output is decorated, undecorated formats skip banners and separators
+pub(super) fn is_pretty() -> bool {
+    logger().format == LogFormat::Pretty
+}
+
+impl Logger {
+    pub(super) fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.filter.level_for(target)
+    }
+
+    pub(super) fn write(&self, line: LogLine) {
+        if line.style != LogStyle::Question && !self.enabled(line.level, line.target) {
+            return;
+        }
+
+        let plain = strip_ansi(&line.message);
+        let json = || {
+            let mut object = serde_json::json!({
+                "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                "level": line.level.as_str().to_lowercase(),
+                "target": line.target,
+                "message": plain.trim(),
+            });
+            if let Some(fields) = &line.fields {
+                object["event"] = fields.clone();
+            }
+            object.to_string()
+        };
+        let plain_line = || {
+            format!(
+                "{} {:>5} {}: {}",
+                Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                line.level,
+                line.target,
+                plain.trim()
+            )
+        };
+
+        match self.format {
+            LogFormat::Pretty => progress_bars().suspend(|| write_pretty(&line)),
+            LogFormat::Plain => println!("{}", plain_line()),
+            LogFormat::Json => println!("{}", json()),
+        }
+
+        // Prompts only make sense on the console
+        if line.style == LogStyle::Question {
+            return;
+        }
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            match self.format {
+                LogFormat::Json => file.write_line(&json()),
+                LogFormat::Pretty | LogFormat::Plain => file.write_line(&plain_line()),
+            }
+        }
+    }
+}
+
+fn write_pretty(line: &LogLine) {
+    let indent = " ".repeat(line.indent);
+    let message = &line.message;
+    let time = || {
+        let time_str = Utc::now().time().to_string();
+        time_str[..13].bright_black().bold().to_string()
+    };
+
+    match line.style {
+        LogStyle::Timestamped => println!("{}[{}]: {}", indent, time(), message),
+        LogStyle::Info => println!("{}ℹ️  {}", indent, message),
+        LogStyle::Wrench => println!("{}🔧 {}", indent, message),
+        LogStyle::Error => eprintln!("{}❌ {}", indent, message),
+        LogStyle::Warning => println!("{}⚠️ {}", indent, message),
+        LogStyle::Success => println!("{}✅ {}", indent, message),
+        LogStyle::Question => println!("{}🤔 {}", indent, message),
+        LogStyle::Dependency => println!(
+            "{}[{}]: {}: {}",
+            indent,
+            time(),
+            line.target.dimmed(),
+            message
+        ),
+    }
+}
+
+/// Remove the color escape sequences `colored` adds
+fn strip_ansi(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip `ESC [ params final-byte`
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) && c != '[' {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Lines logged by dependencies through the `log` crate
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        Logger::enabled(self, metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        self.write(LogLine {
+            level: record.level(),
+            target: record.target(),
+            style: LogStyle::Dependency,
+            indent: 0,
+            message: record.args().to_string(),
+            fields: None,
+        });
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogFormat::Pretty => "pretty",
+            LogFormat::Plain => "plain",
+            LogFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
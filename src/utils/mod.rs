@@ -1,5 +1,8 @@
 mod json;
 mod log;
+mod logger;
 
 pub use json::*;
 pub use log::Log;
+pub(crate) use logger::progress_bars;
+pub use logger::{LOG_FILE_NAME, LogFilter, LogFormat, attach_log_file, init_logging};
@@ -0,0 +1,243 @@
+//! Feeding malformed and oversized frames to the receiving side of a connection
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use serde_json::Value;
+use synclite::{
+    metrics::{register_workspace, render_metrics},
+    models::Compression,
+    network::{
+        FrameLimits, Link, MemoryBudget, ProtocolError, receive_message_limited, send_message_over,
+    },
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        TcpListener, TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+};
+
+/// Set in the length prefix of a frame split into chunks
+const CHUNKED_FRAME: u32 = 1 << 31;
+
+const KIB: u64 = 1024;
+
+/// A connection over loopback: the half under test and the raw stream feeding it
+async fn connection() -> (OwnedReadHalf, OwnedWriteHalf) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (connected, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    let (reader, _) = accepted.unwrap().0.into_split();
+    let (_, writer) = connected.unwrap().into_split();
+    (reader, writer)
+}
+
+fn budget(max_frame_bytes: u64, memory_budget_bytes: u64) -> MemoryBudget {
+    MemoryBudget::new(FrameLimits {
+        max_frame_bytes,
+        memory_budget_bytes,
+    })
+}
+
+/// Receive one message fed as raw bytes, returning whether it failed with a protocol error
+async fn feed(bytes: &[u8], budget: &MemoryBudget) -> Result<Value, bool> {
+    let (mut reader, mut writer) = connection().await;
+    writer.write_all(bytes).await.unwrap();
+    drop(writer);
+
+    let received = tokio::time::timeout(
+        Duration::from_secs(5),
+        receive_message_limited::<Value>(&mut reader, &Link::default(), budget, |_, _| {}),
+    )
+    .await
+    .expect("a malformed frame must not hang the receiver");
+    match received {
+        Ok((message, _)) => Ok(message),
+        Err(e) => Err(e.is::<ProtocolError>()),
+    }
+}
+
+fn plain_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[tokio::test]
+async fn accepts_a_well_formed_frame() {
+    let received = feed(&plain_frame(br#"{"hello":1}"#), &MemoryBudget::default()).await;
+    assert_eq!(received, Ok(serde_json::json!({ "hello": 1 })));
+}
+
+#[tokio::test]
+async fn rejects_a_length_over_the_maximum_frame_size() {
+    // A port scanner's worth of 0xFF: announces 4 GiB, or 2 GiB as a chunked frame
+    let received = feed(&[0xFF; 64], &MemoryBudget::default()).await;
+    assert_eq!(received, Err(true));
+
+    let received = feed(&u32::MAX.to_be_bytes(), &budget(64 * KIB, 64 * KIB)).await;
+    assert_eq!(received, Err(true));
+}
+
+#[tokio::test]
+async fn rejects_a_length_over_the_memory_budget() {
+    let frame = plain_frame(&[b' '; 8 * 1024]);
+    let received = feed(&frame, &budget(16 * KIB, 4 * KIB)).await;
+    assert_eq!(received, Err(true));
+}
+
+#[tokio::test]
+async fn rejects_a_chunked_frame_over_the_maximum_frame_size() {
+    let prefix = CHUNKED_FRAME | (1024 * 1024);
+    let received = feed(&prefix.to_be_bytes(), &budget(64 * KIB, 64 * KIB)).await;
+    assert_eq!(received, Err(true));
+}
+
+#[tokio::test]
+async fn fails_without_a_protocol_error_on_a_truncated_frame() {
+    // The announced length is fine, the peer just went away
+    let mut frame = 1000u32.to_be_bytes().to_vec();
+    frame.extend_from_slice(b"{\"partial\":");
+    let received = feed(&frame, &MemoryBudget::default()).await;
+    assert_eq!(received, Err(false));
+}
+
+#[tokio::test]
+async fn rejects_a_frame_that_isnt_json() {
+    let received = feed(&plain_frame(b"\xFF\xFEnot json"), &MemoryBudget::default()).await;
+    assert_eq!(received, Err(true));
+}
+
+#[tokio::test]
+async fn rejects_a_chunk_longer_than_its_content() {
+    let mut frame = (CHUNKED_FRAME | 4).to_be_bytes().to_vec();
+    frame.push(0);
+    frame.extend_from_slice(&100u32.to_be_bytes());
+    frame.extend_from_slice(&[b' '; 100]);
+    let received = feed(&frame, &MemoryBudget::default()).await;
+    assert_eq!(received, Err(true));
+}
+
+#[tokio::test]
+async fn rejects_an_unknown_chunk_encoding() {
+    let mut frame = (CHUNKED_FRAME | 4).to_be_bytes().to_vec();
+    frame.push(0x7F);
+    frame.extend_from_slice(&4u32.to_be_bytes());
+    frame.extend_from_slice(b"null");
+    let received = feed(&frame, &MemoryBudget::default()).await;
+    assert_eq!(received, Err(true));
+}
+
+#[tokio::test]
+async fn rejects_a_corrupt_compressed_chunk() {
+    for encoding in [1u8, 2] {
+        let mut frame = (CHUNKED_FRAME | 4096).to_be_bytes().to_vec();
+        frame.push(encoding);
+        frame.extend_from_slice(&16u32.to_be_bytes());
+        frame.extend_from_slice(&[0xAB; 16]);
+        let received = feed(&frame, &MemoryBudget::default()).await;
+        assert_eq!(received, Err(true), "encoding {}", encoding);
+    }
+}
+
+#[tokio::test]
+async fn receives_compressed_messages_within_the_budget() {
+    let message = serde_json::json!({ "text": "repetitive ".repeat(100_000) });
+    for compression in Compression::ALL {
+        let (mut reader, mut writer) = connection().await;
+        let link = Link {
+            compression: Some(compression),
+            ..Link::default()
+        };
+        send_message_over(&mut writer, &message, &link, |_, _| {})
+            .await
+            .unwrap();
+
+        let budget = budget(2 * 1024 * KIB, 2 * 1024 * KIB);
+        let (received, _) =
+            receive_message_limited::<Value>(&mut reader, &Link::default(), &budget, |_, _| {})
+                .await
+                .unwrap();
+        assert_eq!(received, message);
+    }
+}
+
+#[tokio::test]
+async fn waits_for_earlier_messages_to_free_the_budget() {
+    let (mut reader, mut writer) = connection().await;
+    let message = Value::String("x".repeat(3000));
+    for _ in 0..2 {
+        send_message_over(&mut writer, &message, &Link::default(), |_, _| {})
+            .await
+            .unwrap();
+    }
+
+    // Room for one message at a time
+    let budget = budget(4 * KIB, 4 * KIB);
+    let (_, first) =
+        receive_message_limited::<Value>(&mut reader, &Link::default(), &budget, |_, _| {})
+            .await
+            .unwrap();
+
+    // Kept alive across the timeout, dropping it would lose the length prefix it already read
+    let link = Link::default();
+    let second = receive_message_limited::<Value>(&mut reader, &link, &budget, |_, _| {});
+    tokio::pin!(second);
+    let blocked = tokio::time::timeout(Duration::from_millis(200), &mut second).await;
+    assert!(
+        blocked.is_err(),
+        "second message received while the first holds the budget"
+    );
+
+    drop(first);
+    let (second, _) = second.await.unwrap();
+    assert_eq!(second, message);
+}
+
+/// Value of a traffic counter of a workspace, as exposed to Prometheus
+fn traffic(name: &str, workspace: &str) -> u64 {
+    let prefix = format!(
+        "{}{{workspace=\"{}\",remote=\"127.0.0.1\"}} ",
+        name, workspace
+    );
+    render_metrics()
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn counts_the_same_bytes_on_both_sides_of_a_compressed_connection() {
+    let message = serde_json::json!({ "text": "repetitive ".repeat(100_000) });
+    let (mut reader, mut writer) = connection().await;
+    let remote = Some("127.0.0.1".parse().unwrap());
+    let sender = register_workspace(Path::new("/traffic/sender"));
+    let receiver = register_workspace(Path::new("/traffic/receiver"));
+
+    let upload = Link {
+        compression: Some(Compression::Zstd),
+        metrics: Some(Arc::clone(&sender)),
+        remote,
+        ..Link::default()
+    };
+    send_message_over(&mut writer, &message, &upload, |_, _| {})
+        .await
+        .unwrap();
+
+    let download = Link {
+        metrics: Some(Arc::clone(&receiver)),
+        remote,
+        ..Link::default()
+    };
+    let budget = budget(2 * 1024 * KIB, 2 * 1024 * KIB);
+    receive_message_limited::<Value>(&mut reader, &download, &budget, |_, _| {})
+        .await
+        .unwrap();
+
+    let sent = traffic("synclite_bytes_sent_total", "/traffic/sender");
+    let received = traffic("synclite_bytes_received_total", "/traffic/receiver");
+    assert!(sent > 0 && sent < 100_000, "{} bytes sent", sent);
+    assert_eq!(received, sent);
+}
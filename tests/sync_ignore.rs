@@ -0,0 +1,51 @@
+//! Matching paths received from other instances against the ignore rules
+
+use std::fs;
+
+use synclite::sync::{SYNC_IGNORE_FILE, SyncIgnore, syncable_path};
+
+/// Rules loaded from a `.syncignore` in a fresh workspace
+fn rules(name: &str, patterns: &str) -> SyncIgnore {
+    let workspace = std::env::temp_dir().join(format!("synclite-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&workspace).unwrap();
+    fs::write(workspace.join(SYNC_IGNORE_FILE), patterns).unwrap();
+
+    let sync_ignore = SyncIgnore::empty(&workspace);
+    sync_ignore.reload().unwrap();
+    fs::remove_dir_all(&workspace).unwrap();
+    sync_ignore
+}
+
+#[test]
+fn never_syncs_paths_outside_the_workspace() {
+    let sync_ignore = rules("outside", "*.log\n");
+
+    assert!(sync_ignore.is_ignored("/etc/passwd", false));
+    assert!(sync_ignore.is_ignored("\\etc\\passwd", false));
+    assert!(sync_ignore.is_ignored("../state.json", false));
+    assert!(sync_ignore.is_ignored("docs/../../secret.txt", false));
+    assert!(sync_ignore.is_ignored("", false));
+}
+
+#[test]
+fn matches_normalized_paths() {
+    let sync_ignore = rules("normalized", "*.log\nbuild/\n");
+
+    assert!(sync_ignore.is_ignored("debug.log", false));
+    assert!(sync_ignore.is_ignored("./build/out.bin", false));
+    assert!(sync_ignore.is_ignored("src\\trace.log", false));
+    assert!(sync_ignore.is_ignored(".synclite/state.json", false));
+    assert!(!sync_ignore.is_ignored("./src/main.rs", false));
+}
+
+#[test]
+fn normalizes_syncable_paths() {
+    assert_eq!(
+        syncable_path("./docs//a.txt"),
+        Some("docs/a.txt".to_string())
+    );
+    assert_eq!(syncable_path("docs\\a.txt"), Some("docs/a.txt".to_string()));
+    assert_eq!(syncable_path("/docs/a.txt"), None);
+    assert_eq!(syncable_path("docs/../a.txt"), None);
+    assert_eq!(syncable_path("."), None);
+}
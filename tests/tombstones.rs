@@ -0,0 +1,92 @@
+//! Telling deletions apart from files that merely dropped out of a state, and when peers have them
+
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use synclite::{
+    models::{FileEntry, SyncSnapshot, SyncState},
+    sync::{acknowledged_by_snapshots, find_offline_deletions},
+};
+
+fn file(hash: &str) -> FileEntry {
+    FileEntry {
+        hash: Some(hash.to_string()),
+        is_deleted: false,
+        last_modified: Utc::now() - Duration::days(1),
+        deleted_at: None,
+    }
+}
+
+fn state(entries: &[(&str, FileEntry)]) -> SyncState {
+    entries
+        .iter()
+        .map(|(path, entry)| (path.to_string(), entry.clone()))
+        .collect()
+}
+
+fn snapshot(state: SyncState) -> SyncSnapshot {
+    SyncSnapshot {
+        synced_at: Utc::now() - Duration::hours(1),
+        state,
+    }
+}
+
+#[test]
+fn a_peer_tombstone_is_a_deletion() {
+    let agreed = state(&[("notes.txt", file("a"))]);
+    let peer = state(&[("notes.txt", file("a").deleted())]);
+
+    let (deleted_locally, deleted_by_peer) =
+        find_offline_deletions(&agreed, &peer, &snapshot(agreed.clone()));
+    assert!(deleted_locally.is_empty());
+    assert_eq!(deleted_by_peer, vec!["notes.txt".to_string()]);
+}
+
+#[test]
+fn a_file_the_peer_starts_ignoring_is_not_a_deletion() {
+    let agreed = state(&[("notes.txt", file("a")), ("build.log", file("b"))]);
+    // Newly ignored files leave the peer's state without a tombstone
+    let peer = state(&[("notes.txt", file("a"))]);
+
+    let (deleted_locally, deleted_by_peer) =
+        find_offline_deletions(&agreed, &peer, &snapshot(agreed.clone()));
+    assert!(deleted_locally.is_empty());
+    assert!(deleted_by_peer.is_empty());
+}
+
+#[test]
+fn a_compacted_local_tombstone_is_still_a_deletion() {
+    let agreed = state(&[("notes.txt", file("a"))]);
+    let local = SyncState::new();
+
+    let (deleted_locally, deleted_by_peer) =
+        find_offline_deletions(&local, &agreed, &snapshot(agreed.clone()));
+    assert_eq!(deleted_locally, vec!["notes.txt".to_string()]);
+    assert!(deleted_by_peer.is_empty());
+}
+
+#[test]
+fn nothing_is_acknowledged_without_known_peers() {
+    let snapshots = HashMap::new();
+    assert!(!acknowledged_by_snapshots("notes.txt", &[], &snapshots));
+}
+
+#[test]
+fn every_known_peer_must_acknowledge_a_deletion() {
+    let known = vec!["laptop".to_string(), "desktop".to_string()];
+    let mut snapshots = HashMap::from([(
+        "laptop".to_string(),
+        snapshot(state(&[("notes.txt", file("a").deleted())])),
+    )]);
+    // The desktop never recorded a snapshot
+    assert!(!acknowledged_by_snapshots("notes.txt", &known, &snapshots));
+
+    snapshots.insert(
+        "desktop".to_string(),
+        snapshot(state(&[("notes.txt", file("a"))])),
+    );
+    assert!(!acknowledged_by_snapshots("notes.txt", &known, &snapshots));
+
+    snapshots.insert("desktop".to_string(), snapshot(SyncState::new()));
+    assert!(acknowledged_by_snapshots("notes.txt", &known, &snapshots));
+}